@@ -0,0 +1,95 @@
+// Compares CallVec with a Vec of boxed closures.  Run with
+// `cargo bench --bench callvec`.
+extern crate criterion;
+extern crate monotonic;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use monotonic::chain::CallVec;
+
+const COUNT: usize = 1000;
+
+type Boxed = Vec<Box<dyn Fn(&u64)>>;
+
+fn push(c: &mut Criterion) {
+    let mut group = c.benchmark_group("push");
+    group.bench_function("CallVec", |b| b.iter_batched(
+        CallVec::new,
+        |calls| {
+            for i in 0..COUNT as u64 {
+                calls.push_callable(move |x: &u64| { black_box(x + i); });
+            }
+            calls
+        },
+        BatchSize::SmallInput));
+    group.bench_function("Vec<Box<dyn Fn>>", |b| b.iter_batched(
+        Vec::new,
+        |mut calls: Boxed| {
+            for i in 0..COUNT as u64 {
+                calls.push(Box::new(move |x: &u64| { black_box(x + i); }));
+            }
+            calls
+        },
+        BatchSize::SmallInput));
+    group.finish();
+}
+
+// Clearing and refilling a list, which reuses the memory from the
+// last round
+fn refill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("refill");
+    let mut calls = CallVec::new();
+    group.bench_function("CallVec", |b| b.iter(|| {
+        calls.clear();
+        for i in 0..COUNT as u64 {
+            calls.push_callable(move |x: &u64| { black_box(x + i); });
+        }
+    }));
+    let mut boxed: Boxed = Vec::new();
+    group.bench_function("Vec<Box<dyn Fn>>", |b| b.iter(|| {
+        boxed.clear();
+        for i in 0..COUNT as u64 {
+            boxed.push(Box::new(move |x: &u64| { black_box(x + i); }));
+        }
+    }));
+    group.finish();
+}
+
+fn call_all(c: &mut Criterion) {
+    let mut group = c.benchmark_group("call_all");
+    let calls = CallVec::new();
+    let boxed: Boxed = (0..COUNT as u64).map(|i| {
+        calls.push_callable(move |x: &u64| { black_box(x + i); });
+        Box::new(move |x: &u64| { black_box(x + i); }) as Box<dyn Fn(&u64)>
+    }).collect();
+    group.bench_function("CallVec", |b| b.iter(|| calls.call_all(black_box(&1))));
+    group.bench_function("Vec<Box<dyn Fn>>", |b| b.iter(|| {
+        for f in &boxed {
+            f(black_box(&1))
+        }
+    }));
+    group.finish();
+}
+
+fn teardown(c: &mut Criterion) {
+    let mut group = c.benchmark_group("teardown");
+    group.bench_function("CallVec", |b| b.iter_batched(
+        || {
+            let calls = CallVec::new();
+            for i in 0..COUNT as u64 {
+                calls.push_callable(move |x: &u64| { black_box(x + i); });
+            }
+            calls
+        },
+        drop,
+        BatchSize::SmallInput));
+    group.bench_function("Vec<Box<dyn Fn>>", |b| b.iter_batched(
+        || (0..COUNT as u64).map(|i| {
+            Box::new(move |x: &u64| { black_box(x + i); }) as Box<dyn Fn(&u64)>
+        }).collect::<Vec<_>>(),
+        drop,
+        BatchSize::SmallInput));
+    group.finish();
+}
+
+criterion_group!(benches, push, refill, call_all, teardown);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+// Compares Chain's iterators with the FlatMap over chunks they
+// replaced.  Run with `cargo bench --bench iter`.
+//
+// The two measure the same within noise.  Both end up as a loop over
+// each chunk's slice, so on small elements they are bound by memory
+// rather than by the iterator's bookkeeping.
+extern crate criterion;
+extern crate monotonic;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use monotonic::chain::Chain;
+
+const COUNT: usize = 1 << 20;
+
+fn filled<T: Copy>(elem: T) -> Chain<T> {
+    let chain = Chain::new();
+    for _ in 0..COUNT {
+        chain.push(elem);
+    }
+    chain
+}
+
+fn forward(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forward");
+    let bytes = filled(1u8);
+    group.bench_function("u8/Iter", |b| b.iter(|| {
+        black_box(&bytes).iter().fold(0u8, |a, &x| a.wrapping_add(x))
+    }));
+    group.bench_function("u8/FlatMap", |b| b.iter(|| {
+        black_box(&bytes).chunks().flat_map(|c| c.iter()).fold(0u8, |a, &x| a.wrapping_add(x))
+    }));
+    let words = filled(1u64);
+    group.bench_function("u64/Iter", |b| b.iter(|| {
+        black_box(&words).iter().fold(0u64, |a, &x| a.wrapping_add(x))
+    }));
+    group.bench_function("u64/FlatMap", |b| b.iter(|| {
+        black_box(&words).chunks().flat_map(|c| c.iter()).fold(0u64, |a, &x| a.wrapping_add(x))
+    }));
+    group.finish();
+}
+
+// A plain `for` loop goes through `next` rather than `fold`, which
+// is where the adapter's per-element branching shows up
+fn next(c: &mut Criterion) {
+    let mut group = c.benchmark_group("next");
+    let bytes = filled(1u8);
+    group.bench_function("u8/Iter", |b| b.iter(|| {
+        let mut sum = 0u8;
+        for &x in black_box(&bytes).iter() {
+            sum = sum.wrapping_add(x);
+        }
+        sum
+    }));
+    group.bench_function("u8/FlatMap", |b| b.iter(|| {
+        let mut sum = 0u8;
+        for &x in black_box(&bytes).chunks().flat_map(|c| c.iter()) {
+            sum = sum.wrapping_add(x);
+        }
+        sum
+    }));
+    group.finish();
+}
+
+fn backward(c: &mut Criterion) {
+    let mut group = c.benchmark_group("backward");
+    let bytes = filled(1u8);
+    group.bench_function("u8/Iter", |b| b.iter(|| {
+        let mut sum = 0u8;
+        for &x in black_box(&bytes).iter().rev() {
+            sum = sum.wrapping_add(x);
+        }
+        sum
+    }));
+    group.bench_function("u8/FlatMap", |b| b.iter(|| {
+        let mut sum = 0u8;
+        for &x in black_box(&bytes).chunks().rev().flat_map(|c| c.iter().rev()) {
+            sum = sum.wrapping_add(x);
+        }
+        sum
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, forward, next, backward);
+criterion_main!(benches);
@@ -0,0 +1,397 @@
+// An untyped arena behind an object-safe interface, so libraries
+// can take `&dyn AnyArena` and allocate from whatever arena the caller
+// owns without being generic over it.
+use super::chain::Chain;
+use super::zone::{DynZone, Zone};
+use std::any::Any;
+use std::alloc::Layout;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+
+pub trait AnyArena<'gt> {
+    // Allocates memory for `layout`, valid for as long as the arena.
+    // Returns null if the memory can't be allocated.
+    fn alloc_bytes(&self, layout: Layout) -> *mut u8;
+
+    // Arranges for `drop` to be called on `ptr` when the arena drops
+    // its contents.  Arena runs registered destructors in reverse
+    // order and DynZone in its drop order; Zone<u8> holds only bytes
+    // and never runs them, leaking the values in place.
+    //
+    // Unsafe because `ptr` must remain valid for `drop` until then.
+    unsafe fn register_drop(&self, ptr: *mut u8, drop: unsafe fn(*mut u8));
+}
+
+// 'gt is a parameter of the trait rather than a bound on the object,
+// so it can't be shortened by coercion: a value allocated here can't
+// borrow anything that an arena's destructors might outlive.
+impl<'a, 'gt> dyn AnyArena<'gt> + 'a {
+    // Moves `value` into the arena, registering its destructor if
+    // it has one
+    pub fn alloc<T: 'gt>(&self, value: T) -> &mut T {
+        self.alloc_with(|| value)
+    }
+
+    // Like `alloc`, but writes the value returned by `f` straight
+    // into the arena's memory.  `f` may allocate from the arena.
+    pub fn alloc_with<T: 'gt, F: FnOnce() -> T>(&self, f: F) -> &mut T {
+        unsafe { self.emplace_with(|slot| ptr::write(slot, f())) }
+    }
+
+    // Constructs a value in place by calling `init` with a pointer to
+    // memory for it, then registers its destructor.  If `init`
+    // panics, the memory is abandoned to the arena.
+    //
+    // Unsafe because `init` must fully initialize the value.
+    pub unsafe fn emplace_with<T: 'gt, F: FnOnce(*mut T)>(&self, init: F) -> &mut T {
+        unsafe fn drop<T>(ptr: *mut u8) {
+            ptr::drop_in_place(ptr as *mut T)
+        }
+
+        let ptr = self.alloc_bytes(Layout::new::<T>()) as *mut T;
+        if ptr.is_null() {
+            panic!("AnyArena: failed to allocate {} bytes", mem::size_of::<T>());
+        }
+        init(ptr);
+        if mem::needs_drop::<T>() {
+            self.register_drop(ptr as *mut u8, drop::<T>);
+        }
+        &mut *ptr
+    }
+
+    pub fn alloc_str(&self, s: &str) -> &str {
+        let ptr = self.alloc_bytes(Layout::for_value(s));
+        if ptr.is_null() {
+            panic!("AnyArena: failed to allocate {} bytes", s.len());
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(s.as_ptr(), ptr, s.len());
+            &*(std::ptr::slice_from_raw_parts(ptr, s.len()) as *const str)
+        }
+    }
+}
+
+// Carves memory for `layout` from a byte chain, for the AnyArena
+// implementations
+pub(crate) fn alloc_aligned(bytes: &Chain<u8>, layout: Layout) -> *mut u8 {
+    let align = layout.align();
+    if layout.size() == 0 {
+        return align as *mut u8
+    }
+    let want = match layout.size().checked_add(align - 1) {
+        Some(want) => want,
+        None => return ptr::null_mut()
+    };
+    unsafe {
+        // Reserving align - 1 extra bytes guarantees an aligned
+        // start fits within the same chunk
+        let origin = match bytes.raw_try_reserve(want) {
+            Ok((origin, _)) => origin,
+            Err(_) => return ptr::null_mut()
+        };
+        let pad = (align - (origin as usize & (align - 1))) & (align - 1);
+        bytes.raw_add_len(pad + layout.size());
+        origin.offset(pad as isize)
+    }
+}
+
+// The basic AnyArena: bytes are carved from a chain of byte chunks
+// and destructors are kept in a list run when the arena is dropped.
+// Values allocated from it may borrow anything outliving 'gt.
+pub struct Arena<'gt> {
+    bytes: Chain<u8>,
+    drops: Chain<(*mut u8, unsafe fn(*mut u8))>,
+    _ph: PhantomData<*mut &'gt ()>
+}
+
+impl<'gt> Arena<'gt> {
+    pub const fn new() -> Self {
+        Arena {
+            bytes: Chain::new(),
+            drops: Chain::new(),
+            _ph: PhantomData
+        }
+    }
+
+    pub fn with_capacity(bytes: usize) -> Self {
+        Arena {
+            bytes: Chain::with_capacity(bytes),
+            drops: Chain::new(),
+            _ph: PhantomData
+        }
+    }
+
+    pub fn as_any(&self) -> &(dyn AnyArena<'gt> + '_) {
+        self
+    }
+}
+
+impl<'gt> Default for Arena<'gt> {
+    fn default() -> Self {
+        Arena::new()
+    }
+}
+
+impl<'gt> AnyArena<'gt> for Arena<'gt> {
+    fn alloc_bytes(&self, layout: Layout) -> *mut u8 {
+        alloc_aligned(&self.bytes, layout)
+    }
+
+    unsafe fn register_drop(&self, ptr: *mut u8, drop: unsafe fn(*mut u8)) {
+        self.drops.push((ptr, drop));
+    }
+}
+
+impl<'gt> Arena<'gt> {
+    // Runs the registered destructors and discards everything
+    // allocated, keeping the memory for reuse
+    pub fn reset(&mut self) {
+        self.run_drops();
+        self.bytes.clear();
+    }
+
+    fn run_drops(&mut self) {
+        // Keeps running the remaining destructors if one panics
+        struct Run<'a>(::chain::Drain<'a, (*mut u8, unsafe fn(*mut u8))>);
+
+        impl<'a> Run<'a> {
+            fn run(&mut self) {
+                for (ptr, drop) in self.0.by_ref().rev() {
+                    unsafe { drop(ptr) }
+                }
+            }
+        }
+
+        impl<'a> Drop for Run<'a> {
+            fn drop(&mut self) {
+                self.run()
+            }
+        }
+
+        Run(self.drops.drain()).run()
+    }
+}
+
+impl<'gt> Drop for Arena<'gt> {
+    fn drop(&mut self) {
+        self.run_drops()
+    }
+}
+
+// An arena or zone that can discard its contents in place
+pub trait Reset {
+    fn reset(&mut self);
+
+    // Bytes currently allocated for the arena's storage
+    fn reserved_bytes(&self) -> usize;
+}
+
+impl<T> Reset for Chain<T> {
+    fn reset(&mut self) {
+        self.clear()
+    }
+
+    fn reserved_bytes(&self) -> usize {
+        self.stats().reserved_bytes()
+    }
+}
+
+impl<T> Reset for Zone<T> {
+    fn reset(&mut self) {
+        Zone::reset(self)
+    }
+
+    fn reserved_bytes(&self) -> usize {
+        self.stats().reserved_bytes()
+    }
+}
+
+impl<'gt> Reset for DynZone<'gt> {
+    fn reset(&mut self) {
+        DynZone::reset(self)
+    }
+
+    fn reserved_bytes(&self) -> usize {
+        DynZone::reserved_bytes(self)
+    }
+}
+
+impl<'gt> Reset for Arena<'gt> {
+    fn reset(&mut self) {
+        Arena::reset(self)
+    }
+
+    fn reserved_bytes(&self) -> usize {
+        self.bytes.stats().reserved_bytes() + self.drops.stats().reserved_bytes()
+    }
+}
+
+// Lets members be handed back as their own types
+trait Member: Reset {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<A: Reset + Any> Member for A {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Handle to an arena added to an ArenaGroup
+pub struct MemberId<A> {
+    index: usize,
+    _ph: PhantomData<fn() -> A>
+}
+
+impl<A> Clone for MemberId<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A> Copy for MemberId<A> {}
+
+// Owns a set of arenas with related lifetimes, such as the node,
+// string and span arenas for one compilation unit, so they can be
+// reset or dropped together
+pub struct ArenaGroup {
+    members: Vec<Box<dyn Member>>
+}
+
+impl ArenaGroup {
+    pub fn new() -> Self {
+        ArenaGroup {
+            members: Vec::new()
+        }
+    }
+
+    pub fn add<A: Reset + 'static>(&mut self, arena: A) -> MemberId<A> {
+        self.members.push(Box::new(arena));
+        MemberId {
+            index: self.members.len() - 1,
+            _ph: PhantomData
+        }
+    }
+
+    // Ids are typed, so the downcasts can only fail if an id from
+    // another group is used
+    pub fn get<A: Reset + 'static>(&self, id: MemberId<A>) -> &A {
+        self.members[id.index].as_any().downcast_ref().expect("ArenaGroup: id from another group")
+    }
+
+    pub fn get_mut<A: Reset + 'static>(&mut self, id: MemberId<A>) -> &mut A {
+        self.members[id.index].as_any_mut().downcast_mut().expect("ArenaGroup: id from another group")
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    // Resets every member, in the order they were added
+    pub fn reset(&mut self) {
+        for member in &mut self.members {
+            member.reset();
+        }
+    }
+
+    // Total bytes allocated across all members
+    pub fn reserved_bytes(&self) -> usize {
+        self.members.iter().map(|m| m.reserved_bytes()).sum()
+    }
+}
+
+impl Default for ArenaGroup {
+    fn default() -> Self {
+        ArenaGroup::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    // A library function that only knows about the interface
+    fn build<'a, 'gt>(arena: &'a (dyn AnyArena<'gt> + 'a), log: &'gt RefCell<Vec<u32>>) -> &'a [u64] {
+        struct Logged<'a>(u32, &'a RefCell<Vec<u32>>);
+        impl<'a> Drop for Logged<'a> {
+            fn drop(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
+        }
+
+        arena.alloc(Logged(1, log));
+        arena.alloc(Logged(2, log));
+        arena.alloc([7u64; 3])
+    }
+
+    #[test]
+    fn any_arena() {
+        let log = RefCell::new(Vec::new());
+        {
+            let arena = Arena::with_capacity(3);
+            let name = arena.as_any().alloc_str("plugin");
+            let words = build(arena.as_any(), &log);
+            assert_eq!(words, [7, 7, 7]);
+            assert_eq!(words.as_ptr() as usize % mem::align_of::<u64>(), 0);
+            assert_eq!(name, "plugin");
+            let big = arena.alloc_bytes(Layout::from_size_align(64, 64).unwrap());
+            assert_eq!(big as usize % 64, 0);
+            let any = arena.as_any();
+            let table = any.alloc_with(|| [any.alloc_str("inner").len(); 512]);
+            let cell = unsafe { any.emplace_with(|slot| ptr::write(slot, String::from("cell"))) };
+            assert_eq!((table[511], &cell[..]), (5, "cell"));
+            assert!(log.borrow().is_empty());
+        }
+        assert_eq!(*log.borrow(), [2, 1]);
+    }
+
+    #[test]
+    fn zones() {
+        let log = RefCell::new(Vec::new());
+        {
+            let zone = DynZone::new();
+            zone.alloc(|| 5u8);
+            let words = build(&zone, &log);
+            assert_eq!(words, [7, 7, 7]);
+            assert!(zone.reserved_bytes() > zone.stats().reserved_bytes());
+        }
+        assert_eq!(log.replace(Vec::new()), [1, 2]);
+        {
+            let bytes = Zone::new();
+            bytes.push(1u8);
+            let words = build(&bytes, &log);
+            assert_eq!(words.as_ptr() as usize % mem::align_of::<u64>(), 0);
+            assert_eq!((&bytes as &dyn AnyArena).alloc_str("text"), "text");
+        }
+        // Zone<u8> doesn't run destructors
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn group() {
+        let mut group = ArenaGroup::new();
+        let nodes = group.add(Zone::<[u32; 4]>::new());
+        let strings = group.add(Zone::<u8>::new());
+        let scratch = group.add(Arena::new());
+        for _ in 0..2 {
+            group.get(nodes).push([1, 2, 3, 4]);
+            assert_eq!(group.get(strings).format(format_args!("n{}", 1)), "n1");
+            group.get(scratch).as_any().alloc(vec![1, 2, 3]);
+            let used = group.reserved_bytes();
+            assert!(used > 0);
+            group.reset();
+            assert_eq!(group.reserved_bytes(), used);
+            assert_eq!(group.get(nodes).stats().chunks()[0].0, 0);
+        }
+        assert_eq!(group.len(), 3);
+        group.get_mut(nodes).reset();
+    }
+}
@@ -0,0 +1,208 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::{self, NonNull};
+use super::chain::{Chain, Drain};
+
+// A callback's call thunk and where its closure lives
+struct Entry<A> {
+    call: unsafe fn(*const u8, &A),
+    obj: *const u8
+}
+
+// A list of callbacks sharing an argument type.  Closures are packed
+// into chunks rather than boxed one by one, and the entries used to
+// call them sit in a chain of their own, so calling them all is a
+// linear scan.  Only closures that need dropping are visited on
+// teardown.
+pub struct CallVec<'gt, A> {
+    // Closure storage, each aligned for its type
+    data: Chain<u8>,
+    // One entry per callback, in push order
+    entries: Chain<Entry<A>>,
+    // Closures with drop glue, and how to drop them
+    drops: Chain<(unsafe fn(*mut u8), *mut u8)>,
+    // Indicate we hold closures borrowing for 'gt, which must
+    // strictly outlive us
+    _ph: PhantomData<*mut &'gt ()>
+}
+
+impl<'gt, A> CallVec<'gt, A> {
+    pub const fn new() -> Self {
+        CallVec {
+            data: Chain::new(),
+            entries: Chain::new(),
+            drops: Chain::new(),
+            _ph: PhantomData
+        }
+    }
+
+    // Creates a list with room for `len` callbacks and `bytes` bytes
+    // of captured state
+    pub fn with_capacity(len: usize, bytes: usize) -> Self {
+        CallVec {
+            data: Chain::with_capacity(bytes),
+            entries: Chain::with_capacity(len),
+            drops: Chain::new(),
+            _ph: PhantomData
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+
+    pub fn push_callable<F: Fn(&A) + 'gt>(&self, f: F) {
+        unsafe fn call<A, F: Fn(&A)>(obj: *const u8, args: &A) {
+            (*(obj as *const F))(args)
+        }
+
+        unsafe fn drop<F>(obj: *mut u8) {
+            ptr::drop_in_place(obj as *mut F)
+        }
+
+        let size = mem::size_of::<F>();
+        let align = mem::align_of::<F>();
+        unsafe {
+            let obj = if size == 0 {
+                NonNull::<F>::dangling().as_ptr() as *mut u8
+            } else {
+                // As in AlignedZone, the extra bytes guarantee an
+                // aligned start within the same chunk
+                let (origin, _) = self.data.raw_reserve(size + align - 1);
+                let pad = (align - (origin as usize & (align - 1))) & (align - 1);
+                self.data.raw_add_len(pad + size);
+                origin.offset(pad as isize)
+            };
+            ptr::write(obj as *mut F, f);
+            self.entries.push(Entry {
+                call: call::<A, F>,
+                obj: obj
+            });
+            if mem::needs_drop::<F>() {
+                self.drops.push((drop::<F>, obj));
+            }
+        }
+    }
+
+    // Calls each callback with `args`, in the order they were pushed
+    pub fn call_all(&self, args: &A) {
+        for entry in &self.entries {
+            unsafe { (entry.call)(entry.obj, args) }
+        }
+    }
+
+    // Drops every callback, keeping the memory for reuse
+    pub fn clear(&mut self) {
+        // Entries go first, so none is left pointing at a dropped
+        // closure if a drop panics
+        self.entries.clear();
+        self.drop_closures();
+        self.data.clear();
+    }
+
+    fn drop_closures(&mut self) {
+        // Drops whatever closures are left, so if one's drop panics
+        // the rest are still dropped as this unwinds, as with Vec;
+        // a second panic aborts
+        struct Guard<'a>(Drain<'a, (unsafe fn(*mut u8), *mut u8)>);
+
+        impl<'a> Guard<'a> {
+            fn run(&mut self) {
+                for (drop, obj) in self.0.by_ref() {
+                    unsafe { drop(obj) }
+                }
+            }
+        }
+
+        impl<'a> Drop for Guard<'a> {
+            fn drop(&mut self) {
+                self.run()
+            }
+        }
+
+        Guard(self.drops.drain()).run()
+    }
+}
+
+impl<'gt, A> Drop for CallVec<'gt, A> {
+    fn drop(&mut self) {
+        self.drop_closures()
+    }
+}
+
+impl<'gt, A> Default for CallVec<'gt, A> {
+    fn default() -> Self {
+        CallVec::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::panic;
+    use std::rc::Rc;
+
+    #[test]
+    fn call_all() {
+        let total = &Cell::new(0);
+        let mut calls = CallVec::new();
+        calls.push_callable(|x: &u32| total.set(total.get() + x));
+        let scale = Box::new(10u32);
+        calls.push_callable(move |x: &u32| total.set(total.get() + x * *scale));
+        let wide = [3u128];
+        calls.push_callable(move |x: &u32| total.set(total.get() + x * wide[0] as u32));
+        assert_eq!(calls.len(), 3);
+        calls.call_all(&2);
+        assert_eq!(total.get(), 28);
+        calls.clear();
+        assert!(calls.is_empty());
+        calls.call_all(&2);
+        assert_eq!(total.get(), 28);
+    }
+
+    #[test]
+    fn drops() {
+        let rc = Rc::new(());
+        let calls = CallVec::new();
+        for _ in 0..3 {
+            let rc = rc.clone();
+            calls.push_callable(move |_: &()| { let _ = &rc; });
+            calls.push_callable(|_: &()| {});
+        }
+        assert_eq!(Rc::strong_count(&rc), 4);
+        drop(calls);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn drop_panic() {
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("bomb")
+            }
+        }
+
+        let rc = Rc::new(());
+        let mut calls = CallVec::new();
+        let bomb = Bomb;
+        calls.push_callable(move |_: &()| { let _ = &bomb; });
+        for _ in 0..3 {
+            let rc = rc.clone();
+            calls.push_callable(move |_: &()| { let _ = &rc; });
+        }
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| calls.clear()));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert!(calls.is_empty());
+        calls.call_all(&());
+    }
+}
@@ -1,16 +1,26 @@
 use std::fmt;
-use std::mem;
+use std::error;
+use std::mem::{self, MaybeUninit};
 use std::ptr;
 use std::slice;
+use std::str;
 use std::iter;
-use std::io::{self, Write};
+use std::ops;
+use std::io;
 use std::cmp;
-use std::rt::heap;
+use std::hash::{Hash, Hasher};
+use std::alloc::{self, Layout};
 use std::cell::Cell;
 use std::marker::PhantomData;
-use std::intrinsics;
-
-// A chunk in the chain
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::growth::{GrowthPolicy, Doubling, SizingProfile};
+use super::usage::MemUsage;
+
+// A chunk in the chain.  The header's layout must be fixed so the
+// zero-sized `items` comes after it rather than being moved to the
+// front for strictly aligned T.
+#[repr(C)]
 struct Chunk<T> {
     // Previous chunk
     prev: *mut Chunk<T>,
@@ -20,485 +30,3603 @@ struct Chunk<T> {
     len: usize,
     // Capacity
     cap: usize,
+    // Count of items in all previous chunks
+    offset: usize,
+    // Capacity that growth continues from.  Equal to `cap`, except
+    // for chunks dedicated to a single oversized reservation, which
+    // only advance growth by one step.
+    grow: usize,
     // Items follow in memory
     items: [T; 0]
 }
 
+// The header is six words, and elements start right after it
+const _: () = assert!(mem::size_of::<Chunk<u8>>() == 6 * mem::size_of::<usize>());
+const _: () = assert!(mem::align_of::<Chunk<u8>>() == mem::align_of::<usize>());
+const _: () = assert!(mem::size_of::<Chunk<u128>>().is_multiple_of(mem::align_of::<u128>()));
+const _: () = assert!(mem::offset_of!(Chunk<u8>, items) == mem::size_of::<Chunk<u8>>());
+const _: () = assert!(mem::offset_of!(Chunk<u128>, items) == mem::size_of::<Chunk<u128>>());
+
+// Returned by fallible allocation methods when the requested size
+// overflows or the allocator is out of memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl error::Error for AllocError {
+    fn description(&self) -> &str {
+        "memory allocation failed"
+    }
+}
+
 pub struct Chain<T> {
     head: Cell<*mut Chunk<T>>,
     tail: Cell<*mut Chunk<T>>,
+    // Sizes new chunks; doubling if not set
+    policy: Option<Box<dyn GrowthPolicy + Send + Sync>>,
+    // Identifies the positions handed out since the chain was last
+    // restructured, or 0 if there are none
+    stamp: Cell<usize>,
+    // Chunks from the head up to some chunk no later than the tail,
+    // for binary searching by offset.  Extended lazily as the chain
+    // grows and cleared when it is restructured.
+    index: Cell<Vec<*mut Chunk<T>>>,
+    usage: Usage,
+    teardown: Teardown,
+    // Notified as chunk allocation crosses byte thresholds
+    budget: Option<Box<Budget>>,
+    // Shown each chunk as the chain moves past it
+    seal: Option<Box<dyn Fn(&[T]) + Send + Sync>>,
     _ph: PhantomData<T>
 }
 
+struct Budget {
+    every: usize,
+    // Bytes allocated since the hook was installed
+    total: Cell<usize>,
+    // Whether a threshold has been crossed since the hook last ran
+    due: Cell<bool>,
+    hook: Box<dyn Fn(usize) + Send + Sync>
+}
+
+// Source of stamps unique across all chains
+static NEXT_STAMP: AtomicUsize = AtomicUsize::new(1);
+
 unsafe impl<T: Send> Send for Chain<T> {}
 
+// Running total and high-water mark of the bytes a chain has
+// allocated.  Without the `peak-stats` feature this is empty and
+// recording compiles away.
+#[cfg(feature = "peak-stats")]
+struct Usage {
+    current: Cell<usize>,
+    peak: Cell<usize>
+}
+
+#[cfg(not(feature = "peak-stats"))]
+struct Usage;
+
+#[cfg(feature = "peak-stats")]
+impl Usage {
+    const fn new() -> Self {
+        Usage {
+            current: Cell::new(0),
+            peak: Cell::new(0)
+        }
+    }
+
+    fn grow(&self, bytes: usize) {
+        let current = self.current.get() + bytes;
+        self.current.set(current);
+        self.peak.set(cmp::max(self.peak.get(), current));
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.current.set(self.current.get() - bytes);
+    }
+}
+
+#[cfg(not(feature = "peak-stats"))]
+impl Usage {
+    const fn new() -> Self {
+        Usage
+    }
+
+    #[inline]
+    fn grow(&self, _: usize) {}
+
+    #[inline]
+    fn shrink(&self, _: usize) {}
+}
+
+// Time taken to drop a chain, split between running the elements'
+// destructors and freeing its chunks
+#[derive(Clone, Copy, Debug)]
+pub struct DropTiming {
+    drops: Duration,
+    frees: Duration
+}
+
+impl DropTiming {
+    pub fn drops(&self) -> Duration {
+        self.drops
+    }
+
+    pub fn frees(&self) -> Duration {
+        self.frees
+    }
+
+    pub fn total(&self) -> Duration {
+        self.drops + self.frees
+    }
+}
+
+// Reports how long dropping the chain took.  Without the
+// `drop-timing` feature this is empty and the clock is never read.
+#[cfg(feature = "drop-timing")]
+struct Teardown {
+    hook: Option<Box<dyn Fn(DropTiming) + Send + Sync>>
+}
+
+#[cfg(not(feature = "drop-timing"))]
+struct Teardown;
+
+#[cfg(feature = "drop-timing")]
+impl Teardown {
+    const fn new() -> Self {
+        Teardown {
+            hook: None
+        }
+    }
+
+    fn start(&self) -> Option<Instant> {
+        self.hook.as_ref().map(|_| Instant::now())
+    }
+
+    fn report(&self, start: Option<Instant>, dropped: Option<Instant>) {
+        if let (Some(hook), Some(start), Some(dropped)) = (self.hook.as_ref(), start, dropped) {
+            hook(DropTiming {
+                drops: dropped - start,
+                frees: dropped.elapsed()
+            })
+        }
+    }
+}
+
+#[cfg(not(feature = "drop-timing"))]
+impl Teardown {
+    const fn new() -> Self {
+        Teardown
+    }
+
+    #[inline]
+    fn start(&self) -> Option<Instant> {
+        None
+    }
+
+    #[inline]
+    fn report(&self, _: Option<Instant>, _: Option<Instant>) {}
+}
+
 impl<T> Chunk<T> {
-    fn array_size(len: usize) -> usize {
-        len.checked_mul(mem::size_of::<T>()).unwrap()
+    fn array_size(len: usize) -> Option<usize> {
+        len.checked_mul(mem::size_of::<T>())
+    }
+
+    fn mem_size(len: usize) -> Option<usize> {
+        Self::array_size(len)
+            .and_then(|size| size.checked_add(mem::size_of::<Self>()))
+            .and_then(|size| if size > isize::MAX as usize { None } else { Some(size) })
+    }
+
+    fn layout(len: usize) -> Option<Layout> {
+        Self::mem_size(len).and_then(|size| Layout::from_size_align(size, mem::align_of::<Self>()).ok())
     }
 
-    fn mem_size(len: usize) -> usize {
-        mem::size_of::<Self>().checked_add(Self::array_size(len)).unwrap()
+    // Zero-sized elements need no storage, so a single chunk
+    // holding only the header can count all of them
+    fn initial_cap(cap: usize) -> usize {
+        if mem::size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            cap
+        }
     }
 
     fn new(cap: usize) -> *mut Self {
+        match Self::try_new(cap) {
+            Ok(chunk) => chunk,
+            Err(_) => panic!("Chain: failed to allocate chunk!")
+        }
+    }
+
+    fn try_new(cap: usize) -> Result<*mut Self, AllocError> {
+        let layout = Self::layout(cap).ok_or(AllocError)?;
         unsafe {
-            let res = heap::allocate(Self::mem_size(cap),
-                                     mem::align_of::<Self>()) as *mut Self;
+            let res = alloc::alloc(layout) as *mut Self;
             if res.is_null() {
-                panic!("Chain: failed to allocate chunk!")
+                return Err(AllocError)
             }
             ptr::write(&mut (*res).prev, ptr::null_mut());
             ptr::write(&mut (*res).next, ptr::null_mut());
             ptr::write(&mut (*res).len, 0);
             ptr::write(&mut (*res).cap, cap);
-            res
+            ptr::write(&mut (*res).offset, 0);
+            ptr::write(&mut (*res).grow, cap);
+            Ok(res)
+        }
+    }
+
+    unsafe fn free(chunk: *mut Self) {
+        alloc::dealloc(chunk as *mut u8, Self::layout((*chunk).cap).unwrap());
+    }
+
+    // Recomputes offsets of `chunk` and every chunk after it
+    // after lengths have been changed
+    unsafe fn renumber(mut chunk: *mut Self) {
+        let mut offset = 0;
+        while !chunk.is_null() {
+            (*chunk).offset = offset;
+            offset += (*chunk).len;
+            chunk = (*chunk).next;
+        }
+    }
+
+    // Frees `chunk` and every chunk linked after it
+    unsafe fn free_list(mut chunk: *mut Self) {
+        while !chunk.is_null() {
+            let next = (*chunk).next;
+            Self::free(chunk);
+            chunk = next;
+        }
+    }
+}
+
+// Tracks the write position while compacting a chain.  Dropping it
+// truncates the chain there, so if the predicate panics partway
+// through, unvisited elements are leaked rather than dropped twice.
+struct Compact<T> {
+    chunk: *mut Chunk<T>,
+    len: usize,
+    head: *mut Chunk<T>,
+    tail: *mut Chunk<T>
+}
+
+impl<T> Drop for Compact<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut cur = self.chunk;
+            (*cur).len = self.len;
+            while cur != self.tail {
+                cur = (*cur).next;
+                (*cur).len = 0;
+            }
+            Chunk::renumber(self.head);
         }
     }
 }
 
 impl<T> Chain<T> {
-    pub fn new() -> Self {
-        Self::with_capacity(8)
+    // Size of the header at the start of each chunk, including any
+    // padding before the first element
+    pub const CHUNK_HEADER_SIZE: usize = mem::size_of::<Chunk<T>>();
+    // Alignment of each chunk allocation
+    pub const CHUNK_ALIGN: usize = mem::align_of::<Chunk<T>>();
+
+    // Layout of the allocation for a chunk holding `cap` elements, or
+    // None if it would be too large
+    pub fn chunk_layout(cap: usize) -> Option<Layout> {
+        Chunk::<T>::layout(cap)
     }
 
-    pub fn with_capacity(cap: usize) -> Self {
-        let head = Chunk::new(cmp::max(cap, 1));
+    // Creates an empty chain.  No memory is allocated until the
+    // first element is added.
+    pub const fn new() -> Self {
         Chain {
+            head: Cell::new(ptr::null_mut()),
+            tail: Cell::new(ptr::null_mut()),
+            policy: None,
+            stamp: Cell::new(0),
+            index: Cell::new(Vec::new()),
+            usage: Usage::new(),
+            teardown: Teardown::new(),
+            budget: None,
+            seal: None,
+            _ph: PhantomData
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        if cap == 0 {
+            return Self::new()
+        }
+        let head = Chunk::new(Chunk::<T>::initial_cap(cap));
+        let chain = Chain {
             head: Cell::new(head),
             tail: Cell::new(head),
+            policy: None,
+            stamp: Cell::new(0),
+            index: Cell::new(Vec::new()),
+            usage: Usage::new(),
+            teardown: Teardown::new(),
+            budget: None,
+            seal: None,
+            _ph: PhantomData
+        };
+        chain.allocated(head);
+        chain
+    }
+
+    // Creates an empty chain that sizes its chunks with `policy`
+    pub fn with_policy<G>(policy: G) -> Self
+            where G: GrowthPolicy + Send + Sync + 'static {
+        Chain {
+            head: Cell::new(ptr::null_mut()),
+            tail: Cell::new(ptr::null_mut()),
+            policy: Some(Box::new(policy)),
+            stamp: Cell::new(0),
+            index: Cell::new(Vec::new()),
+            usage: Usage::new(),
+            teardown: Teardown::new(),
+            budget: None,
+            seal: None,
             _ph: PhantomData
         }
     }
 
-    // FIXME: track total len in header to make this O(1)?
-    pub fn len(&self) -> usize {
-        let mut len = 0;
+    // Creates a chain with every chunk described by `profile`
+    // already allocated.  Pushes move through the chunks in order
+    // as each fills, as with the spare chunks left by `clear`.
+    pub fn with_profile(profile: &SizingProfile) -> Self {
+        let chain = Self::new();
+        let mut last: *mut Chunk<T> = ptr::null_mut();
+        for &cap in profile.capacities() {
+            let chunk = Chunk::new(cap);
+            chain.allocated(chunk);
+            unsafe {
+                if last.is_null() {
+                    chain.head.set(chunk);
+                    chain.tail.set(chunk);
+                } else {
+                    (*chunk).prev = last;
+                    (*last).next = chunk;
+                }
+            }
+            last = chunk;
+        }
+        chain
+    }
+
+    // Records the capacities of all chunks the chain has allocated,
+    // including spares, for replay with `with_profile`
+    pub fn sizing_profile(&self) -> SizingProfile {
+        let mut caps = Vec::new();
         let mut cur = self.head.get();
+        while !cur.is_null() {
+            unsafe {
+                caps.push((*cur).cap);
+                cur = (*cur).next;
+            }
+        }
+        SizingProfile::from_capacities(caps)
+    }
 
+    // Bytes allocated for chunks, including spare chunks and
+    // chunk headers
+    fn reserved_bytes(&self) -> usize {
+        let mut total = 0;
+        let mut cur = self.head.get();
         while !cur.is_null() {
             unsafe {
-                len += (*cur).len;
+                total += Chunk::<T>::mem_size((*cur).cap).unwrap();
                 cur = (*cur).next;
             }
         }
-        len
+        total
     }
 
-    // Reserves space for at least `len` more contiguous elements, returning
-    // a pointer to the space and the available capacity (which may be > `len`)
-    #[inline(never)]
-    pub fn reserve(&self, len: usize) -> (*mut T, usize) {
-        unsafe {
-            let tail = self.tail.get();
-            let cap = (*tail).cap;
-            if cap - (*tail).len < len {
-                // Grow capacity exponentially to amortize cost of insertions
-                let mut new_cap = cap.checked_mul(2).unwrap();
-                while new_cap < len {
-                    new_cap = new_cap.checked_mul(2).unwrap();
-                }
-                let new = Chunk::new(new_cap);
+    // Feeds the contents to `state` a chunk at a time, which for
+    // primitive elements lets the hasher consume whole slices.
+    // Unlike `Hash::hash`, the result may depend on how elements are
+    // split across chunks unless the hasher treats its input as a
+    // single byte stream, as `DefaultHasher` does.
+    pub fn content_hash<H: Hasher>(&self, state: &mut H) where T: Hash {
+        state.write_usize(self.len());
+        for chunk in self.chunks() {
+            T::hash_slice(chunk, state);
+        }
+    }
 
-                (*new).prev = tail;
-                (*tail).next = new;
-                self.tail.set(new);
+    // Describes how the chain's memory is laid out across chunks
+    pub fn stats(&self) -> ChainStats {
+        let mut chunks = Vec::new();
+        let mut wasted = 0;
+        let mut past_tail = self.tail.get().is_null();
+        let mut cur = self.head.get();
+        while !cur.is_null() {
+            unsafe {
+                chunks.push(((*cur).len, (*cur).cap));
+                if !past_tail && cur != self.tail.get() {
+                    wasted += (*cur).cap - (*cur).len;
+                }
+                if cur == self.tail.get() {
+                    past_tail = true;
+                }
+                cur = (*cur).next;
             }
-
-            let tail = self.tail.get();
-            let ptr = (*tail).items.as_mut_ptr().offset((*tail).len as isize);
-            let cap = (*tail).cap - (*tail).len;
-            (ptr, cap)
+        }
+        ChainStats {
+            chunks: chunks,
+            reserved: self.reserved_bytes(),
+            wasted: wasted
         }
     }
 
-    // Adds to length of curent chunk.  Usually used after
-    // writing into reserved space.
-    pub unsafe fn add_len(&self, len: usize) {
-        let tail = self.tail.get();
-        (*tail).len += len;
+    // Total memory attributable to the chain: its own chunks plus
+    // whatever the elements own on the heap
+    pub fn deep_size(&self) -> usize where T: MemUsage {
+        mem::size_of::<Self>() + self.reserved_bytes() +
+            self.iter().map(MemUsage::heap_size).sum::<usize>()
     }
 
-    // Shrinks length of allocation at (ptr, ptr + old_len) if possible
-    pub unsafe fn shrink_len(&self, ptr: *mut T, old_len: usize, new_len: usize) {
-        let tail = self.tail.get();
-        if ptr.offset(old_len as isize) == (*tail).items.as_mut_ptr().offset((*tail).len as isize) {
-            (*tail).len = (*tail).len - old_len + new_len;
+    // Capacity for a new chunk following one of capacity `last`
+    fn next_capacity(&self, last: usize, needed: usize) -> usize {
+        if mem::size_of::<T>() == 0 {
+            return usize::MAX
         }
+        let cap = match self.policy {
+            Some(ref policy) => policy.next_capacity(last, needed),
+            None => Doubling.next_capacity(last, needed)
+        };
+        cmp::max(cap, needed)
     }
 
-    #[inline]
-    pub fn push(&self, elem: T) -> &T {
-        let (ptr, _) = self.reserve(1);
-        unsafe {
-            ptr::write(ptr, elem);
-            self.add_len(1);
-            &*ptr
-        }
+    // Allocates a chunk with room for `len` elements after one whose
+    // growth capacity is `grow` (0 if there is none).  A request
+    // larger than the chunk growth would produce next gets a chunk
+    // of exactly its size instead, and advances growth by one step
+    // rather than to its size.  Advancing at all keeps a run of
+    // requests just past the normal size from each getting a chunk.
+    fn new_chunk(&self, grow: usize, len: usize) -> Result<*mut Chunk<T>, AllocError> {
+        let normal = self.next_capacity(grow, 1);
+        let chunk = if len > normal {
+            let chunk = Chunk::try_new(len)?;
+            unsafe {
+                (*chunk).grow = normal;
+            }
+            chunk
+        } else {
+            Chunk::try_new(self.next_capacity(grow, len))?
+        };
+        self.allocated(chunk);
+        Ok(chunk)
     }
 
-    pub fn extend_as_slice<E: IntoIterator<Item=T>>(&self, elems: E) -> &[T]
-            where E::IntoIter: ExactSizeIterator {
-        let iter = elems.into_iter();
-        let len = iter.len();
-        let (ptr, _) = self.reserve(len);
-        let mut cur = ptr;
-        unsafe {
-            for elem in iter {
-                ptr::write(cur, elem);
-                cur = cur.offset(1);
+    // Records that `chunk` now belongs to the chain
+    fn allocated(&self, chunk: *mut Chunk<T>) {
+        let bytes = unsafe { Chunk::<T>::mem_size((*chunk).cap).unwrap() };
+        self.usage.grow(bytes);
+        if let Some(ref budget) = self.budget {
+            let before = budget.total.get();
+            let after = before.saturating_add(bytes);
+            budget.total.set(after);
+            if after / budget.every != before / budget.every {
+                budget.due.set(true);
             }
-            self.add_len(len);
-            slice::from_raw_parts(ptr, len)
         }
     }
 
-    pub fn clear(&mut self) {
-        unsafe {
-            loop {
-                let chunk = self.head.get();
-                self.head.set((*chunk).next);
-                if intrinsics::needs_drop::<T>() {
-                    let mut ptr = (*chunk).items.as_mut_ptr();
-                    let end = ptr.offset((*chunk).len as isize);
-                    while ptr < end {
-                        intrinsics::drop_in_place(ptr);
-                        ptr = ptr.offset(mem::size_of::<T>() as isize);
-                    }
-                }
-                if chunk == self.tail.get() {
-                    break
-                }
-                heap::deallocate(chunk as *mut u8,
-                                 mem::size_of::<Chunk<T>>() + (*chunk).len * mem::size_of::<T>(),
-                                 mem::align_of::<Chunk<T>>());
+    // Runs the budget hook if a threshold has been crossed, returning
+    // whether it ran.  Only called once the chain is consistent, since
+    // the hook may touch the chain through some other path.
+    fn run_budget_hook(&self) -> bool {
+        match self.budget {
+            Some(ref budget) if budget.due.get() => {
+                budget.due.set(false);
+                (budget.hook)(budget.total.get());
+                true
             }
-            let save = self.tail.get();
-            self.head.set(save);
-            (*save).len = 0;
+            _ => false
         }
     }
 
-    pub fn chunks(&self) -> Chunks<T> {
-        Chunks {
-            start: self.head.get(),
-            end: self.tail.get(),
-            _ph: PhantomData
-        }
+    // Calls `hook` with the running total each time the bytes
+    // allocated for new chunks cross a multiple of `every`, counting
+    // from now.  Long builds can use this to yield or apply
+    // backpressure at regular intervals.  The hook runs after the
+    // chunk is linked in and before the triggering push completes.
+    pub fn set_budget_hook<F>(&mut self, every: usize, hook: F)
+            where F: Fn(usize) + Send + Sync + 'static {
+        assert!(every != 0, "Chain: budget interval must be nonzero");
+        self.budget = Some(Box::new(Budget {
+            every: every,
+            total: Cell::new(0),
+            due: Cell::new(false),
+            hook: Box::new(hook)
+        }));
     }
 
-    pub fn chunks_mut(&mut self) -> ChunksMut<T> {
-        ChunksMut {
-            start: self.head.get(),
-            end: self.tail.get(),
-            _ph: PhantomData
-        }
+    // Calls `hook` with the contents of each chunk once the chain
+    // moves on to the next one, which is the point to compress it or
+    // spill a copy to slower storage.  The chunk itself stays in
+    // place, since references into it may still be live.
+    //
+    // Sealing is undone if `pop`, `clear`, `clear_some` or `drain`
+    // make a sealed chunk the tail again.  It is then refilled and the hook
+    // runs on it a second time, so a chunk may be sealed more than
+    // once.  The address of the slice's first element identifies the
+    // chunk, and each call supersedes the earlier ones for it.
+    pub fn set_seal_hook<F>(&mut self, hook: F)
+            where F: Fn(&[T]) + Send + Sync + 'static {
+        self.seal = Some(Box::new(hook));
     }
 
-    pub fn iter(&self) -> Iter<T> {
-        #[inline(always)]
-        fn id<T>(x: T) -> T { x }
-        Iter(self.chunks().flat_map(id))
+    // Runs the seal hook on `chunk`, returning whether it ran
+    fn run_seal_hook(&self, chunk: *mut Chunk<T>) -> bool {
+        match self.seal {
+            Some(ref hook) => {
+                hook(unsafe { slice::from_raw_parts((*chunk).items.as_ptr(), (*chunk).len) });
+                true
+            }
+            None => false
+        }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<T> {
-        #[inline(always)]
-        fn id<T>(x: T) -> T { x }
-        IterMut(self.chunks_mut().flat_map(id))
+    // Records that `chunk` and every chunk after it have left the chain
+    fn released(&self, mut chunk: *mut Chunk<T>) {
+        while !chunk.is_null() {
+            unsafe {
+                self.usage.shrink(Chunk::<T>::mem_size((*chunk).cap).unwrap());
+                chunk = (*chunk).next;
+            }
+        }
     }
-}
 
-impl<'a, T: 'a> IntoIterator for &'a Chain<T> {
-    type Item = &'a T;
-    type IntoIter = Iter<'a, T>;
+    // Most bytes the chain has had allocated for chunks at once since
+    // it was created or `reset_peak` was last called
+    #[cfg(feature = "peak-stats")]
+    pub fn peak_bytes(&self) -> usize {
+        self.usage.peak.get()
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+    // Lowers the high-water mark to what is allocated now
+    #[cfg(feature = "peak-stats")]
+    pub fn reset_peak(&self) {
+        self.usage.peak.set(self.usage.current.get());
     }
-}
 
-impl<'a, T: 'a> IntoIterator for &'a mut Chain<T> {
-    type Item = &'a mut T;
-    type IntoIter = IterMut<'a, T>;
+    // Calls `hook` with the time spent dropping the elements and
+    // freeing the chunks when the chain is dropped, to measure the
+    // cost of tearing down an arena
+    #[cfg(feature = "drop-timing")]
+    pub fn set_drop_timing_hook<F>(&mut self, hook: F)
+            where F: Fn(DropTiming) + Send + Sync + 'static {
+        self.teardown.hook = Some(Box::new(hook));
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter_mut()
+    pub fn len(&self) -> usize {
+        let tail = self.tail.get();
+        if tail.is_null() {
+            0
+        } else {
+            unsafe { (*tail).offset + (*tail).len }
+        }
     }
-}
 
-impl<T> IntoIterator for Chain<T> {
-    type Item = T;
-    type IntoIter = IntoIter<T>;
+    // Marks the chain as restructured, invalidating positions handed
+    // out and the chunk index
+    fn restructured(&self) {
+        self.stamp.set(0);
+        let mut index = self.index.take();
+        index.clear();
+        self.index.set(index);
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
+    // Finds the chunk holding the element at `index`, and the
+    // element's position within it, by binary searching the chunk
+    // index on each chunk's offset.  Chunks added since the last
+    // lookup follow the index's last entry, so bringing it up to
+    // the tail only visits those.
+    fn locate(&self, index: usize) -> Option<(*mut Chunk<T>, usize)> {
+        if index >= self.len() {
+            return None
+        }
+        let tail = self.tail.get();
+        let mut chunks = self.index.take();
         unsafe {
-            let start = self.head.get();
-            let end = self.tail.get();
-            mem::forget(self);
-            IntoIter {
-                start: start,
-                end: end,
-                front: (*start).items.as_mut_ptr(),
-                _ph: PhantomData
+            let last = chunks.last().cloned();
+            if last != Some(tail) {
+                let mut chunk = match last {
+                    Some(last) => (*last).next,
+                    None => self.head.get()
+                };
+                loop {
+                    chunks.push(chunk);
+                    if chunk == tail {
+                        break
+                    }
+                    chunk = (*chunk).next;
+                }
             }
+            // The last chunk starting at or before `index`, which
+            // skips any empty chunks sharing its offset
+            let pos = chunks.partition_point(|&chunk| (*chunk).offset <= index) - 1;
+            let chunk = chunks[pos];
+            self.index.set(chunks);
+            Some((chunk, index - (*chunk).offset))
         }
     }
-}
 
-impl<T: fmt::Debug> fmt::Debug for Chain<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut need_comma = false;
-        try!(write!(f, "["));
-        for elem in self {
-            if need_comma {
-                try!(write!(f, ", "));
-            }
-            try!(elem.fmt(f));
-            need_comma = true;
+    // Element at `index`, found in O(log chunks)
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.locate(index).map(|(chunk, i)| unsafe {
+            &*(*chunk).items.as_ptr().offset(i as isize)
+        })
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.locate(index).map(|(chunk, i)| unsafe {
+            &mut *(*chunk).items.as_mut_ptr().offset(i as isize)
+        })
+    }
+
+    // Views the current elements as a ChainSlice, which can be split
+    // without copying.  Elements pushed later aren't part of it.
+    pub fn as_chain_slice(&self) -> ChainSlice<'_, T> {
+        ChainSlice {
+            start: self.head.get(),
+            skip: 0,
+            len: self.len(),
+            _ph: PhantomData
         }
-        write!(f, "]")
     }
-}
 
-impl io::Write for Chain<u8> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let (ptr, len) = self.reserve(0);
-        let len = cmp::min(len, buf.len());
-        if len != 0 {
-            unsafe {
-                ptr::copy_nonoverlapping(buf.as_ptr(), ptr, len);
+    // Index of `elem` if it is one of the chain's elements, found
+    // from its address by searching back from the tail chunk.
+    // Always None for zero-sized types, whose addresses don't tell
+    // elements apart.
+    pub fn index_of(&self, elem: &T) -> Option<usize> {
+        let size = mem::size_of::<T>();
+        if size == 0 {
+            return None
+        }
+        let addr = elem as *const T as usize;
+        let mut chunk = self.tail.get();
+        unsafe {
+            while !chunk.is_null() {
+                let start = (*chunk).items.as_ptr() as usize;
+                if addr >= start && addr < start + (*chunk).len * size {
+                    return Some((*chunk).offset + (addr - start) / size)
+                }
+                chunk = (*chunk).prev;
             }
         }
-        Ok(len)
+        None
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+    // Compares two elements by the order they were pushed, which is
+    // also the order iteration visits them.  Sorting references by it
+    // walks the chunks front to back.  Panics if either isn't an
+    // element of the chain.
+    pub fn cmp_order(&self, a: &T, b: &T) -> cmp::Ordering {
+        match (self.index_of(a), self.index_of(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => panic!("Chain: cmp_order given an element of another chain")
+        }
     }
-}
 
-impl<T> Drop for Chain<T> {
-    fn drop(&mut self) {
-        self.clear();
-        let chunk = self.head.get();
+    // Returns the first `n` elements as one slice, if they all lie
+    // in the first chunk
+    pub fn head_slice(&self, n: usize) -> Option<&[T]> {
+        let head = self.head.get();
+        if n == 0 {
+            return Some(&[])
+        }
+        if head.is_null() {
+            return None
+        }
         unsafe {
-            heap::deallocate(chunk as *mut u8,
-                             mem::size_of::<Chunk<T>>() + (*chunk).len * mem::size_of::<T>(),
-                             mem::align_of::<Chunk<T>>());
+            if (*head).len >= n {
+                Some(slice::from_raw_parts((*head).items.as_ptr(), n))
+            } else {
+                None
+            }
+        }
+    }
 
+    #[inline]
+    pub fn last(&self) -> Option<&T> {
+        match self.len() {
+            0 => None,
+            len => self.get(len - 1)
         }
     }
-}
 
-pub struct Chunks<'a, T: 'a> {
-    start: *mut Chunk<T>,
+    // Removes the last element.  Emptying the tail chunk makes the
+    // previous chunk the tail again, keeping the emptied one as a
+    // spare for the next push.
+    pub fn pop(&mut self) -> Option<T> {
+        self.restructured();
+        unsafe {
+            let mut tail = self.tail.get();
+            if tail.is_null() {
+                return None
+            }
+            while (*tail).len == 0 {
+                if (*tail).prev.is_null() {
+                    return None
+                }
+                tail = (*tail).prev;
+            }
+            (*tail).len -= 1;
+            let elem = ptr::read((*tail).items.as_ptr().offset((*tail).len as isize));
+            if (*tail).len == 0 && !(*tail).prev.is_null() {
+                // The previous chunk was sealed; this unseals it
+                tail = (*tail).prev;
+            }
+            self.tail.set(tail);
+            Some(elem)
+        }
+    }
+
+    // Reserves space for at least `len` more contiguous elements, returning
+    // a pointer to the space and the available capacity (which may be > `len`)
+    #[inline(never)]
+    pub(crate) fn raw_reserve(&self, len: usize) -> (*mut T, usize) {
+        match self.raw_try_reserve(len) {
+            Ok(space) => space,
+            Err(_) => panic!("Chain: failed to allocate chunk!")
+        }
+    }
+
+    // Like `raw_reserve`, but returns an error instead of panicking if
+    // the size overflows or the allocator fails
+    pub(crate) fn raw_try_reserve(&self, len: usize) -> Result<(*mut T, usize), AllocError> {
+        unsafe {
+            let tail = self.tail.get();
+            if tail.is_null() {
+                // First allocation for a chain created empty
+                let new = self.new_chunk(0, len)?;
+                self.head.set(new);
+                self.tail.set(new);
+                if self.run_budget_hook() {
+                    return self.raw_try_reserve(len)
+                }
+                return Ok(((*new).items.as_mut_ptr(), (*new).cap))
+            }
+            let cap = (*tail).cap;
+            if cap - (*tail).len < len {
+                let next = (*tail).next;
+                if !next.is_null() && (*next).cap >= len {
+                    // Reuse an empty chunk left over from draining
+                    self.tail.set(next);
+                } else {
+                    let new = self.new_chunk((*tail).grow, len)?;
+
+                    (*new).prev = tail;
+                    (*new).next = next;
+                    if !next.is_null() {
+                        (*next).prev = new;
+                    }
+                    (*tail).next = new;
+                    self.tail.set(new);
+                }
+                let new = self.tail.get();
+                (*new).offset = (*tail).offset + (*tail).len;
+                // The hooks may have pushed, so look again
+                let sealed = self.run_seal_hook(tail);
+                if self.run_budget_hook() || sealed {
+                    return self.raw_try_reserve(len)
+                }
+            }
+
+            let tail = self.tail.get();
+            let ptr = (*tail).items.as_mut_ptr().offset((*tail).len as isize);
+            let cap = (*tail).cap - (*tail).len;
+            Ok((ptr, cap))
+        }
+    }
+
+    // Whether reserving `len` contiguous elements would allocate a
+    // chunk, rather than using the tail's spare room or a spare chunk
+    pub fn will_grow(&self, len: usize) -> bool {
+        if len == 0 || self.spare().1 >= len {
+            return false
+        }
+        let tail = self.tail.get();
+        if tail.is_null() {
+            return true
+        }
+        unsafe {
+            let next = (*tail).next;
+            next.is_null() || (*next).cap < len
+        }
+    }
+
+    // Makes room for `len` more elements to be added contiguously
+    // without allocating again.  If that needs a new chunk, it is
+    // exactly `len` long rather than sized by the growth policy.
+    pub fn reserve_exact(&self, len: usize) {
+        if !self.will_grow(len) {
+            return
+        }
+        unsafe {
+            let tail = self.tail.get();
+            let chunk = Chunk::new(len);
+            if !tail.is_null() {
+                // Doesn't disturb the growth sequence, as with other
+                // oversized chunks
+                (*chunk).grow = (*tail).grow;
+            }
+            self.allocated(chunk);
+            self.link_spare(chunk, true);
+        }
+    }
+
+    // Allocates `count` empty chunks of `cap` elements up front, so
+    // that pushes can run without allocating until they are used up
+    pub fn preallocate_chunks(&self, count: usize, cap: usize) {
+        for _ in 0..count {
+            let chunk = Chunk::new(Chunk::<T>::initial_cap(cap));
+            self.allocated(chunk);
+            unsafe {
+                self.link_spare(chunk, false);
+            }
+        }
+    }
+
+    // Adds an empty chunk after the tail, ahead of any other spare
+    // chunks if `first` is set and after them otherwise
+    unsafe fn link_spare(&self, chunk: *mut Chunk<T>, first: bool) {
+        let tail = self.tail.get();
+        if tail.is_null() {
+            self.head.set(chunk);
+            self.tail.set(chunk);
+            return
+        }
+        let mut prev = tail;
+        if !first {
+            while !(*prev).next.is_null() {
+                prev = (*prev).next;
+            }
+        }
+        let next = (*prev).next;
+        (*chunk).prev = prev;
+        (*chunk).next = next;
+        if !next.is_null() {
+            (*next).prev = chunk;
+        }
+        (*prev).next = chunk;
+    }
+
+    // Reserves contiguous space for at least `len` elements to be
+    // filled in place without unsafe code.  Whatever has been
+    // written when the reservation is committed or dropped becomes
+    // part of the chain.
+    pub fn reserve_uninit(&mut self, len: usize) -> Reservation<'_, T> {
+        let (ptr, cap) = self.raw_reserve(len);
+        Reservation {
+            chain: self,
+            ptr: ptr,
+            cap: cap,
+            filled: 0
+        }
+    }
+
+    // Like `reserve_uninit`, but returns an error instead of
+    // panicking if the size overflows or the allocator fails
+    pub fn try_reserve_uninit(&mut self, len: usize) -> Result<Reservation<'_, T>, AllocError> {
+        let (ptr, cap) = self.raw_try_reserve(len)?;
+        Ok(Reservation {
+            chain: self,
+            ptr: ptr,
+            cap: cap,
+            filled: 0
+        })
+    }
+
+    // The raw reservation interface.  Nothing ties the pointer to the
+    // chain's borrow or checks the lengths passed back, so it is
+    // kept only for existing callers.
+
+    #[deprecated(note = "use reserve_uninit")]
+    pub fn reserve(&self, len: usize) -> (*mut T, usize) {
+        self.raw_reserve(len)
+    }
+
+    #[deprecated(note = "use try_reserve_uninit")]
+    pub fn try_reserve(&self, len: usize) -> Result<(*mut T, usize), AllocError> {
+        self.raw_try_reserve(len)
+    }
+
+    #[deprecated(note = "use reserve_uninit")]
+    pub unsafe fn add_len(&self, len: usize) {
+        self.raw_add_len(len)
+    }
+
+    #[deprecated(note = "use reserve_uninit")]
+    pub unsafe fn shrink_len(&self, ptr: *mut T, old_len: usize, new_len: usize) {
+        self.raw_shrink_len(ptr, old_len, new_len)
+    }
+
+    // Returns the space remaining in the current chunk without
+    // allocating.  The capacity is 0 if the chain has no chunks yet
+    // or the current chunk is full.
+    pub fn spare(&self) -> (*mut T, usize) {
+        let tail = self.tail.get();
+        if tail.is_null() {
+            return (ptr::null_mut(), 0)
+        }
+        unsafe {
+            let ptr = (*tail).items.as_mut_ptr().offset((*tail).len as isize);
+            (ptr, (*tail).cap - (*tail).len)
+        }
+    }
+
+    // Elements that can still be added to the current chunk, and so
+    // the most that can be reserved contiguously without allocating
+    #[inline]
+    pub fn remaining_in_current_chunk(&self) -> usize {
+        self.spare().1
+    }
+
+    // Elements that can be added without allocating, counting both
+    // the current chunk and any empty chunks kept after it
+    pub fn total_spare_capacity(&self) -> usize {
+        let mut total = self.spare().1;
+        let tail = self.tail.get();
+        if tail.is_null() {
+            return total
+        }
+        unsafe {
+            let mut cur = (*tail).next;
+            while !cur.is_null() {
+                total = total.saturating_add((*cur).cap);
+                cur = (*cur).next;
+            }
+        }
+        total
+    }
+
+    // Adds to length of curent chunk.  Usually used after
+    // writing into reserved space.
+    pub(crate) unsafe fn raw_add_len(&self, len: usize) {
+        let tail = self.tail.get();
+        (*tail).len += len;
+    }
+
+    // Shrinks length of allocation at (ptr, ptr + old_len) if possible
+    pub(crate) unsafe fn raw_shrink_len(&self, ptr: *mut T, old_len: usize, new_len: usize) {
+        let tail = self.tail.get();
+        if ptr.offset(old_len as isize) == (*tail).items.as_mut_ptr().offset((*tail).len as isize) {
+            (*tail).len = (*tail).len - old_len + new_len;
+        }
+    }
+
+    #[inline]
+    pub fn push(&self, elem: T) -> &T {
+        let (ptr, _) = self.raw_reserve(1);
+        unsafe {
+            ptr::write(ptr, elem);
+            self.raw_add_len(1);
+            &*ptr
+        }
+    }
+
+    // Constructs an element in place by calling `init` with a
+    // pointer to its slot.  Nothing is added if `init` panics.
+    //
+    // Unsafe because `init` must fully initialize the slot, and must
+    // not add to this chain.
+    pub unsafe fn emplace_with<F: FnOnce(*mut T)>(&self, init: F) -> &T {
+        let (ptr, _) = self.raw_reserve(1);
+        init(ptr);
+        self.raw_add_len(1);
+        &*ptr
+    }
+
+    // Like `push`, but hands the element back if space for
+    // it can't be allocated
+    #[inline]
+    pub fn try_push(&self, elem: T) -> Result<&T, T> {
+        match self.raw_try_reserve(1) {
+            Ok((ptr, _)) => unsafe {
+                ptr::write(ptr, elem);
+                self.raw_add_len(1);
+                Ok(&*ptr)
+            },
+            Err(_) => Err(elem)
+        }
+    }
+
+    pub fn extend_as_slice<E: IntoIterator<Item=T>>(&self, elems: E) -> &[T]
+            where E::IntoIter: ExactSizeIterator {
+        let iter = elems.into_iter();
+        let len = iter.len();
+        let (ptr, _) = self.raw_reserve(len);
+        let mut cur = ptr;
+        unsafe {
+            for elem in iter {
+                ptr::write(cur, elem);
+                cur = cur.offset(1);
+            }
+            self.raw_add_len(len);
+            slice::from_raw_parts(ptr, len)
+        }
+    }
+
+    // Copies `elems` into contiguous space with a single memcpy
+    pub fn extend_from_slice(&self, elems: &[T]) -> &[T] where T: Copy {
+        let (ptr, _) = self.raw_reserve(elems.len());
+        unsafe {
+            ptr::copy_nonoverlapping(elems.as_ptr(), ptr, elems.len());
+            self.raw_add_len(elems.len());
+            slice::from_raw_parts(ptr, elems.len())
+        }
+    }
+
+    // Appends values returned by `f` until the chain holds `new_len`
+    // elements.  The tail's spare room is used first, then a single
+    // chunk big enough for the rest.  Nothing happens if the chain is
+    // already that long, since elements can't be removed through &self.
+    pub fn resize_with<F: FnMut() -> T>(&self, new_len: usize, mut f: F) {
+        let mut len = self.len();
+        while len < new_len {
+            // `f` may push too, so reserve only once it has run
+            let elem = f();
+            let want = if self.spare().1 != 0 { 1 } else { new_len - len };
+            let (ptr, _) = self.raw_reserve(want);
+            unsafe {
+                ptr::write(ptr, elem);
+                self.raw_add_len(1);
+            }
+            len = self.len();
+        }
+    }
+
+    // Like `resize_with`, appending clones of `value`
+    pub fn resize(&self, new_len: usize, value: T) where T: Clone {
+        if self.len() >= new_len {
+            return
+        }
+        self.resize_with(new_len - 1, || value.clone());
+        self.push(value);
+    }
+
+    // Keeps only the elements for which `f` returns true, preserving
+    // order.  Survivors are moved down to fill the gaps, spilling into
+    // the slack at the end of earlier chunks, and chunks left empty
+    // at the end are freed.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        self.restructured();
+        if self.head.get().is_null() {
+            return
+        }
+        unsafe {
+            let tail = self.tail.get();
+            let mut write = Compact {
+                chunk: self.head.get(),
+                len: 0,
+                head: self.head.get(),
+                tail: tail
+            };
+            let mut chunk = self.head.get();
+            loop {
+                let src = (*chunk).items.as_mut_ptr();
+                for i in 0..(*chunk).len {
+                    let ptr = src.offset(i as isize);
+                    if f(&*ptr) {
+                        if write.len == (*write.chunk).cap {
+                            (*write.chunk).len = write.len;
+                            write.chunk = (*write.chunk).next;
+                            write.len = 0;
+                        }
+                        let dst = (*write.chunk).items.as_mut_ptr().offset(write.len as isize);
+                        if dst != ptr {
+                            ptr::copy_nonoverlapping(ptr, dst, 1);
+                        }
+                        write.len += 1;
+                    } else {
+                        ptr::drop_in_place(ptr);
+                    }
+                }
+                if chunk == tail {
+                    break
+                }
+                chunk = (*chunk).next;
+            }
+
+            let last = write.chunk;
+            drop(write);
+            self.released((*last).next);
+            Chunk::free_list((*last).next);
+            (*last).next = ptr::null_mut();
+            self.tail.set(last);
+        }
+    }
+
+    // Moves all elements into a single chunk of exactly the right
+    // size and frees the rest, giving back the slack left by growth.
+    // Later pushes start a new chunk, continuing the growth sequence
+    // from where it left off.
+    pub fn compact(&mut self) {
+        self.restructured();
+        let head = self.head.get();
+        if head.is_null() {
+            return
+        }
+        let len = self.len();
+        unsafe {
+            let tail = self.tail.get();
+            if head == tail && (*head).next.is_null() && (*head).cap == len {
+                return
+            }
+            let grow = (*tail).grow;
+            let new = if len == 0 {
+                ptr::null_mut()
+            } else {
+                let new = Chunk::new(Chunk::<T>::initial_cap(len));
+                self.allocated(new);
+                let mut dst = (*new).items.as_mut_ptr();
+                let mut cur = head;
+                loop {
+                    ptr::copy_nonoverlapping((*cur).items.as_ptr(), dst, (*cur).len);
+                    dst = dst.offset((*cur).len as isize);
+                    if cur == tail {
+                        break
+                    }
+                    cur = (*cur).next;
+                }
+                (*new).len = len;
+                (*new).grow = grow;
+                new
+            };
+            self.released(head);
+            Chunk::free_list(head);
+            self.head.set(new);
+            self.tail.set(new);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.head.get().is_null() {
+            return
+        }
+        unsafe {
+            self.clear_chunks()
+        }
+    }
+
+    // Drops up to `max` elements from the end of the chain, returning
+    // whether any remain.  Calling this repeatedly spreads the cost of
+    // clearing a large chain over time; unlike `clear`, elements are
+    // dropped last to first.  Chunks are kept for reuse.
+    pub fn clear_some(&mut self, max: usize) -> bool {
+        if !mem::needs_drop::<T>() {
+            self.clear();
+            return false
+        }
+        self.restructured();
+        let mut budget = max;
+        unsafe {
+            let mut tail = self.tail.get();
+            if tail.is_null() {
+                return false
+            }
+            loop {
+                let len = (*tail).len;
+                let count = cmp::min(len, budget);
+                // Shrink first so a panicking drop can't be resumed
+                // into a double drop
+                (*tail).len = len - count;
+                budget -= count;
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                    (*tail).items.as_mut_ptr().offset((len - count) as isize), count));
+                if (*tail).len != 0 {
+                    return true
+                }
+                if (*tail).prev.is_null() {
+                    return false
+                }
+                // The emptied chunk stays linked as a spare, and the
+                // sealed one before it becomes the tail again
+                tail = (*tail).prev;
+                self.tail.set(tail);
+                if budget == 0 {
+                    return (*tail).len != 0 || !(*tail).prev.is_null()
+                }
+            }
+        }
+    }
+
+    // Drops all elements but keeps every chunk, so refilling the
+    // chain reuses the same memory.  If an element's drop panics, a
+    // guard finishes the job while unwinding, so the chain is left
+    // empty and consistent; a second panic aborts, as with Vec.
+    unsafe fn clear_chunks(&self) {
+        struct Guard<'a, T: 'a>(&'a Chain<T>);
+
+        impl<'a, T> Drop for Guard<'a, T> {
+            fn drop(&mut self) {
+                unsafe { self.0.clear_chunks() }
+            }
+        }
+
+        let mut chunk = self.head.get();
+        loop {
+            let len = (*chunk).len;
+            // Mark the chunk empty first so a resumed clear
+            // doesn't drop its elements twice
+            (*chunk).len = 0;
+            (*chunk).offset = 0;
+            if mem::needs_drop::<T>() && len != 0 {
+                let guard = Guard(self);
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut((*chunk).items.as_mut_ptr(), len));
+                mem::forget(guard);
+            }
+            if chunk == self.tail.get() {
+                break
+            }
+            chunk = (*chunk).next;
+        }
+        // Later chunks become spares for reserve to move into, and
+        // all of them are unsealed
+        self.tail.set(self.head.get());
+        self.restructured();
+    }
+
+    // Removes all elements, yielding them by value.  The chain's
+    // chunks stay allocated and are reused by subsequent pushes.
+    // Elements not consumed are dropped along with the iterator.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.restructured();
+        let head = self.head.get();
+        let tail = self.tail.get();
+        // Detach the chunks until the drain is finished, so leaking
+        // it leaks the elements instead of exposing moved-out values
+        self.head.set(ptr::null_mut());
+        self.tail.set(ptr::null_mut());
+        Drain {
+            chain: self,
+            head: head,
+            last: tail,
+            start: head,
+            end: tail,
+            front: 0
+        }
+    }
+
+    // Moves all elements into a single contiguous Vec
+    pub fn into_vec(self) -> Vec<T> {
+        let mut vec: Vec<T> = Vec::with_capacity(self.len());
+        unsafe {
+            let mut cur = self.head.get();
+            while !cur.is_null() {
+                let len = (*cur).len;
+                let dst = vec.as_mut_ptr().offset(vec.len() as isize);
+                ptr::copy_nonoverlapping((*cur).items.as_ptr(), dst, len);
+                vec.set_len(vec.len() + len);
+                // Elements now belong to the Vec
+                (*cur).len = 0;
+                if cur == self.tail.get() {
+                    break
+                }
+                cur = (*cur).next;
+            }
+        }
+        vec
+    }
+
+    // Consumes the chain, yielding each chunk's elements as an owned
+    // boxed slice.  Chunk memory holds a header before the elements,
+    // so each chunk is moved into a new allocation and freed as it
+    // is reached.
+    pub fn into_chunks(self) -> IntoChunks<T> {
+        self.restructured();
+        IntoChunks {
+            chain: self
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<T> where T: Clone {
+        let mut vec = Vec::with_capacity(self.len());
+        for chunk in self.chunks() {
+            vec.extend_from_slice(chunk);
+        }
+        vec
+    }
+
+    // Detaches every chunk but the tail as a FrozenChain, which can
+    // be handed to another thread while this chain keeps appending
+    // into the tail.
+    pub fn split_last_chunk(&mut self) -> FrozenChain<T> {
+        self.restructured();
+        let head = self.head.get();
+        let tail = self.tail.get();
+        if head == tail {
+            return FrozenChain(Chain::new())
+        }
+        unsafe {
+            let last = (*tail).prev;
+            (*last).next = ptr::null_mut();
+            self.released(head);
+            (*tail).prev = ptr::null_mut();
+            (*tail).offset = 0;
+            self.head.set(tail);
+            FrozenChain(Chain {
+                head: Cell::new(head),
+                tail: Cell::new(last),
+                policy: None,
+                stamp: Cell::new(0),
+                index: Cell::new(Vec::new()),
+                usage: Usage::new(),
+                teardown: Teardown::new(),
+                budget: None,
+                seal: None,
+                _ph: PhantomData
+            })
+        }
+    }
+
+    // Returns the position the next pushed element will occupy.
+    // Positions stay valid until the chain is cleared, drained,
+    // retained or split.
+    pub fn position(&self) -> Position {
+        let mut stamp = self.stamp.get();
+        if stamp == 0 {
+            stamp = NEXT_STAMP.fetch_add(1, Ordering::Relaxed);
+            self.stamp.set(stamp);
+        }
+        let tail = self.tail.get();
+        Position {
+            chunk: tail as *mut u8,
+            index: if tail.is_null() { 0 } else { unsafe { (*tail).len } },
+            stamp: stamp
+        }
+    }
+
+    // A null chunk means the start of the chain, which is always valid
+    fn check(&self, pos: Position) -> Option<(*mut Chunk<T>, usize)> {
+        if pos.chunk.is_null() {
+            Some((ptr::null_mut(), 0))
+        } else if pos.stamp == self.stamp.get() {
+            Some((pos.chunk as *mut Chunk<T>, pos.index))
+        } else {
+            None
+        }
+    }
+
+    pub fn cursor(&self) -> Cursor<'_, T> {
+        Cursor {
+            chain: self,
+            chunk: ptr::null_mut(),
+            index: 0
+        }
+    }
+
+    // Resumes at `pos` in O(1), or returns None if the position
+    // has been invalidated or belongs to another chain
+    pub fn cursor_at(&self, pos: Position) -> Option<Cursor<'_, T>> {
+        self.check(pos).map(|(chunk, index)| Cursor {
+            chain: self,
+            chunk: chunk,
+            index: index
+        })
+    }
+
+    pub fn cursor_mut_at(&mut self, pos: Position) -> Option<CursorMut<'_, T>> {
+        self.check(pos).map(move |(chunk, index)| CursorMut {
+            chain: self,
+            chunk: chunk,
+            index: index
+        })
+    }
+
+    // Moves (chunk, index) to the next element, returning it
+    unsafe fn advance(&self, chunk: &mut *mut Chunk<T>, index: &mut usize) -> Option<*mut T> {
+        if chunk.is_null() {
+            *chunk = self.head.get();
+            *index = 0;
+            if chunk.is_null() {
+                return None
+            }
+        }
+        while *index >= (**chunk).len {
+            if *chunk == self.tail.get() {
+                return None
+            }
+            *chunk = (**chunk).next;
+            *index = 0;
+        }
+        let elem = (**chunk).items.as_mut_ptr().offset(*index as isize);
+        *index += 1;
+        Some(elem)
+    }
+
+    pub fn chunks(&self) -> Chunks<'_, T> {
+        Chunks {
+            start: self.head.get(),
+            end: self.tail.get(),
+            _ph: PhantomData
+        }
+    }
+
+    pub fn chunks_mut(&mut self) -> ChunksMut<'_, T> {
+        ChunksMut {
+            start: self.head.get(),
+            end: self.tail.get(),
+            _ph: PhantomData
+        }
+    }
+
+    // Iterates in the order elements were pushed.  This is
+    // guaranteed: pushes fill each chunk front to back before moving
+    // on, and `retain` and `compact` keep elements' relative order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            chunks: self.chunks(),
+            front: [].iter(),
+            back: [].iter()
+        }
+    }
+
+    // Iterates over each element paired with the one after it,
+    // including pairs that straddle a chunk boundary
+    pub fn pairs(&self) -> Pairs<'_, T> {
+        let mut iter = self.iter();
+        Pairs {
+            prev: iter.next(),
+            iter: iter
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            chunks: self.chunks_mut(),
+            front: [].iter_mut(),
+            back: [].iter_mut()
+        }
+    }
+}
+
+impl<'a, T: 'a> IntoIterator for &'a Chain<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: 'a> IntoIterator for &'a mut Chain<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T> IntoIterator for Chain<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let start = self.head.get();
+        let end = self.tail.get();
+        drop(self.index.take());
+        mem::forget(self);
+        // Detach the empty spares after the tail, so the remaining
+        // range always ends at `end`
+        let spare = if end.is_null() {
+            ptr::null_mut()
+        } else {
+            unsafe { mem::replace(&mut (*end).next, ptr::null_mut()) }
+        };
+        IntoIter {
+            start: start,
+            end: end,
+            front: 0,
+            spare: spare,
+            _ph: PhantomData
+        }
+    }
+}
+
+impl<T> Extend<T> for Chain<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, elems: I) {
+        let mut iter = elems.into_iter();
+        while let Some(first) = iter.next() {
+            // Reserve enough for everything the iterator promises,
+            // then write as much as fits before reserving again
+            let (lower, _) = iter.size_hint();
+            let (ptr, cap) = self.raw_reserve(lower.saturating_add(1));
+            unsafe {
+                ptr::write(ptr, first);
+                self.raw_add_len(1);
+                for i in 1..cap {
+                    match iter.next() {
+                        Some(elem) => {
+                            ptr::write(ptr.offset(i as isize), elem);
+                            self.raw_add_len(1);
+                        }
+                        None => return
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a> Extend<&'a T> for Chain<T> {
+    fn extend<I: IntoIterator<Item=&'a T>>(&mut self, elems: I) {
+        self.extend(elems.into_iter().cloned())
+    }
+}
+
+impl<T> iter::FromIterator<T> for Chain<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(elems: I) -> Self {
+        let mut chain = Chain::new();
+        chain.extend(elems);
+        chain
+    }
+}
+
+// Comparisons are element-wise, so chunk boundaries don't matter
+impl<T: PartialEq<U>, U> PartialEq<Chain<U>> for Chain<T> {
+    fn eq(&self, other: &Chain<U>) -> bool {
+        let mut a = self.iter();
+        let mut b = other.iter();
+        loop {
+            match (a.next(), b.next()) {
+                (Some(x), Some(y)) => if x != y { return false },
+                (None, None) => return true,
+                _ => return false
+            }
+        }
+    }
+}
+
+impl<T: Eq> Eq for Chain<T> {}
+
+impl<T: PartialOrd> PartialOrd for Chain<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord> Ord for Chain<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Hash> Hash for Chain<T> {
+    // Hashes elements one at a time rather than a chunk at a time
+    // so that equal chains hash equally regardless of layout
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for elem in self {
+            elem.hash(state);
+        }
+    }
+}
+
+impl<T> ops::Index<usize> for Chain<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("Chain: index out of bounds")
+    }
+}
+
+impl<T> ops::IndexMut<usize> for Chain<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("Chain: index out of bounds")
+    }
+}
+
+impl<T> Default for Chain<T> {
+    fn default() -> Self {
+        Chain::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Chain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut need_comma = false;
+        write!(f, "[")?;
+        for elem in self {
+            if need_comma {
+                write!(f, ", ")?;
+            }
+            elem.fmt(f)?;
+            need_comma = true;
+        }
+        write!(f, "]")
+    }
+}
+
+// Methods appending a number's bytes in a fixed byte order, each
+// returning the bytes written
+macro_rules! put_bytes {
+    ($($name:ident: $ty:ty => $conv:ident;)*) => {$(
+        #[inline]
+        pub fn $name(&self, value: $ty) -> &[u8] {
+            self.extend_from_slice(&value.$conv())
+        }
+    )*}
+}
+
+impl Chain<u8> {
+    // Appends all of `buf`, topping off the current chunk before
+    // spilling the remainder into a new one
+    fn append(&self, buf: &[u8]) {
+        let (ptr, spare) = self.spare();
+        let head = cmp::min(spare, buf.len());
+        unsafe {
+            if head != 0 {
+                ptr::copy_nonoverlapping(buf.as_ptr(), ptr, head);
+                self.raw_add_len(head);
+            }
+        }
+        let rest = &buf[head..];
+        if !rest.is_empty() {
+            self.extend_from_slice(rest);
+        }
+    }
+
+    // Copies `s` into contiguous space, returning the copy
+    pub fn push_str(&self, s: &str) -> &str {
+        unsafe { str::from_utf8_unchecked(self.extend_from_slice(s.as_bytes())) }
+    }
+
+    // Reads from `reader` straight into the chain's spare capacity,
+    // growing it as needed, until end of input or `limit` bytes.
+    // Bytes read before an error are kept.  Returns the count read.
+    pub fn extend_from_reader<R: io::Read>(&self, mut reader: R, limit: Option<usize>) -> io::Result<usize> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut total = 0;
+        // End of the spare space already zeroed, which `read` needs
+        // to see initialized memory
+        let mut zeroed = ptr::null_mut::<u8>();
+        while total < limit {
+            let (ptr, spare) = self.raw_reserve(1);
+            let want = cmp::min(spare, limit - total);
+            unsafe {
+                let end = ptr.offset(spare as isize);
+                if zeroed < ptr || zeroed > end {
+                    zeroed = ptr;
+                }
+                let need = ptr.offset(want as isize);
+                if zeroed < need {
+                    ptr::write_bytes(zeroed, 0, need as usize - zeroed as usize);
+                    zeroed = need;
+                }
+                match reader.read(slice::from_raw_parts_mut(ptr, want)) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        self.raw_add_len(n);
+                        total += n;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    put_bytes! {
+        put_u8: u8 => to_le_bytes;
+        put_i8: i8 => to_le_bytes;
+        put_u16_le: u16 => to_le_bytes;
+        put_u16_be: u16 => to_be_bytes;
+        put_i16_le: i16 => to_le_bytes;
+        put_i16_be: i16 => to_be_bytes;
+        put_u32_le: u32 => to_le_bytes;
+        put_u32_be: u32 => to_be_bytes;
+        put_i32_le: i32 => to_le_bytes;
+        put_i32_be: i32 => to_be_bytes;
+        put_u64_le: u64 => to_le_bytes;
+        put_u64_be: u64 => to_be_bytes;
+        put_i64_le: i64 => to_le_bytes;
+        put_i64_be: i64 => to_be_bytes;
+        put_f32_le: f32 => to_le_bytes;
+        put_f32_be: f32 => to_be_bytes;
+        put_f64_le: f64 => to_le_bytes;
+        put_f64_be: f64 => to_be_bytes;
+    }
+
+    // Appends `value` as an unsigned LEB128 varint, encoding straight
+    // into reserved space and keeping only the bytes used
+    pub fn put_uvarint(&self, mut value: u64) -> &[u8] {
+        // Enough for any u64 at 7 bits per byte
+        const MAX_LEN: usize = 10;
+        let (ptr, _) = self.raw_reserve(MAX_LEN);
+        let mut len = 0;
+        unsafe {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    *ptr.offset(len as isize) = byte;
+                    len += 1;
+                    break
+                }
+                *ptr.offset(len as isize) = byte | 0x80;
+                len += 1;
+            }
+            self.raw_add_len(len);
+            slice::from_raw_parts(ptr, len)
+        }
+    }
+
+    // Appends `value` as a zigzag-encoded varint, as protobuf's
+    // sint64, so small negative numbers stay short
+    pub fn put_ivarint(&self, value: i64) -> &[u8] {
+        self.put_uvarint(((value << 1) ^ (value >> 63)) as u64)
+    }
+
+    // Returns a reader that yields the contents of the chain
+    // chunk by chunk without copying
+    pub fn reader(&self) -> ChainReader<'_> {
+        ChainReader {
+            chunks: self.chunks(),
+            cur: &[]
+        }
+    }
+
+    // Returns the non-empty chunks as `IoSlice`s, for handing the
+    // contents to `write_vectored` without copying
+    pub fn as_io_slices(&self) -> IoSlices<'_> {
+        IoSlices {
+            chunks: self.chunks()
+        }
+    }
+
+    // Splits the contents into the bytes before `index` and the rest,
+    // or returns None if `index` is past the end
+    pub fn split_at_byte(&self, index: usize) -> Option<(ChainSlice<'_, u8>, ChainSlice<'_, u8>)> {
+        self.as_chain_slice().split_at(index)
+    }
+
+    // Splits the contents around the first occurrence of `needle`,
+    // which may straddle chunks, leaving it out of both halves
+    pub fn split_on(&self, needle: &[u8]) -> Option<(ChainSlice<'_, u8>, ChainSlice<'_, u8>)> {
+        self.as_chain_slice().split_on(needle)
+    }
+}
+
+// Strings written this way may be split across chunks; use
+// `push_str` when the copy must be contiguous
+impl fmt::Write for Chain<u8> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct IoSlices<'a> {
+    chunks: Chunks<'a, u8>
+}
+
+impl<'a> Iterator for IoSlices<'a> {
+    type Item = io::IoSlice<'a>;
+
+    fn next(&mut self) -> Option<io::IoSlice<'a>> {
+        self.chunks.by_ref().find(|c| !c.is_empty()).map(io::IoSlice::new)
+    }
+}
+
+pub struct ChainReader<'a> {
+    chunks: Chunks<'a, u8>,
+    cur: &'a [u8]
+}
+
+impl<'a> io::Read for ChainReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let avail = io::BufRead::fill_buf(self)?;
+            let len = cmp::min(avail.len(), buf.len());
+            buf[..len].copy_from_slice(&avail[..len]);
+            len
+        };
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl<'a> io::BufRead for ChainReader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.cur.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.cur = chunk,
+                None => break
+            }
+        }
+        Ok(self.cur)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Never called with more than the last fill_buf returned,
+        // so this stays within the current chunk
+        self.cur = &self.cur[amt..];
+    }
+}
+
+impl io::Write for Chain<u8> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        // Reserve for the whole batch up front so the slices are
+        // packed into at most one new chunk
+        let total = bufs.iter().map(|b| b.len()).sum();
+        if self.spare().1 < total {
+            self.raw_reserve(total);
+        }
+        for buf in bufs {
+            self.append(buf);
+        }
+        Ok(total)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Space at the end of a chain being filled in place.  The chain is
+// borrowed mutably, so nothing else can allocate into the space
+// while it is being written.
+pub struct Reservation<'a, T: 'a> {
+    chain: &'a mut Chain<T>,
+    ptr: *mut T,
+    cap: usize,
+    filled: usize
+}
+
+impl<'a, T> Reservation<'a, T> {
+    // Elements written so far
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.filled
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn push(&mut self, elem: T) -> Result<(), T> {
+        if self.filled == self.cap {
+            return Err(elem)
+        }
+        unsafe {
+            ptr::write(self.ptr.offset(self.filled as isize), elem);
+        }
+        self.filled += 1;
+        Ok(())
+    }
+
+    // The space not yet written, for code that fills memory itself.
+    // Call `assume_filled` afterward to count what was written.
+    pub fn spare_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr.offset(self.filled as isize) as *mut MaybeUninit<T>,
+                                      self.cap - self.filled)
+        }
+    }
+
+    // Counts the first `n` elements of `spare_mut` as written.
+    // Unsafe because they must really have been initialized.
+    pub unsafe fn assume_filled(&mut self, n: usize) {
+        assert!(n <= self.cap - self.filled);
+        self.filled += n;
+    }
+
+    // Adds the written elements to the chain and returns them
+    pub fn commit(self) -> &'a mut [T] {
+        let ptr = self.ptr;
+        let len = self.filled;
+        // Dropping commits
+        drop(self);
+        unsafe { slice::from_raw_parts_mut(ptr, len) }
+    }
+}
+
+impl<'a, T> Drop for Reservation<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.chain.raw_add_len(self.filled);
+        }
+    }
+}
+
+// Snapshot of a chain's chunk layout, for tuning capacities
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainStats {
+    chunks: Vec<(usize, usize)>,
+    reserved: usize,
+    wasted: usize
+}
+
+impl ChainStats {
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // `(len, cap)` of each chunk in order, including spare chunks
+    // kept for reuse after the last one in use
+    pub fn chunks(&self) -> &[(usize, usize)] {
+        &self.chunks
+    }
+
+    // Bytes allocated for all chunks, headers included
+    pub fn reserved_bytes(&self) -> usize {
+        self.reserved
+    }
+
+    // Elements of capacity left unused in chunks before the last one
+    // in use.  This space can no longer be filled, since growing
+    // always moves on to a new chunk.
+    pub fn wasted(&self) -> usize {
+        self.wasted
+    }
+}
+
+// Chunks split off a Chain.  Nothing more can be added, so
+// unlike a Chain it can be shared between threads.
+pub struct FrozenChain<T>(Chain<T>);
+
+unsafe impl<T: Sync> Sync for FrozenChain<T> {}
+
+impl<T> FrozenChain<T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.0.get(index)
+    }
+
+    #[inline]
+    pub fn chunks(&self) -> Chunks<'_, T> {
+        self.0.chunks()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.0.iter()
+    }
+}
+
+impl<T> IntoIterator for FrozenChain<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a FrozenChain<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.0.iter()
+    }
+}
+
+impl<T> Drop for Chain<T> {
+    fn drop(&mut self) {
+        // Frees the chunks even if clearing panics
+        struct Free<'a, T: 'a>(&'a Chain<T>);
+
+        impl<'a, T> Drop for Free<'a, T> {
+            fn drop(&mut self) {
+                unsafe {
+                    Chunk::free_list(self.0.head.get());
+                }
+            }
+        }
+
+        if self.head.get().is_null() {
+            return
+        }
+        let start = self.teardown.start();
+        let free = Free(self);
+        unsafe {
+            free.0.clear_chunks();
+        }
+        let dropped = start.map(|_| Instant::now());
+        drop(free);
+        self.teardown.report(start, dropped);
+    }
+}
+
+pub struct Chunks<'a, T: 'a> {
+    start: *mut Chunk<T>,
+    end: *mut Chunk<T>,
+    _ph: PhantomData<&'a [T]>
+}
+
+impl<'a, T> Iterator for Chunks<'a, T> {
+    type Item = &'a [T];
+
+    fn next(&mut self) -> Option<&'a [T]> {
+        let chunk = self.start;
+        if chunk.is_null() {
+            None
+        } else {
+            unsafe {
+                if chunk == self.end {
+                    self.start = ptr::null_mut();
+                    self.end = ptr::null_mut()
+                } else {
+                    self.start = (*chunk).next
+                }
+                Some(slice::from_raw_parts((*chunk).items.as_ptr(), (*chunk).len))
+            }
+        }
+    }
+}
+
+unsafe impl <'a, T:Send> Send for Chunks<'a, T> {}
+unsafe impl <'a, T:Sync> Sync for Chunks<'a, T> {}
+
+impl<'a, T> Clone for Chunks<'a, T> {
+    fn clone(&self) -> Self {
+        Chunks {
+            start: self.start,
+            end: self.end,
+            _ph: PhantomData
+        }
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Chunks<'a, T> {
+    fn next_back(&mut self) -> Option<&'a [T]> {
+        let chunk = self.end;
+        if chunk.is_null() {
+            None
+        } else {
+            unsafe {
+                if chunk == self.start {
+                    self.start = ptr::null_mut();
+                    self.end = ptr::null_mut()
+                } else {
+                    self.end = (*chunk).prev
+                }
+                Some(slice::from_raw_parts((*chunk).items.as_ptr(), (*chunk).len))
+            }
+        }
+    }
+}
+
+pub struct ChunksMut<'a, T: 'a> {
+    start: *mut Chunk<T>,
+    end: *mut Chunk<T>,
+    _ph: PhantomData<&'a mut [T]>
+}
+
+impl<'a, T> Iterator for ChunksMut<'a, T> {
+    type Item = &'a mut [T];
+
+    fn next(&mut self) -> Option<&'a mut [T]> {
+        let chunk = self.start;
+        if chunk.is_null() {
+            None
+        } else {
+            unsafe {
+                if chunk == self.end {
+                    self.start = ptr::null_mut();
+                    self.end = ptr::null_mut()
+                } else {
+                    self.start = (*chunk).next
+                }
+                Some(slice::from_raw_parts_mut((*chunk).items.as_mut_ptr(), (*chunk).len))
+            }
+        }
+    }
+}
+
+unsafe impl <'a, T:Send> Send for ChunksMut<'a, T> {}
+unsafe impl <'a, T:Sync> Sync for ChunksMut<'a, T> {}
+
+impl<'a, T> DoubleEndedIterator for ChunksMut<'a, T> {
+    fn next_back(&mut self) -> Option<&'a mut [T]> {
+        let chunk = self.end;
+        if chunk.is_null() {
+            None
+        } else {
+            unsafe {
+                if chunk == self.start {
+                    self.start = ptr::null_mut();
+                    self.end = ptr::null_mut()
+                } else {
+                    self.end = (*chunk).prev
+                }
+                Some(slice::from_raw_parts_mut((*chunk).items.as_mut_ptr(), (*chunk).len))
+            }
+        }
+    }
+}
+
+// Walks the chunks, iterating within the current chunk at each end
+// with a plain slice iterator so the per-element path stays inlinable
+pub struct Iter<'a, T: 'a> {
+    chunks: Chunks<'a, T>,
+    front: slice::Iter<'a, T>,
+    back: slice::Iter<'a, T>
+}
+
+impl<'a, T: 'a> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(elem) = self.front.next() {
+                return Some(elem)
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.front = chunk.iter(),
+                None => return self.back.next()
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.front.len() + self.back.len(), None)
+    }
+
+    // Folds a whole chunk at a time, which lets the slice fold
+    // vectorize where stepping through `next` can't
+    #[inline]
+    fn fold<A, F>(self, init: A, mut f: F) -> A where F: FnMut(A, &'a T) -> A {
+        let acc = self.front.fold(init, &mut f);
+        let acc = self.chunks.fold(acc, |acc, chunk| chunk.iter().fold(acc, &mut f));
+        self.back.fold(acc, f)
+    }
+}
+
+impl<'a, T: 'a> DoubleEndedIterator for Iter<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(elem) = self.back.next_back() {
+                return Some(elem)
+            }
+            match self.chunks.next_back() {
+                Some(chunk) => self.back = chunk.iter(),
+                None => return self.front.next_back()
+            }
+        }
+    }
+
+    #[inline]
+    fn rfold<A, F>(self, init: A, mut f: F) -> A where F: FnMut(A, &'a T) -> A {
+        let mut acc = self.back.rfold(init, &mut f);
+        for chunk in self.chunks.rev() {
+            acc = chunk.iter().rfold(acc, &mut f);
+        }
+        self.front.rfold(acc, f)
+    }
+}
+
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Self {
+        Iter {
+            chunks: self.chunks.clone(),
+            front: self.front.clone(),
+            back: self.back.clone()
+        }
+    }
+}
+
+pub struct Pairs<'a, T: 'a> {
+    iter: Iter<'a, T>,
+    prev: Option<&'a T>
+}
+
+impl<'a, T: 'a> Iterator for Pairs<'a, T> {
+    type Item = (&'a T, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a T, &'a T)> {
+        let prev = self.prev?;
+        let next = self.iter.next()?;
+        self.prev = Some(next);
+        Some((prev, next))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.prev.is_none() {
+            return (0, Some(0))
+        }
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T> Clone for Pairs<'a, T> {
+    fn clone(&self) -> Self {
+        Pairs {
+            iter: self.iter.clone(),
+            prev: self.prev
+        }
+    }
+}
+
+pub struct IterMut<'a, T: 'a> {
+    chunks: ChunksMut<'a, T>,
+    front: slice::IterMut<'a, T>,
+    back: slice::IterMut<'a, T>
+}
+
+impl<'a, T: 'a> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a mut T> {
+        loop {
+            if let Some(elem) = self.front.next() {
+                return Some(elem)
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.front = chunk.iter_mut(),
+                None => return self.back.next()
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.front.len() + self.back.len(), None)
+    }
+
+    #[inline]
+    fn fold<A, F>(self, init: A, mut f: F) -> A where F: FnMut(A, &'a mut T) -> A {
+        let mut acc = self.front.fold(init, &mut f);
+        for chunk in self.chunks {
+            acc = chunk.iter_mut().fold(acc, &mut f);
+        }
+        self.back.fold(acc, f)
+    }
+}
+
+impl<'a, T: 'a> DoubleEndedIterator for IterMut<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        loop {
+            if let Some(elem) = self.back.next_back() {
+                return Some(elem)
+            }
+            match self.chunks.next_back() {
+                Some(chunk) => self.back = chunk.iter_mut(),
+                None => return self.front.next_back()
+            }
+        }
+    }
+
+    #[inline]
+    fn rfold<A, F>(self, init: A, mut f: F) -> A where F: FnMut(A, &'a mut T) -> A {
+        let mut acc = self.back.rfold(init, &mut f);
+        for chunk in self.chunks.rev() {
+            acc = chunk.iter_mut().rfold(acc, &mut f);
+        }
+        self.front.rfold(acc, f)
+    }
+}
+
+pub struct IntoChunks<T> {
+    chain: Chain<T>
+}
+
+impl<T> Iterator for IntoChunks<T> {
+    type Item = Box<[T]>;
+
+    fn next(&mut self) -> Option<Box<[T]>> {
+        loop {
+            let chunk = self.chain.head.get();
+            if chunk.is_null() {
+                return None
+            }
+            unsafe {
+                let len = (*chunk).len;
+                let mut vec: Vec<T> = Vec::with_capacity(len);
+                ptr::copy_nonoverlapping((*chunk).items.as_ptr(), vec.as_mut_ptr(), len);
+                vec.set_len(len);
+                let next = (*chunk).next;
+                if chunk == self.chain.tail.get() {
+                    // Only empty spares remain
+                    Chunk::free_list(next);
+                    self.chain.head.set(ptr::null_mut());
+                    self.chain.tail.set(ptr::null_mut());
+                } else {
+                    (*next).prev = ptr::null_mut();
+                    self.chain.head.set(next);
+                }
+                Chunk::free(chunk);
+                if len != 0 {
+                    return Some(vec.into_boxed_slice())
+                }
+            }
+        }
+    }
+}
+
+pub struct IntoIter<T> {
+    start: *mut Chunk<T>,
+    end: *mut Chunk<T>,
+    // Index of the next element in the start chunk.  An index
+    // rather than a pointer, so zero-sized elements are counted.
+    front: usize,
+    // Empty chunks that followed the tail, freed with the iterator
+    spare: *mut Chunk<T>,
+    _ph: PhantomData<T>
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start.is_null() {
+            return None
+        }
+        loop {
+            unsafe {
+                let chunk = self.start;
+                if self.front == (*chunk).len {
+                    if self.start == self.end {
+                        return None
+                    }
+                    self.start = (*chunk).next;
+                    Chunk::free(chunk);
+                    self.front = 0;
+                    continue;
+                }
+                let ptr = (*chunk).items.as_mut_ptr().offset(self.front as isize);
+                self.front += 1;
+
+                return Some(ptr::read(ptr))
+            }
+        }
+    }
+}
+
+unsafe impl <T: Send> Send for IntoIter<T> {}
+unsafe impl <T: Sync> Sync for IntoIter<T> {}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.end.is_null() {
+            return None
+        }
+        loop {
+            unsafe {
+                let chunk = self.end;
+                let front = if chunk == self.start { self.front } else { 0 };
+                if (*chunk).len == front {
+                    if chunk == self.start {
+                        return None
+                    }
+                    self.end = (*chunk).prev;
+                    (*self.end).next = ptr::null_mut();
+                    Chunk::free(chunk);
+                    continue;
+                }
+                (*chunk).len -= 1;
+                let ptr = (*chunk).items.as_mut_ptr().offset((*chunk).len as isize);
+                return Some(ptr::read(ptr))
+            }
+        }
+    }
+}
+
+impl<T> Drop for IntoIter<T> {
+    fn drop(&mut self) {
+        // Drops the remaining elements and frees the chunks, even
+        // while unwinding from a panicking element drop
+        struct Guard<'a, T: 'a>(&'a mut IntoIter<T>);
+
+        impl<'a, T> Drop for Guard<'a, T> {
+            fn drop(&mut self) {
+                for _ in self.0.by_ref() {}
+                debug_assert!(self.0.start == self.0.end);
+                unsafe {
+                    Chunk::free_list(self.0.start);
+                    Chunk::free_list(self.0.spare);
+                }
+            }
+        }
+
+        let guard = Guard(self);
+        for elem in guard.0.by_ref() {
+            drop(elem);
+        }
+    }
+}
+
+pub struct Drain<'a, T: 'a> {
+    chain: &'a mut Chain<T>,
+    // First and last chunks, restored to the chain afterward
+    head: *mut Chunk<T>,
+    last: *mut Chunk<T>,
+    // Remaining range, as in IntoIter
+    start: *mut Chunk<T>,
     end: *mut Chunk<T>,
+    front: usize
+}
+
+impl<'a, T> Iterator for Drain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start.is_null() {
+            return None
+        }
+        loop {
+            unsafe {
+                let chunk = self.start;
+                if self.front == (*chunk).len {
+                    if chunk == self.end {
+                        return None
+                    }
+                    self.start = (*chunk).next;
+                    self.front = 0;
+                    continue;
+                }
+                let ptr = (*chunk).items.as_mut_ptr().offset(self.front as isize);
+                self.front += 1;
+
+                return Some(ptr::read(ptr))
+            }
+        }
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Drain<'a, T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.end.is_null() {
+            return None
+        }
+        loop {
+            unsafe {
+                let chunk = self.end;
+                let front = if chunk == self.start { self.front } else { 0 };
+                if (*chunk).len == front {
+                    if chunk == self.start {
+                        return None
+                    }
+                    self.end = (*chunk).prev;
+                    continue;
+                }
+                (*chunk).len -= 1;
+                let ptr = (*chunk).items.as_mut_ptr().offset((*chunk).len as isize);
+                return Some(ptr::read(ptr))
+            }
+        }
+    }
+}
+
+// A recorded place in a Chain, from which a cursor can resume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    // Chunk and index within it of the next element, type erased
+    // so positions don't carry the element type
+    chunk: *mut u8,
+    index: usize,
+    stamp: usize
+}
+
+unsafe impl Send for Position {}
+unsafe impl Sync for Position {}
+
+// Iterates forward from a position.  More elements may be pushed
+// while a cursor is live; it sees them once it reaches them.
+pub struct Cursor<'a, T: 'a> {
+    chain: &'a Chain<T>,
+    chunk: *mut Chunk<T>,
+    index: usize
+}
+
+impl<'a, T> Cursor<'a, T> {
+    // The position of the element `next` would return
+    pub fn position(&self) -> Position {
+        Position {
+            stamp: self.chain.position().stamp,
+            chunk: self.chunk as *mut u8,
+            index: self.index
+        }
+    }
+}
+
+impl<'a, T> Iterator for Cursor<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        unsafe {
+            self.chain.advance(&mut self.chunk, &mut self.index).map(|elem| &*elem)
+        }
+    }
+}
+
+pub struct CursorMut<'a, T: 'a> {
+    chain: &'a mut Chain<T>,
+    chunk: *mut Chunk<T>,
+    index: usize
+}
+
+impl<'a, T> CursorMut<'a, T> {
+    pub fn position(&self) -> Position {
+        Position {
+            stamp: self.chain.position().stamp,
+            chunk: self.chunk as *mut u8,
+            index: self.index
+        }
+    }
+}
+
+impl<'a, T> Iterator for CursorMut<'a, T> {
+    type Item = &'a mut T;
+
+    // Each element is returned at most once, as the cursor only
+    // moves forward
+    fn next(&mut self) -> Option<&'a mut T> {
+        unsafe {
+            self.chain.advance(&mut self.chunk, &mut self.index).map(|elem| &mut *elem)
+        }
+    }
+}
+
+impl<'a, T> Drop for Drain<'a, T> {
+    fn drop(&mut self) {
+        // Drops the remaining elements and reattaches the chunks,
+        // even while unwinding from a panicking element drop
+        struct Guard<'r, 'a: 'r, T: 'a>(&'r mut Drain<'a, T>);
+
+        impl<'r, 'a, T> Drop for Guard<'r, 'a, T> {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                for _ in drain.by_ref() {}
+                if drain.head.is_null() {
+                    return
+                }
+                unsafe {
+                    let mut cur = drain.head;
+                    loop {
+                        (*cur).len = 0;
+                        (*cur).offset = 0;
+                        if cur == drain.last {
+                            break
+                        }
+                        cur = (*cur).next;
+                    }
+                }
+                drain.chain.head.set(drain.head);
+                drain.chain.tail.set(drain.head);
+            }
+        }
+
+        let guard = Guard(self);
+        for elem in guard.0.by_ref() {
+            drop(elem);
+        }
+    }
+}
+
+// A run of a chain's elements, which may span chunks
+pub struct ChainSlice<'a, T: 'a> {
+    // Chunk holding the first element, and its index there
+    start: *mut Chunk<T>,
+    skip: usize,
+    len: usize,
+    _ph: PhantomData<&'a [T]>
+}
+
+impl<'a, T> Clone for ChainSlice<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for ChainSlice<'a, T> {}
+
+impl<'a, T> ChainSlice<'a, T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The slice's part of each chunk it touches, skipping empty ones
+    pub fn chunks(&self) -> SliceChunks<'a, T> {
+        SliceChunks {
+            chunk: self.start,
+            skip: self.skip,
+            remaining: self.len,
+            _ph: PhantomData
+        }
+    }
+
+    pub fn iter(&self) -> iter::Flatten<SliceChunks<'a, T>> {
+        self.chunks().flatten()
+    }
+
+    pub fn to_vec(&self) -> Vec<T> where T: Clone {
+        let mut vec = Vec::with_capacity(self.len);
+        for chunk in self.chunks() {
+            vec.extend_from_slice(chunk);
+        }
+        vec
+    }
+
+    // Splits into the elements before `index` and the rest, or
+    // returns None if `index` is past the end.  Finding the chunk
+    // takes a walk from the start of the slice.
+    pub fn split_at(&self, index: usize) -> Option<(Self, Self)> {
+        if index > self.len {
+            return None
+        }
+        let mut chunk = self.start;
+        let mut pos = self.skip + index;
+        unsafe {
+            // Only chunks holding elements of the slice are visited,
+            // so `chunk` is never null while elements remain
+            while index < self.len && pos >= (*chunk).len {
+                pos -= (*chunk).len;
+                chunk = (*chunk).next;
+            }
+        }
+        Some((ChainSlice {
+            start: self.start,
+            skip: self.skip,
+            len: index,
+            _ph: PhantomData
+        }, ChainSlice {
+            start: chunk,
+            skip: pos,
+            len: self.len - index,
+            _ph: PhantomData
+        }))
+    }
+}
+
+impl<'a> ChainSlice<'a, u8> {
+    // Index of the first occurrence of `needle`.  Candidates are
+    // compared byte by byte, so matches straddling chunks are found.
+    pub fn find(&self, needle: &[u8]) -> Option<usize> {
+        let mut iter = self.iter();
+        let mut pos = 0;
+        while self.len - pos >= needle.len() {
+            if iter.clone().zip(needle).all(|(a, b)| a == b) {
+                return Some(pos)
+            }
+            iter.next();
+            pos += 1;
+        }
+        None
+    }
+
+    // Splits around the first occurrence of `needle`, leaving it out
+    // of both halves
+    pub fn split_on(&self, needle: &[u8]) -> Option<(Self, Self)> {
+        let index = self.find(needle)?;
+        let (head, rest) = self.split_at(index)?;
+        let (_, tail) = rest.split_at(needle.len())?;
+        Some((head, tail))
+    }
+}
+
+impl<'a, T: PartialEq> PartialEq<[T]> for ChainSlice<'a, T> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.len == other.len() && self.iter().eq(other)
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for ChainSlice<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct SliceChunks<'a, T: 'a> {
+    chunk: *mut Chunk<T>,
+    skip: usize,
+    remaining: usize,
     _ph: PhantomData<&'a [T]>
 }
 
-impl<'a, T> Iterator for Chunks<'a, T> {
-    type Item = &'a [T];
+impl<'a, T> Clone for SliceChunks<'a, T> {
+    fn clone(&self) -> Self {
+        SliceChunks {
+            chunk: self.chunk,
+            skip: self.skip,
+            remaining: self.remaining,
+            _ph: PhantomData
+        }
+    }
+}
+
+impl<'a, T> Iterator for SliceChunks<'a, T> {
+    type Item = &'a [T];
+
+    fn next(&mut self) -> Option<&'a [T]> {
+        while self.remaining != 0 {
+            unsafe {
+                let chunk = self.chunk;
+                let len = cmp::min((*chunk).len - self.skip, self.remaining);
+                let start = (*chunk).items.as_ptr().offset(self.skip as isize);
+                self.chunk = (*chunk).next;
+                self.skip = 0;
+                self.remaining -= len;
+                if len != 0 {
+                    return Some(slice::from_raw_parts(start, len))
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[test]
+    #[allow(clippy::declare_interior_mutable_const, clippy::borrow_interior_mutable_const)]
+    fn lazy_alloc() {
+        const EMPTY: Chain<u32> = Chain::new();
+        assert_eq!(EMPTY.len(), 0);
+        assert_eq!(EMPTY.iter().next(), None);
+
+        let mut chain = Chain::new();
+        chain.clear();
+        chain.retain(|_| true);
+        assert_eq!(Chain::<u32>::new().into_iter().next(), None);
+        chain.push(1);
+        chain.push(2);
+        assert_eq!(chain.into_iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn drain() {
+        let mut chain = Chain::with_capacity(2);
+        for i in 0..20 {
+            chain.push(i.to_string());
+        }
+        assert_eq!(chain.drain().collect::<Vec<_>>(),
+                   (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+        assert_eq!(chain.len(), 0);
+
+        for i in 0..20 {
+            chain.push(i.to_string());
+        }
+        {
+            let mut drain = chain.drain();
+            assert_eq!(drain.next_back(), Some("19".to_string()));
+            assert_eq!(drain.next(), Some("0".to_string()));
+        }
+        assert_eq!(chain.len(), 0);
+        chain.push("again".to_string());
+        assert_eq!(chain.iter().collect::<Vec<_>>(), vec!["again"]);
+    }
+
+    #[test]
+    fn into_iter_back() {
+        let chain = Chain::with_capacity(1);
+        for i in 0..3 {
+            chain.push(i.to_string());
+        }
+        let mut iter = chain.into_iter();
+        for i in (0..3).rev() {
+            assert_eq!(iter.next_back(), Some(i.to_string()));
+        }
+        drop(iter);
+
+        // Spare chunks left by clear are freed too
+        let mut chain = Chain::with_capacity(1);
+        for i in 0..6 {
+            chain.push(i.to_string());
+        }
+        chain.clear();
+        for i in 0..3 {
+            chain.push(i.to_string());
+        }
+        let mut iter = chain.into_iter();
+        assert_eq!(iter.next_back(), Some("2".to_string()));
+        assert_eq!(iter.next_back(), Some("1".to_string()));
+        assert_eq!(iter.next(), Some("0".to_string()));
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn iter_both_ends() {
+        let mut chain = Chain::with_capacity(3);
+        for i in 0..10 {
+            chain.push(i);
+        }
+        let mut iter = chain.iter();
+        assert_eq!(iter.next(), Some(&0));
+        assert_eq!(iter.next_back(), Some(&9));
+        let rest = iter.clone().cloned().collect::<Vec<_>>();
+        assert_eq!(rest, (1..9).collect::<Vec<_>>());
+        assert_eq!(iter.clone().fold(Vec::new(), |mut v, &i| { v.push(i); v }), rest);
+        assert_eq!(iter.clone().rfold(Vec::new(), |mut v, &i| { v.insert(0, i); v }), rest);
+        assert_eq!(iter.rev().cloned().collect::<Vec<_>>(),
+                   (1..9).rev().collect::<Vec<_>>());
+
+        for elem in chain.iter_mut().rev() {
+            *elem *= 2;
+        }
+        let mut iter = chain.iter_mut();
+        iter.next_back();
+        iter.rfold((), |_, elem| *elem += 1);
+        chain.iter_mut().fold((), |_, elem| *elem -= 1);
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(),
+                   (0..10).map(|i| if i == 9 { 17 } else { i * 2 }).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn extend() {
+        let mut chain: Chain<u32> = (0..5).collect();
+        chain.extend(5..50);
+        chain.extend(&[50, 51]);
+        chain.extend((52..100).filter(|_| true));
+        assert_eq!(chain.len(), 100);
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(),
+                   (0..100).collect::<Vec<_>>());
+        chain.extend(None::<u32>);
+        assert_eq!(chain.len(), 100);
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn compare() {
+        use std::collections::HashMap;
+
+        let a: Chain<u8> = Chain::with_capacity(1);
+        let b: Chain<u8> = Chain::with_capacity(64);
+        a.extend_as_slice(b"hello".iter().cloned());
+        for &byte in b"hello" {
+            b.push(byte);
+        }
+        assert_eq!(a, b);
+
+        let mut map = HashMap::new();
+        map.insert(a, 1);
+        assert_eq!(map.get(&b), Some(&1));
+
+        b.push(b'!');
+        assert!(!map.contains_key(&b));
+        let c: Chain<u8> = b"hell".iter().cloned().collect();
+        assert!(c < b);
+        assert!(b > c);
+        assert_eq!(b.cmp(&b), cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn get() {
+        let mut chain = Chain::with_capacity(1);
+        assert_eq!(chain.get(0), None);
+        for i in 0..100 {
+            assert_eq!(chain.len(), i);
+            chain.push(i);
+        }
+        for i in 0..100 {
+            assert_eq!(chain.get(i), Some(&i));
+        }
+        assert_eq!(chain.get(100), None);
+        chain[42] = 0;
+        *chain.get_mut(99).unwrap() = 1;
+        assert_eq!((chain[42], chain[99]), (0, 1));
+
+        chain.retain(|&i| i % 2 == 1);
+        assert_eq!(chain.len(), 50);
+        assert_eq!(chain.get(49), Some(&1));
+        assert_eq!(chain.get(10), Some(&21));
+        chain.drain();
+        chain.push(7);
+        assert_eq!((chain.len(), chain.get(0)), (1, Some(&7)));
+    }
+
+    #[test]
+    fn get_index() {
+        let mut chain = Chain::with_policy(super::super::growth::Fixed(4));
+        let mut model = Vec::new();
+        let check = |chain: &Chain<usize>, model: &Vec<usize>| {
+            for (i, elem) in model.iter().enumerate() {
+                assert_eq!(chain.get(i), Some(elem));
+            }
+            assert_eq!(chain.get(model.len()), None);
+        };
+        // Lookups between pushes extend the index from its last entry
+        for i in 0..50 {
+            chain.push(i);
+            model.push(i);
+            check(&chain, &model);
+        }
+        // Popping back across chunks and refilling the spares
+        for _ in 0..10 {
+            chain.pop();
+            model.pop();
+        }
+        check(&chain, &model);
+        chain.extend_from_slice(&[100; 9]);
+        model.extend_from_slice(&[100; 9]);
+        check(&chain, &model);
+        chain.clear();
+        model.clear();
+        check(&chain, &model);
+        for i in 0..30 {
+            chain.push(i);
+            model.push(i);
+        }
+        check(&chain, &model);
+    }
+
+    #[test]
+    fn into_vec() {
+        let chain: Chain<String> = (0..50).map(|i| i.to_string()).collect();
+        let expected: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+        assert_eq!(chain.to_vec(), expected);
+        assert_eq!(chain.into_vec(), expected);
+        assert!(Chain::<u8>::new().into_vec().is_empty());
+    }
+
+    #[test]
+    fn write() {
+        let mut chain = Chain::with_capacity(4);
+        chain.write_all(b"hello, ").unwrap();
+        assert_eq!(chain.write(b"world").unwrap(), 5);
+        let bufs = [io::IoSlice::new(b"! "), io::IoSlice::new(b"bye")];
+        assert_eq!(chain.write_vectored(&bufs).unwrap(), 5);
+        assert_eq!(chain.to_vec(), b"hello, world! bye");
+    }
+
+    #[test]
+    fn reader() {
+        use std::io::{BufRead, Read};
+        let mut chain = Chain::with_capacity(3);
+        chain.write_all(b"line one\nline two\n").unwrap();
+        let mut reader = chain.reader();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line one\n");
+        let mut rest = Vec::new();
+        io::copy(&mut reader, &mut rest).unwrap();
+        assert_eq!(rest, b"line two\n");
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn io_slices() {
+        let mut chain = Chain::with_capacity(4);
+        chain.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        let slices: Vec<io::IoSlice> = chain.as_io_slices().collect();
+        assert!(slices.len() > 1);
+        let mut out = Vec::new();
+        let n = out.write_vectored(&slices).unwrap();
+        assert_eq!(n, chain.len());
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\n\r\n");
+        chain.clear();
+        assert_eq!(chain.as_io_slices().count(), 0);
+    }
+
+    #[test]
+    fn drop_panic() {
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+
+        thread_local!(static DROPPED: Cell<usize> = const { Cell::new(0) });
+
+        struct Bomb(bool);
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.set(d.get() + 1));
+                if self.0 {
+                    panic!("boom")
+                }
+            }
+        }
+
+        fn filled() -> Chain<Bomb> {
+            DROPPED.with(|d| d.set(0));
+            let chain = Chain::with_capacity(4);
+            for i in 0..40 {
+                chain.push(Bomb(i == 5));
+            }
+            chain
+        }
+
+        fn dropped() -> usize {
+            DROPPED.with(|d| d.get())
+        }
 
-    fn next(&mut self) -> Option<&'a [T]> {
-        let chunk = self.start;
-        if chunk.is_null() {
-            None
-        } else {
-            unsafe {
-                if chunk == self.end {
-                    self.start = ptr::null_mut();
-                    self.end = ptr::null_mut()
-                } else {
-                    self.start = (*chunk).next
-                }
-                Some(slice::from_raw_parts((*chunk).items.as_ptr(), (*chunk).len))
+        let mut chain = filled();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| chain.clear()));
+        assert!(res.is_err());
+        assert_eq!((dropped(), chain.len()), (40, 0));
+        chain.push(Bomb(false));
+        assert_eq!(chain.len(), 1);
+        drop(chain);
+
+        let chain = filled();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| drop(chain))).is_err());
+        assert_eq!(dropped(), 40);
+
+        let chain = filled();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| drop(chain.into_iter()))).is_err());
+        assert_eq!(dropped(), 40);
+
+        let mut chain = filled();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| drop(chain.drain()))).is_err());
+        assert_eq!((dropped(), chain.len()), (40, 0));
+    }
+
+    #[test]
+    fn split_last_chunk() {
+        use std::thread;
+
+        let mut chain = Chain::with_capacity(2);
+        assert_eq!(chain.split_last_chunk().len(), 0);
+        let mut next = 0;
+        let mut consumers = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..10 {
+                chain.push(next);
+                next += 1;
             }
+            let frozen = chain.split_last_chunk();
+            consumers.push(thread::spawn(move || frozen.into_iter().collect::<Vec<_>>()));
         }
+        let mut all: Vec<u32> = consumers.into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect();
+        all.extend(chain.iter().cloned());
+        assert_eq!(all, (0..40).collect::<Vec<_>>());
+        assert_eq!(chain.get(0), Some(&all[40 - chain.len()]));
     }
-}
 
-unsafe impl <'a, T:Send> Send for Chunks<'a, T> {}
-unsafe impl <'a, T:Sync> Sync for Chunks<'a, T> {}
+    #[test]
+    fn clear_reuses_chunks() {
+        let mut chain = Chain::with_capacity(4);
+        chain.extend(0..100u32);
+        let first = &chain[0] as *const u32;
+        let last = &chain[99] as *const u32;
+        chain.clear();
+        assert_eq!(chain.len(), 0);
+        chain.extend(100..200);
+        assert_eq!(&chain[0] as *const u32, first);
+        assert_eq!(&chain[99] as *const u32, last);
+        assert!(chain.iter().cloned().eq(100..200));
+    }
 
-impl<'a, T> DoubleEndedIterator for Chunks<'a, T> {
-    fn next_back(&mut self) -> Option<&'a [T]> {
-        let chunk = self.end;
-        if chunk.is_null() {
-            None
-        } else {
-            unsafe {
-                if chunk == self.start {
-                    self.start = ptr::null_mut();
-                    self.end = ptr::null_mut()
-                } else {
-                    self.end = (*chunk).prev
-                }
-                Some(slice::from_raw_parts((*chunk).items.as_ptr(), (*chunk).len))
-            }
+    #[test]
+    fn overaligned() {
+        #[repr(align(64))]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Line(u8);
+
+        let wide = Chain::with_capacity(4);
+        let lines = Chain::with_capacity(4);
+        for i in 0..40 {
+            wide.push(i as u128);
+            lines.push(Line(i));
+        }
+        assert!(wide.chunks().count() > 2 && lines.chunks().count() > 2);
+        assert!(wide.iter().cloned().eq(0..40));
+        assert!(lines.iter().map(|l| l.0).eq(0..40));
+        for &(len, cap) in wide.stats().chunks().iter().chain(lines.stats().chunks()) {
+            assert!(len <= cap);
         }
     }
-}
 
-pub struct ChunksMut<'a, T: 'a> {
-    start: *mut Chunk<T>,
-    end: *mut Chunk<T>,
-    _ph: PhantomData<&'a mut [T]>
-}
+    #[test]
+    fn chunk_layout() {
+        assert_eq!(Chain::<u8>::CHUNK_HEADER_SIZE, 6 * mem::size_of::<usize>());
+        assert_eq!(Chain::<u64>::CHUNK_ALIGN, mem::align_of::<u64>().max(mem::align_of::<usize>()));
+        let layout = Chain::<u32>::chunk_layout(10).unwrap();
+        assert_eq!(layout.size(), Chain::<u32>::CHUNK_HEADER_SIZE + 40);
+        assert_eq!(layout.align(), Chain::<u32>::CHUNK_ALIGN);
+        assert_eq!(Chain::<u32>::chunk_layout(usize::MAX), None);
+
+        let mut chain = Chain::with_capacity(4);
+        chain.extend(0..10u32);
+        for chunk in chain.chunks() {
+            let header = chunk.as_ptr() as usize - Chain::<u32>::CHUNK_HEADER_SIZE;
+            assert!(header.is_multiple_of(Chain::<u32>::CHUNK_ALIGN));
+        }
+    }
 
-impl<'a, T> Iterator for ChunksMut<'a, T> {
-    type Item = &'a mut [T];
+    #[test]
+    fn clear_some() {
+        let rc = Rc::new(());
+        let mut chain = Chain::with_capacity(4);
+        for _ in 0..10 {
+            chain.push(rc.clone());
+        }
+        let first = &chain[0] as *const Rc<()>;
+        assert!(chain.clear_some(3));
+        assert_eq!((chain.len(), Rc::strong_count(&rc)), (7, 8));
+        assert!(chain.clear_some(6));
+        assert_eq!(chain.len(), 1);
+        assert!(!chain.clear_some(6));
+        assert_eq!((chain.len(), Rc::strong_count(&rc)), (0, 1));
+        assert!(!chain.clear_some(1));
+        chain.push(rc.clone());
+        assert_eq!(&chain[0] as *const Rc<()>, first);
+    }
 
-    fn next(&mut self) -> Option<&'a mut [T]> {
-        let chunk = self.start;
-        if chunk.is_null() {
-            None
-        } else {
-            unsafe {
-                if chunk == self.end {
-                    self.start = ptr::null_mut();
-                    self.end = ptr::null_mut()
-                } else {
-                    self.start = (*chunk).next
-                }
-                Some(slice::from_raw_parts_mut((*chunk).items.as_mut_ptr(), (*chunk).len))
-            }
+    #[test]
+    fn try_reserve() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.try_reserve_uninit(usize::MAX).err(), Some(AllocError));
+        assert_eq!(chain.try_reserve_uninit(usize::MAX / 8).err(), Some(AllocError));
+        assert_eq!(chain.try_push(1u64), Ok(&1));
+        assert_eq!(chain.try_reserve_uninit(usize::MAX / 2).err(), Some(AllocError));
+        assert_eq!(chain.len(), 1);
+        assert!(chain.try_reserve_uninit(100).unwrap().capacity() >= 100);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut chain = Chain::new();
+        for _ in 0..1000 {
+            chain.push(());
         }
+        assert_eq!((chain.len(), chain.chunks().count()), (1000, 1));
+        assert_eq!(chain.iter().count(), 1000);
+        assert_eq!(chain.drain().rev().count(), 1000);
+        chain.extend(vec![(); 50]);
+        assert_eq!(chain.get(49), Some(&()));
+        let mut iter = chain.into_iter();
+        assert_eq!(iter.next_back(), Some(()));
+        assert_eq!(iter.count(), 49);
     }
-}
 
-unsafe impl <'a, T:Send> Send for ChunksMut<'a, T> {}
-unsafe impl <'a, T:Sync> Sync for ChunksMut<'a, T> {}
+    #[test]
+    fn growth_policy() {
+        use super::super::growth::{Fixed, Capped};
 
-impl<'a, T> DoubleEndedIterator for ChunksMut<'a, T> {
-    fn next_back(&mut self) -> Option<&'a mut [T]> {
-        let chunk = self.end;
-        if chunk.is_null() {
-            None
-        } else {
-            unsafe {
-                if chunk == self.start {
-                    self.start = ptr::null_mut();
-                    self.end = ptr::null_mut()
-                } else {
-                    self.end = (*chunk).prev
-                }
-                Some(slice::from_raw_parts_mut((*chunk).items.as_mut_ptr(), (*chunk).len))
-            }
+        fn caps(chain: &Chain<u32>) -> Vec<usize> {
+            chain.chunks().map(|c| c.len()).collect()
+        }
+
+        let chain = Chain::with_policy(Fixed(10));
+        for i in 0..35 {
+            chain.push(i);
         }
+        assert_eq!(caps(&chain), [10, 10, 10, 5]);
+        chain.extend_as_slice(0..12);
+        assert_eq!(caps(&chain), [10, 10, 10, 5, 12]);
+
+        let chain = Chain::with_policy(Capped(32));
+        for i in 0..100 {
+            chain.push(i);
+        }
+        assert_eq!(caps(&chain), [8, 16, 32, 32, 12]);
+
+        let chain = Chain::with_policy(|last: usize, _| last + 4);
+        for i in 0..30 {
+            chain.push(i);
+        }
+        assert_eq!(caps(&chain), [4, 8, 12, 6]);
     }
-}
 
-// Wrapper to hide ugly adapter type
-pub struct Iter<'a, T: 'a>(iter::FlatMap<Chunks<'a, T>, &'a [T], fn(&'a [T]) -> &'a [T]>);
+    #[test]
+    fn oversized() {
+        let chain = Chain::<u8>::new();
+        chain.push(1);
+        let (_, cap) = chain.raw_reserve(100_000);
+        assert_eq!(cap, 100_000);
+        unsafe {
+            chain.raw_add_len(cap);
+        }
+        chain.push(2);
+        // Growth resumes one step past the 8-element first chunk
+        assert_eq!(chain.spare().1, 31);
+        assert_eq!(chain.len(), 100_002);
+
+        // Requests just past the normal size soon fit normal chunks
+        let chain = Chain::<u8>::new();
+        for _ in 0..100 {
+            chain.extend_from_slice(&[0; 24]);
+        }
+        assert_eq!(chain.chunks().count(), 9);
+    }
 
-impl<'a, T: 'a> Iterator for Iter<'a, T> {
-    type Item = &'a T;
-    fn next(&mut self) -> Option<&'a T> {
-        self.0.next()
+    #[test]
+    fn sizing_profile() {
+        let chain = Chain::new();
+        for i in 0..1000 {
+            chain.push(i);
+        }
+        chain.extend_as_slice(0..5000);
+        let profile = chain.sizing_profile();
+        assert_eq!(profile.total(), 8 + 16 + 32 + 64 + 128 + 256 + 512 + 5000);
+
+        let mut replay = Chain::with_profile(&profile);
+        for _ in 0..2 {
+            for i in 0..1000 {
+                replay.push(i);
+            }
+            replay.extend_as_slice(0..5000);
+            assert_eq!(replay.sizing_profile(), profile);
+            assert!(replay.iter().eq(chain.iter()));
+            replay.clear();
+        }
+        assert_eq!(Chain::<u8>::with_profile(&SizingProfile::default()).len(), 0);
     }
-}
 
-impl<'a, T: 'a> DoubleEndedIterator for Iter<'a, T> {
-    fn next_back(&mut self) -> Option<&'a T> {
-        self.0.next_back()
+    #[test]
+    fn content_hash() {
+        use std::collections::hash_map::DefaultHasher;
+
+        fn fingerprint(chain: &Chain<u8>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            chain.content_hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let mut small = Chain::with_capacity(2);
+        let mut large = Chain::with_capacity(64);
+        small.write_all(b"some arena contents").unwrap();
+        large.write_all(b"some arena contents").unwrap();
+        assert!(small.chunks().count() > 1);
+        assert_eq!(fingerprint(&small), fingerprint(&large));
+        large.push(b'!');
+        assert!(fingerprint(&small) != fingerprint(&large));
     }
-}
 
-// Wrapper to hide ugly adapter type
-pub struct IterMut<'a, T: 'a>(iter::FlatMap<ChunksMut<'a, T>, &'a mut [T],
-                              fn(&'a mut [T]) -> &'a mut [T]>);
+    #[cfg(feature = "peak-stats")]
+    #[test]
+    fn peak_bytes() {
+        let mut chain = Chain::new();
+        assert_eq!(chain.peak_bytes(), 0);
+        chain.extend_as_slice(0..100u32);
+        chain.extend_as_slice(0..1000u32);
+        let peak = chain.peak_bytes();
+        assert_eq!(peak, chain.reserved_bytes());
+        chain.retain(|&x| x < 10);
+        assert!(chain.reserved_bytes() < peak);
+        assert_eq!(chain.peak_bytes(), peak);
+        chain.reset_peak();
+        assert_eq!(chain.peak_bytes(), chain.reserved_bytes());
+        drop(chain.split_last_chunk());
+        chain.push(1);
+        assert!(chain.peak_bytes() < peak);
+    }
 
-impl<'a, T: 'a> Iterator for IterMut<'a, T> {
-    type Item = &'a mut T;
-    fn next(&mut self) -> Option<&'a mut T> {
-        self.0.next()
+    #[test]
+    fn compact() {
+        let mut chain: Chain<String> = (0..100).map(|i| i.to_string()).collect();
+        chain.raw_reserve(1000);
+        chain.compact();
+        assert_eq!(chain.stats().chunks(), &[(100, 100)]);
+        assert!(chain.iter().map(|s| s.parse::<i32>().unwrap()).eq(0..100));
+        chain.push("more".to_string());
+        assert_eq!(chain.len(), 101);
+        assert_eq!(chain[100], "more");
+        chain.clear();
+        chain.compact();
+        assert_eq!(chain.stats().chunk_count(), 0);
+        chain.push("again".to_string());
+        assert_eq!(chain.to_vec(), ["again"]);
     }
-}
 
-impl<'a, T: 'a> DoubleEndedIterator for IterMut<'a, T> {
-    fn next_back(&mut self) -> Option<&'a mut T> {
-        self.0.next_back()
+    #[test]
+    fn into_chunks() {
+        let chain: Chain<String> = Chain::with_capacity(2);
+        for i in 0..10 {
+            chain.push(i.to_string());
+        }
+        let mut chunks = chain.into_chunks();
+        let first = chunks.next().unwrap();
+        assert_eq!(&first[..], ["0", "1"]);
+        let rest: Vec<String> = chunks.flat_map(|c| c.into_vec()).collect();
+        assert_eq!(rest.len(), 8);
+        assert_eq!(rest[7], "9");
+
+        let mut partial = Chain::new();
+        partial.extend((0..100).map(|i| vec![i]));
+        partial.clear();
+        partial.push(vec![7]);
+        assert_eq!(partial.into_chunks().count(), 1);
     }
-}
 
-pub struct IntoIter<T> {
-    start: *mut Chunk<T>,
-    end: *mut Chunk<T>,
-    front: *mut T,
-    _ph: PhantomData<T>
-}
+    #[test]
+    fn budget_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = Chain::with_policy(super::super::growth::Fixed(1024));
+        let log = seen.clone();
+        chain.set_budget_hook(4096, move |total| log.lock().unwrap().push(total));
+        for i in 0..10000u32 {
+            chain.push(i);
+        }
+        // Each chunk is just over 4 KiB, so every one crosses a threshold
+        let stats = chain.stats();
+        let chunk = stats.reserved_bytes() / stats.chunk_count();
+        let expect: Vec<usize> = (1..stats.chunk_count() + 1).map(|n| n * chunk).collect();
+        assert_eq!(*seen.lock().unwrap(), expect);
+    }
 
-impl<T> Iterator for IntoIter<T> {
-    type Item = T;
+    #[test]
+    fn reserve_uninit() {
+        let mut chain = Chain::with_capacity(2);
+        chain.push("first".to_string());
+        {
+            let mut res = chain.reserve_uninit(3);
+            assert!(res.capacity() >= 3);
+            res.push("a".to_string()).unwrap();
+            for (i, slot) in res.spare_mut().iter_mut().take(2).enumerate() {
+                *slot = MaybeUninit::new(i.to_string());
+            }
+            unsafe { res.assume_filled(2) };
+            let filled = res.commit();
+            filled[0].push('!');
+            assert_eq!(filled, ["a!", "0", "1"]);
+        }
+        // Dropping without committing keeps what was written
+        chain.reserve_uninit(1).push("b".to_string()).unwrap();
+        assert_eq!(chain.to_vec(), ["first", "a!", "0", "1", "b"]);
+    }
 
-    fn next(&mut self) -> Option<T> {
-        loop {
-            unsafe {
-                let chunk = self.start;
-                let back = (*chunk).items.as_mut_ptr().offset((*chunk).len as isize);
-                if self.front == back {
-                    if self.start == self.end {
-                        return None
-                    }
-                    self.start = (*chunk).next;
-                    heap::deallocate(chunk as *mut u8,
-                                     mem::size_of::<Chunk<T>>() + (*chunk).cap * mem::size_of::<T>(),
-                                     mem::min_align_of::<Chunk<T>>());
-                    self.front = (*self.start).items.as_mut_ptr();
-                    continue;
+    #[test]
+    fn emplace_with() {
+        let chain = Chain::new();
+        let big = unsafe {
+            chain.emplace_with(|slot: *mut [u64; 512]| {
+                for i in 0..512 {
+                    ptr::write((*slot).as_mut_ptr().offset(i as isize), i as u64);
                 }
-                let ptr = self.front;
-                self.front = self.front.offset(1);
+            })
+        };
+        assert_eq!(big[511], 511);
+        assert_eq!(chain.len(), 1);
+    }
 
-                return Some(ptr::read(ptr))
-            }
+    #[test]
+    fn push_str() {
+        let mut chain = Chain::with_capacity(4);
+        let word = chain.push_str("contiguous");
+        assert_eq!(word, "contiguous");
+        fmt::Write::write_fmt(&mut chain, format_args!(" {}+{}={}", 1, 2, 1 + 2)).unwrap();
+        assert_eq!(chain.to_vec(), b"contiguous 1+2=3");
+    }
+
+    #[test]
+    fn cmp_order() {
+        let chain = Chain::with_capacity(2);
+        let refs: Vec<&u32> = (0..20).map(|i| chain.push(i)).collect();
+        let mut shuffled: Vec<&u32> = refs.iter().rev().step_by(3).cloned().collect();
+        shuffled.sort_by(|a, b| chain.cmp_order(a, b));
+        assert_eq!(shuffled, [&1, &4, &7, &10, &13, &16, &19]);
+        assert_eq!(chain.index_of(refs[13]), Some(13));
+        assert_eq!(chain.index_of(&13), None);
+    }
+
+    #[test]
+    fn split_on() {
+        let chain: Chain<u8> = Chain::with_capacity(4);
+        chain.extend_from_slice(b"Host");
+        chain.extend_from_slice(b": x\r");
+        chain.extend_from_slice(b"\n\r\nbody");
+        assert!(chain.chunks().count() > 1);
+        let (head, body) = chain.split_on(b"\r\n\r\n").unwrap();
+        assert_eq!((&head.to_vec()[..], &body.to_vec()[..]), (&b"Host: x"[..], &b"body"[..]));
+        assert_eq!(body.chunks().count(), 1);
+        assert!(chain.split_on(b"\n\n").is_none());
+        let (name, value) = head.split_on(b": ").unwrap();
+        assert!(name == b"Host"[..] && value == b"x"[..]);
+    }
+
+    #[test]
+    fn split_at_byte() {
+        let chain: Chain<u8> = Chain::with_capacity(4);
+        chain.extend_from_slice(b"abcd");
+        chain.extend_from_slice(b"efgh");
+        // At a chunk boundary, within a chunk and at either end
+        for i in 0..9 {
+            let (a, b) = chain.split_at_byte(i).unwrap();
+            assert_eq!((a.len(), b.len()), (i, 8 - i));
+            assert!(a == b"abcdefgh"[..i] && b == b"abcdefgh"[i..]);
         }
+        assert!(chain.split_at_byte(9).is_none());
+        let empty: Chain<u8> = Chain::new();
+        assert!(empty.split_at_byte(0).unwrap().1.is_empty());
     }
-}
 
-unsafe impl <T: Send> Send for IntoIter<T> {}
-unsafe impl <T: Sync> Sync for IntoIter<T> {}
+    #[test]
+    fn head_slice() {
+        let mut chain = Chain::with_capacity(8);
+        assert_eq!(chain.head_slice(0), Some(&[][..]));
+        assert_eq!(chain.head_slice(1), None);
+        chain.write_all(b"\x7fELF and the rest of the file").unwrap();
+        assert_eq!(chain.head_slice(4), Some(&b"\x7fELF"[..]));
+        assert_eq!(chain.head_slice(8).map(|s| s.len()), Some(8));
+        assert_eq!(chain.head_slice(9), None);
+    }
 
-impl<T> DoubleEndedIterator for IntoIter<T> {
-    fn next_back(&mut self) -> Option<T> {
-        loop {
-            unsafe {
-                let chunk = self.end;
-                let back = (*chunk).items.as_mut_ptr().offset((*chunk).len as isize);
-                if back == self.front {
-                    if chunk == self.start {
-                        return None
-                    }
-                    self.end = (*chunk).prev;
-                    heap::deallocate(
-                        chunk as *mut u8,
-                        mem::size_of::<Chunk<T>>() + (*chunk).cap * mem::size_of::<T>(),
-                        mem::min_align_of::<Chunk<T>>());
-                    continue;
-                }
-                (*chunk).len -= 1;
-                let ptr = (*chunk).items.as_mut_ptr().offset((*chunk).len as isize);
-                return Some(ptr::read(ptr))
-            }
+    #[test]
+    fn pop() {
+        let mut chain = Chain::with_capacity(2);
+        assert_eq!(chain.pop(), None);
+        for i in 0..5 {
+            chain.push(i.to_string());
         }
+        assert_eq!(chain.last().map(|s| &s[..]), Some("4"));
+        assert_eq!(chain.pop(), Some("4".to_string()));
+        assert_eq!(chain.pop(), Some("3".to_string()));
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.last().map(|s| &s[..]), Some("2"));
+        let chunks = chain.stats().chunk_count();
+        chain.push("x".to_string());
+        chain.push("y".to_string());
+        assert_eq!(chain.stats().chunk_count(), chunks);
+        assert_eq!(chain.to_vec(), ["0", "1", "2", "x", "y"]);
+        while chain.pop().is_some() {}
+        assert_eq!(chain.len(), 0);
+        assert_eq!(chain.last(), None);
     }
-}
 
-impl<T> Drop for IntoIter<T> {
-    fn drop(&mut self) {
-        while let Some(_) = self.next() {}
-        debug_assert!(self.start == self.end);
-        unsafe {
-            heap::deallocate(self.start as *mut u8,
-                             mem::size_of::<Chunk<T>>() + (*self.start).cap * mem::size_of::<T>(),
-                             mem::min_align_of::<Chunk<T>>());
+    #[test]
+    fn seal_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let sealed = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = Chain::with_capacity(4);
+        let log = sealed.clone();
+        chain.set_seal_hook(move |chunk: &[u32]| log.lock().unwrap().push(chunk.to_vec()));
+        chain.extend_as_slice(0..3);
+        chain.extend_as_slice(3..5);
+        for i in 5..20 {
+            chain.push(i);
         }
+        let sealed = sealed.lock().unwrap();
+        assert_eq!(sealed[0], [0, 1, 2]);
+        assert_eq!(sealed[1], (3..11).collect::<Vec<_>>());
+        assert_eq!(sealed.len(), 2);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[test]
+    fn seal_hook_reuse() {
+        use std::sync::{Arc, Mutex};
+
+        let sealed = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = Chain::with_policy(super::super::growth::Fixed(4));
+        let log = sealed.clone();
+        chain.set_seal_hook(move |chunk: &[u32]| {
+            log.lock().unwrap().push((chunk.as_ptr() as usize, chunk.to_vec()))
+        });
+        for i in 0..5 {
+            chain.push(i);
+        }
+        // Popping back across the boundary unseals the first chunk,
+        // so it is sealed again once refilled
+        assert_eq!(chain.pop(), Some(4));
+        assert_eq!(chain.pop(), Some(3));
+        chain.push(10);
+        chain.push(11);
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(), [0, 1, 2, 10, 11]);
+        let sealed = sealed.lock().unwrap();
+        assert_eq!(sealed.len(), 2);
+        assert_eq!(sealed[0].1, [0, 1, 2, 3]);
+        assert_eq!(sealed[1].1, [0, 1, 2, 10]);
+        assert_eq!(sealed[0].0, sealed[1].0);
+    }
+
+    #[test]
+    fn stats() {
+        let mut chain = Chain::with_policy(super::super::growth::Fixed(4));
+        assert_eq!(chain.stats().chunk_count(), 0);
+        chain.extend_as_slice(0..3u32);
+        chain.extend_as_slice(0..2u32);
+        chain.push(9);
+        let stats = chain.stats();
+        assert_eq!(stats.chunks(), &[(3, 4), (3, 4)]);
+        assert_eq!(stats.wasted(), 1);
+        assert_eq!(stats.reserved_bytes(), chain.reserved_bytes());
+        chain.clear();
+        let stats = chain.stats();
+        assert_eq!(stats.chunks(), &[(0, 4), (0, 4)]);
+        assert_eq!(stats.wasted(), 0);
+    }
+
+    #[test]
+    fn deep_size() {
+        let chain = Chain::with_capacity(4);
+        let base = chain.deep_size();
+        chain.push(String::with_capacity(100));
+        chain.push(String::from("abc"));
+        assert_eq!(chain.deep_size(), base + 100 + 3);
+        let nested: Chain<Vec<Option<Box<u64>>>> = Chain::new();
+        nested.push(vec![Some(Box::new(1)), None]);
+        assert!(nested.deep_size() >= 2 * mem::size_of::<Option<Box<u64>>>() + 8);
+    }
+
+    #[test]
+    fn cursor() {
+        let mut chain = Chain::with_capacity(2);
+        let start = chain.position();
+        let mut marks = Vec::new();
+        for i in 0..50 {
+            if i % 10 == 0 {
+                marks.push(chain.position());
+            }
+            chain.push(i);
+        }
+        for (n, &mark) in marks.iter().enumerate() {
+            assert!(chain.cursor_at(mark).unwrap().eq(&chain.iter().cloned().collect::<Vec<_>>()[n * 10..]));
+        }
+        let mut cursor = chain.cursor_at(marks[2]).unwrap();
+        assert_eq!(cursor.next(), Some(&20));
+        let resume = cursor.position();
+        for elem in chain.cursor_mut_at(resume).unwrap().take(5) {
+            *elem = 0;
+        }
+        assert!(chain.cursor_at(start).unwrap().skip(20).take(7).eq(&[20, 0, 0, 0, 0, 0, 26]));
+
+        let end = chain.position();
+        chain.push(50);
+        assert_eq!(chain.cursor_at(end).unwrap().collect::<Vec<_>>(), [&50]);
+
+        chain.retain(|_| true);
+        assert!(chain.cursor_at(end).is_none());
+        assert!(Chain::<u32>::new().cursor_at(chain.position()).is_none());
+        assert_eq!(chain.cursor().count(), 51);
+    }
+
+    #[test]
+    fn extend_from_slice() {
+        let chain = Chain::with_capacity(4);
+        let words = ["alpha", "beta", "gamma"];
+        let copy = chain.extend_from_slice(&words);
+        assert_eq!(copy, words);
+        assert!(chain.extend_from_slice(&[]).is_empty());
+        assert_eq!(chain.extend_from_slice(&words[1..]), ["beta", "gamma"]);
+        assert_eq!(chain.len(), 5);
+    }
+
+    #[test]
+    fn retain() {
+        let mut chain = Chain::with_capacity(4);
+        for i in 0..100 {
+            chain.push(i);
+        }
+        chain.retain(|&i| i % 3 == 0);
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(),
+                   (0..100).filter(|i| i % 3 == 0).collect::<Vec<_>>());
+        chain.push(100);
+        assert_eq!(chain.len(), 35);
+        chain.retain(|_| false);
+        assert_eq!(chain.len(), 0);
+        chain.push(1);
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(), vec![1]);
+    }
 
     #[test]
     fn drop_type() {
@@ -531,4 +3659,133 @@ mod test {
 
         assert_eq!(unsafe { COUNT }, 0);
     }
+
+    #[test]
+    fn resize() {
+        let chain = Chain::with_capacity(4);
+        chain.push(1u8);
+        chain.resize(10, 0);
+        assert_eq!(chain.len(), 10);
+        // Spare room in the first chunk, then one chunk for the rest
+        assert_eq!(chain.chunks().map(|c| c.len()).collect::<Vec<_>>(), [4, 6]);
+        chain.resize(5, 7);
+        assert_eq!(chain.len(), 10);
+
+        let mut n = 0;
+        chain.resize_with(13, || { n += 1; n });
+        assert_eq!(chain.to_vec(), [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn put_bytes() {
+        let chain = Chain::new();
+        assert_eq!(chain.put_u8(1), [1]);
+        assert_eq!(chain.put_u16_be(0x0203), [2, 3]);
+        assert_eq!(chain.put_u32_le(0x07060504), [4, 5, 6, 7]);
+        chain.put_i64_be(-2);
+        chain.put_f64_le(1.5);
+        assert_eq!(chain.len(), 23);
+        assert_eq!(&chain.to_vec()[7..15], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+        assert_eq!(chain.to_vec()[15..], 1.5f64.to_le_bytes());
+    }
+
+    #[test]
+    fn varint() {
+        let chain = Chain::new();
+        assert_eq!(chain.put_uvarint(1), [0x01]);
+        assert_eq!(chain.put_uvarint(300), [0xac, 0x02]);
+        assert_eq!(chain.put_uvarint(u64::MAX).len(), 10);
+        assert_eq!(chain.put_ivarint(-1), [0x01]);
+        assert_eq!(chain.put_ivarint(1), [0x02]);
+        assert_eq!(chain.put_ivarint(i64::MIN), chain.put_uvarint(u64::MAX));
+        // Only the encoded bytes are kept
+        assert_eq!(chain.len(), 1 + 2 + 10 + 1 + 1 + 10 + 10);
+    }
+
+    #[cfg(feature = "drop-timing")]
+    #[test]
+    fn drop_timing() {
+        use std::sync::{Arc, Mutex};
+
+        let timing = Arc::new(Mutex::new(None));
+        let mut chain = Chain::new();
+        let seen = timing.clone();
+        chain.set_drop_timing_hook(move |t| *seen.lock().unwrap() = Some(t));
+        for i in 0..1000 {
+            chain.push(i.to_string());
+        }
+        drop(chain);
+        let timing = timing.lock().unwrap().unwrap();
+        assert!(timing.drops() > Duration::from_secs(0));
+        assert_eq!(timing.total(), timing.drops() + timing.frees());
+    }
+
+    #[test]
+    fn extend_from_reader() {
+        let data: Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+        let chain = Chain::with_capacity(100);
+        assert_eq!(chain.extend_from_reader(&data[..], None).unwrap(), 10000);
+        assert_eq!(chain.to_vec(), data);
+        assert!(chain.stats().chunk_count() > 1);
+
+        let chain = Chain::new();
+        assert_eq!(chain.extend_from_reader(&data[..], Some(10)).unwrap(), 10);
+        assert_eq!(chain.to_vec(), &data[..10]);
+    }
+
+    #[test]
+    fn preallocate() {
+        let chain = Chain::new();
+        chain.preallocate_chunks(3, 16);
+        let reserved = chain.stats().reserved_bytes();
+        for i in 0..48u32 {
+            chain.push(i);
+        }
+        assert_eq!(chain.stats().reserved_bytes(), reserved);
+        assert_eq!(chain.stats().chunk_count(), 3);
+
+        chain.reserve_exact(100);
+        let reserved = chain.stats().reserved_bytes();
+        chain.extend_as_slice(0..100);
+        assert_eq!(chain.stats().reserved_bytes(), reserved);
+        assert_eq!(chain.stats().wasted(), 0);
+        assert_eq!(chain.len(), 148);
+    }
+
+    #[test]
+    fn pairs() {
+        let chain = Chain::with_capacity(2);
+        assert_eq!(chain.pairs().count(), 0);
+        chain.push(1);
+        assert_eq!(chain.pairs().count(), 0);
+        for i in 2..10 {
+            chain.push(i * i);
+        }
+        assert!(chain.stats().chunk_count() > 2);
+        let deltas: Vec<i32> = chain.pairs().map(|(a, b)| b - a).collect();
+        assert_eq!(deltas, [3, 5, 7, 9, 11, 13, 15, 17]);
+    }
+
+    #[test]
+    fn spare_capacity() {
+        let chain = Chain::new();
+        assert_eq!(chain.total_spare_capacity(), 0);
+        chain.preallocate_chunks(2, 8);
+        chain.push(1u8);
+        assert_eq!(chain.remaining_in_current_chunk(), 7);
+        assert_eq!(chain.total_spare_capacity(), 15);
+    }
+
+    #[test]
+    fn will_grow() {
+        let chain = Chain::new();
+        assert!(chain.will_grow(1));
+        assert!(!chain.will_grow(0));
+        chain.reserve_exact(4);
+        assert!(!chain.will_grow(4));
+        assert!(chain.will_grow(5));
+        chain.extend_from_slice(&[1u8, 2, 3]);
+        assert!(!chain.will_grow(1));
+        assert!(chain.will_grow(2));
+    }
 }
@@ -1,10 +1,12 @@
-use super::chain::{Chain, Chunks};
-use std::marker::{self, PhantomData};
+use super::chain::{AllocError, Chain, ChainStats, Chunks};
+#[cfg(feature = "nightly")]
+use std::marker;
+use std::marker::PhantomData;
 use std::fmt;
-use std::ops;
+use std::ops::{self, ControlFlow};
 use std::mem;
 use std::ptr;
-use std::intrinsics;
+use std::slice;
 use std::cell::Cell;
 
 const SENTINEL: usize = !0;
@@ -13,9 +15,67 @@ pub trait Erase<T: ?Sized, E: ?Sized> {
     fn erase(real: &T) -> &E;
 }
 
-// Erasure strategy: coerce to unsized
+// Mutable form of `Erase`, used by `DynChain::iter_mut` and `drain`
+pub trait EraseMut<T: ?Sized, E: ?Sized>: Erase<T, E> {
+    fn erase_mut(real: &mut T) -> &mut E;
+}
+
+// Says whether a chain using an erasure strategy gives mutable
+// access to its elements.  With `Access = Exclusive`, only types the
+// strategy implements `EraseMut` for can be pushed, which is what
+// makes `iter_mut` and `drain` available.  `Shared` strategies only
+// need `Erase`.
+pub trait Strategy {
+    type Access;
+}
+
+pub enum Shared {}
+pub enum Exclusive {}
+
+// Mutable erasure recorded for each element pushed with strategy `S`,
+// chosen by the strategy's `Access`
+pub trait Access<T, E: ?Sized, S> {
+    fn erase_mut() -> Option<unsafe fn(*mut u8, usize) -> *mut E>;
+}
+
+impl<T, E: ?Sized, S: Erase<T, E>> Access<T, E, S> for Shared {
+    fn erase_mut() -> Option<unsafe fn(*mut u8, usize) -> *mut E> {
+        None
+    }
+}
+
+impl<T, E: ?Sized, S: EraseMut<T, E>> Access<T, E, S> for Exclusive {
+    fn erase_mut() -> Option<unsafe fn(*mut u8, usize) -> *mut E> {
+        unsafe fn erase_mut<T, EI: ?Sized, SI>(it: *mut u8, _: usize) -> *mut EI
+                where SI: EraseMut<T, EI> {
+            SI::erase_mut(&mut *(it as *mut T)) as *mut EI
+        }
+
+        Some(erase_mut::<T, E, S>)
+    }
+}
+
+// Strategies that can store a T in a chain of E
+pub trait Store<T, E: ?Sized>: Erase<T, E> {
+    fn erase_mut() -> Option<unsafe fn(*mut u8, usize) -> *mut E>;
+}
+
+impl<T, E: ?Sized, S> Store<T, E> for S
+        where S: Erase<T, E> + Strategy, S::Access: Access<T, E, S> {
+    fn erase_mut() -> Option<unsafe fn(*mut u8, usize) -> *mut E> {
+        S::Access::erase_mut()
+    }
+}
+
+// Erasure strategy: coerce to unsized.  Any coercion is allowed with
+// the `nightly` feature; on stable only a fixed set of targets is.
 pub struct Unsize(());
 
+impl Strategy for Unsize {
+    type Access = Exclusive;
+}
+
+#[cfg(feature = "nightly")]
 impl<T: ?Sized, E: ?Sized> Erase<T, E> for Unsize
         where T: marker::Unsize<E> {
     fn erase(real: &T) -> &E {
@@ -23,9 +83,104 @@ impl<T: ?Sized, E: ?Sized> Erase<T, E> for Unsize
     }
 }
 
+#[cfg(feature = "nightly")]
+impl<T: ?Sized, E: ?Sized> EraseMut<T, E> for Unsize
+        where T: marker::Unsize<E> {
+    fn erase_mut(real: &mut T) -> &mut E {
+        real
+    }
+}
+
+#[cfg(not(feature = "nightly"))]
+macro_rules! unsize_to {
+    ($($tr:path),*) => {$(
+        impl<T: $tr + 'static> Erase<T, dyn $tr> for Unsize {
+            fn erase(real: &T) -> &(dyn $tr + 'static) {
+                real
+            }
+        }
+
+        impl<T: $tr + 'static> EraseMut<T, dyn $tr> for Unsize {
+            fn erase_mut(real: &mut T) -> &mut (dyn $tr + 'static) {
+                real
+            }
+        }
+
+        impl<T: $tr + Send + 'static> Erase<T, dyn $tr + Send> for Unsize {
+            fn erase(real: &T) -> &(dyn $tr + Send + 'static) {
+                real
+            }
+        }
+
+        impl<T: $tr + Send + 'static> EraseMut<T, dyn $tr + Send> for Unsize {
+            fn erase_mut(real: &mut T) -> &mut (dyn $tr + Send + 'static) {
+                real
+            }
+        }
+    )*}
+}
+
+#[cfg(not(feature = "nightly"))]
+unsize_to!(fmt::Debug, fmt::Display, ::std::any::Any);
+
+#[cfg(not(feature = "nightly"))]
+impl<T, const N: usize> Erase<[T; N], [T]> for Unsize {
+    fn erase(real: &[T; N]) -> &[T] {
+        real
+    }
+}
+
+#[cfg(not(feature = "nightly"))]
+impl<T, const N: usize> EraseMut<[T; N], [T]> for Unsize {
+    fn erase_mut(real: &mut [T; N]) -> &mut [T] {
+        real
+    }
+}
+
+// Unsized types that can be stored in a DynChain by copying their
+// contents with `push_unsized_copy`.  Implemented for slices of Copy
+// types and str.
+//
+// Unsafe because `from_elems` must rebuild a valid value from the
+// contents of one returned by `as_elems`.
+pub unsafe trait UnsizedCopy {
+    type Elem: Copy;
+
+    fn as_elems(&self) -> &[Self::Elem];
+    unsafe fn from_elems(ptr: *mut Self::Elem, len: usize) -> *mut Self;
+}
+
+unsafe impl<T: Copy> UnsizedCopy for [T] {
+    type Elem = T;
+
+    fn as_elems(&self) -> &[T] {
+        self
+    }
+
+    unsafe fn from_elems(ptr: *mut T, len: usize) -> *mut [T] {
+        ptr::slice_from_raw_parts_mut(ptr, len)
+    }
+}
+
+unsafe impl UnsizedCopy for str {
+    type Elem = u8;
+
+    fn as_elems(&self) -> &[u8] {
+        self.as_bytes()
+    }
+
+    unsafe fn from_elems(ptr: *mut u8, len: usize) -> *mut str {
+        ptr::slice_from_raw_parts_mut(ptr, len) as *mut str
+    }
+}
+
 // Erasure strategy: deref
 pub struct Deref(());
 
+impl Strategy for Deref {
+    type Access = Shared;
+}
+
 impl<T: ?Sized, E: ?Sized> Erase<T, E> for Deref
         where T: ops::Deref<Target=E> {
     fn erase(real: &T) -> &E {
@@ -33,19 +188,89 @@ impl<T: ?Sized, E: ?Sized> Erase<T, E> for Deref
     }
 }
 
+// Describes the concrete type stored in an element, for code
+// that inspects elements as raw bytes.  The type name is only
+// recorded with the `type-info` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeInfo {
+    size: usize,
+    align: usize,
+    #[cfg(feature = "type-info")]
+    name: &'static str
+}
+
+impl TypeInfo {
+    fn of<T>() -> Self {
+        TypeInfo {
+            size: mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
+            #[cfg(feature = "type-info")]
+            name: ::std::any::type_name::<T>()
+        }
+    }
+
+    // Type of a copied slice of `len` T's, stored as E
+    #[cfg_attr(not(feature = "type-info"), allow(clippy::extra_unused_type_parameters))]
+    fn of_slice<E: ?Sized, T>(len: usize) -> Self {
+        TypeInfo {
+            size: mem::size_of::<T>() * len,
+            align: mem::align_of::<T>(),
+            #[cfg(feature = "type-info")]
+            name: ::std::any::type_name::<E>()
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    #[cfg(feature = "type-info")]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
 struct Forward<E: ?Sized> {
     // Pointer to object
     obj: *mut u8,
+    // Pointer to the element's metadata
+    meta: *mut u8,
     // Pointer past end of object
     end: *mut u8,
+    // Element count for a copied slice, otherwise unused
+    len: usize,
+    // Type of object
+    info: TypeInfo,
     // Convert to erased type
-    erase: unsafe fn(*mut u8) -> *const E,
+    erase: unsafe fn(*mut u8, usize) -> *const E,
+    // Mutable form of `erase`, if the strategy has one
+    erase_mut: Option<unsafe fn(*mut u8, usize) -> *mut E>,
     // Drop glue
     drop: unsafe fn(*mut u8),
     // Backward function
     backward: BackwardFn<E>
 }
 
+impl<E: ?Sized> Forward<E> {
+    #[inline]
+    unsafe fn elem(&self) -> *const E {
+        (self.erase)(self.obj, self.len)
+    }
+
+    #[inline]
+    unsafe fn elem_mut(&self) -> *mut E {
+        // Only reached for `Exclusive` strategies, which record one
+        match self.erase_mut {
+            Some(erase_mut) => erase_mut(self.obj, self.len),
+            None => unreachable!()
+        }
+    }
+}
+
 // Function that returns a Forward structure
 // One of these is stored prior to each object
 // in the vector.
@@ -63,43 +288,74 @@ struct FencePost<E: ?Sized> {
     _ph: PhantomData<E>
 }
 
+// The first element has no predecessor, so its backward
+// function is encoded as 0
+fn backward_word<E: ?Sized>(b: Option<BackwardFn<E>>) -> usize {
+    match b {
+        Some(b) => b as usize,
+        None => 0
+    }
+}
+
 impl<E: ?Sized> FencePost<E> {
-    fn new(f: ForwardFn<E>, b: BackwardFn<E>) -> Self {
+    fn new(f: ForwardFn<E>, b: Option<BackwardFn<E>>) -> Self {
         FencePost {
-            word: f as usize ^ b as usize,
+            word: f as usize ^ backward_word(b),
             _ph: PhantomData
         }
     }
     
-    unsafe fn forward(&self, b: BackwardFn<E>) -> ForwardFn<E> {
-        mem::transmute(self.word ^ b as usize)
+    unsafe fn forward(&self, b: Option<BackwardFn<E>>) -> ForwardFn<E> {
+        mem::transmute(self.word ^ backward_word(b))
     }
     
-    unsafe fn backward(&self, f: ForwardFn<E>) -> BackwardFn<E> {
+    unsafe fn backward(&self, f: ForwardFn<E>) -> Option<BackwardFn<E>> {
         mem::transmute(self.word ^ f as usize)
     }
 }
 
-pub struct DynChain<'gt, E: ?Sized, S=Unsize> {
+// Order in which a DynChain drops its elements.  The default is
+// insertion order, matching Vec; `Reverse` drops the most recently
+// pushed element first, for elements (guards, finalizers) that may
+// depend on those pushed before them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropOrder {
+    Insertion,
+    Reverse
+}
+
+// Each element is stored after a fence post, followed by a
+// metadata value of type M (nothing by default):
+//
+//     [fence word] [sentinel words] [M] [padding] [object]
+//
+// The metadata is placed as close to the object as alignment
+// allows, so it can be found from either end.
+pub struct DynChain<'gt, E: ?Sized, S=Unsize, M=()> {
     // The actual backing vector
     vec: Chain<u8>,
     // Most recent backward function
-    backward: Cell<BackwardFn<E>>,
-    // Indicate we contain E, ignore S,
+    backward: Cell<Option<BackwardFn<E>>>,
+    // Number of elements
+    len: Cell<usize>,
+    // Order to drop elements in
+    order: DropOrder,
+    // Indicate we contain E and M, ignore S,
     // and that 'gt must strictly outlive us
-    _ph: PhantomData<(E, *const S, *mut &'gt ())>
+    _ph: PhantomData<(*const S, M, *mut &'gt (), *const E)>
 }
 
-unsafe impl<'gt, E: ?Sized + Send, S> Send for DynChain<'gt, E, S> {}
+unsafe impl<'gt, E: ?Sized + Send, S, M: Send> Send for DynChain<'gt, E, S, M> {}
 
 // Some utility methods for raw pointer
+#[allow(clippy::wrong_self_convention)]
 trait PtrUtil: Sized {
     unsafe fn align(self, a: usize) -> Self;
     fn as_u8_ptr(self) -> *mut u8;
 
     #[inline]
     unsafe fn align_for<T>(self) -> Self {
-        self.align(mem::min_align_of::<T>())
+        self.align(mem::align_of::<T>())
     }
 
     #[inline]
@@ -132,63 +388,124 @@ impl<T> PtrUtil for *mut T {
     }
 }
 
-impl<'gt, E: ?Sized, S=Unsize> DynChain<'gt, E, S> {
-    pub fn new() -> Self {
-        Self::with_capacity(128)
+impl<'gt, E: ?Sized, S, M> DynChain<'gt, E, S, M> {
+    // Creates an empty chain without allocating.  Elements
+    // are dropped in insertion order.
+    pub const fn new() -> Self {
+        Self::with_drop_order(DropOrder::Insertion)
+    }
+
+    pub const fn with_drop_order(order: DropOrder) -> Self {
+        DynChain {
+            vec: Chain::new(),
+            backward: Cell::new(None),
+            len: Cell::new(0),
+            order: order,
+            _ph: PhantomData
+        }
     }
 
     pub fn with_capacity(cap: usize) -> Self {
         DynChain {
             vec: Chain::with_capacity(cap),
-            backward: Cell::new(unsafe { mem::transmute(0usize) }),
+            backward: Cell::new(None),
+            len: Cell::new(0),
+            order: DropOrder::Insertion,
             _ph: PhantomData
         }
     }
 
+    pub fn drop_order(&self) -> DropOrder {
+        self.order
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    // Describes the chunks backing the chain; lengths and
+    // capacities are in bytes rather than elements
+    pub fn stats(&self) -> ChainStats {
+        self.vec.stats()
+    }
+
     // Returns worst case space required to store something
     // in the vec with appropriate alignment.  This could be
     // improved to take the actual alignment of the vector
     // chunk offset into account.
     #[inline]
     fn space_for<T>() -> usize {
-        mem::size_of::<T>() + mem::min_align_of::<T>() - 1
+        mem::size_of::<T>() + mem::align_of::<T>() - 1
     }
 
     // Forward function for T
-    unsafe fn forward<T>(fence: *mut FencePost<E>) -> Forward<E> where S: Erase<T, E> {
+    unsafe fn forward<T>(fence: *mut FencePost<E>) -> Forward<E> where S: Store<T, E> {
         unsafe fn drop<T>(it: *mut u8) {
-            intrinsics::drop_in_place(it as *mut T);
+            ptr::drop_in_place(it as *mut T);
         }
 
-        unsafe fn erase<'a, T:'a, EI: ?Sized, SI>(it: *mut u8) -> *const EI
+        unsafe fn erase<'a, T:'a, EI: ?Sized, SI>(it: *mut u8, _: usize) -> *const EI
                 where SI: Erase<T, EI> {
             SI::erase(&*(it as *mut T)) as *const EI
         }
 
-        let obj = fence.offset(1).align_for::<T>() as *mut u8;
+        let obj = Self::obj_after::<T>(fence);
         let end = obj.offset(mem::size_of::<T>() as isize);
 
         Forward {
             obj: obj,
+            meta: Self::meta_before(obj),
             end: end,
+            len: 0,
+            info: TypeInfo::of::<T>(),
             drop: drop::<T>,
             erase: erase::<T, E, S>,
+            erase_mut: S::erase_mut(),
             backward: Self::backward::<T>
         }
     }
     
+    // Forward function for an entry laid out for T but holding no
+    // object, left by an `emplace` whose closure pushed to the chain.
+    // Walks skip it, since its object is null.
+    unsafe fn forward_stub<T>(fence: *mut FencePost<E>) -> Forward<E> where S: Store<T, E> {
+        unsafe fn nop(_: *mut u8) {}
+
+        Forward {
+            obj: ptr::null_mut(),
+            drop: nop,
+            backward: Self::backward_stub::<T>,
+            ..Self::forward::<T>(fence)
+        }
+    }
+
+    // Position of the object following `fence`
+    unsafe fn obj_after<T>(fence: *mut FencePost<E>) -> *mut u8 {
+        let meta = fence.offset(1).align_for::<M>() as *mut u8;
+        meta.offset(mem::size_of::<M>() as isize).align_for::<T>()
+    }
+
+    // Position of the metadata preceding `obj`
+    fn meta_before(obj: *mut u8) -> *mut u8 {
+        let meta = obj as usize - mem::size_of::<M>();
+        (meta & !(mem::align_of::<M>() - 1)) as *mut u8
+    }
+
     // Backward function for T
-    unsafe fn backward<T>(end: *mut u8) -> Backward<E> where S: Erase<T, E> {
-        let mut ptr = end.offset(-(mem::size_of::<T>() as isize));
-        
-        if mem::min_align_of::<T>() <= mem::min_align_of::<FencePost<E>>() {
-            ptr = (ptr as usize & !(mem::min_align_of::<FencePost<E>>() - 1)) as *mut u8;
-            ptr = ptr.offset(-(mem::size_of::<FencePost<E>>() as isize));
-        } else {
-            ptr = ptr.offset(-(mem::size_of::<FencePost<E>>() as isize));
-            while *(ptr as *mut usize) ^ Self::forward::<T> as usize == SENTINEL {
-                ptr = ptr.offset(-(mem::min_align_of::<usize>() as isize))
-            }
+    unsafe fn backward<T>(end: *mut u8) -> Backward<E> where S: Store<T, E> {
+        let obj = end.offset(-(mem::size_of::<T>() as isize));
+        let meta = Self::meta_before(obj);
+        // Skip back over the sentinel words to the fence
+        let mut ptr = (meta as usize & !(mem::align_of::<usize>() - 1)) as *mut u8;
+        ptr = ptr.offset(-(mem::size_of::<FencePost<E>>() as isize));
+        while *(ptr as *mut usize) ^ Self::forward::<T> as *const () as usize == SENTINEL {
+            ptr = ptr.offset(-(mem::align_of::<usize>() as isize))
         }
 
         Backward {
@@ -197,99 +514,483 @@ impl<'gt, E: ?Sized, S=Unsize> DynChain<'gt, E, S> {
         }
     }
 
-    unsafe fn alloc<T>(&self) -> *mut T where S: Erase<T, E> {
-        let size = Self::space_for::<FencePost<E>>() + Self::space_for::<T>();
-        let (space, _) = self.vec.reserve(size);
+    // Backward function for a stub entry.  Its sentinel words were
+    // written for T, so the walk back to the fence is the same.
+    unsafe fn backward_stub<T>(end: *mut u8) -> Backward<E> where S: Store<T, E> {
+        Backward {
+            forward: Self::forward_stub::<T>,
+            ..Self::backward::<T>(end)
+        }
+    }
+
+    unsafe fn alloc<T>(&self, meta: M) -> Result<*mut T, AllocError> where S: Store<T, E> {
+        let size = Self::space_for::<FencePost<E>>() + Self::space_for::<M>() + Self::space_for::<T>();
+        let (space, _) = self.vec.raw_try_reserve(size)?;
         let fence = space.align_for::<FencePost<E>>() as *mut FencePost<E>;
-        let obj = fence.offset(1).align_for::<T>() as *mut T;
-        self.vec.add_len(obj.offset(1).diff(space) as usize);
+        let obj = Self::obj_after::<T>(fence);
+        let meta_ptr = Self::meta_before(obj);
+        self.vec.raw_add_len(obj.offset(mem::size_of::<T>() as isize).diff(space) as usize);
         // Fill padding with sentinel value
         let mut sentinel = fence.offset(1) as *mut usize;
-        while sentinel != obj as *mut usize {
-            *sentinel = Self::forward::<T> as usize ^ SENTINEL;
-            sentinel = sentinel.offset(mem::size_of::<usize>() as isize);
+        let sentinel_end = (meta_ptr as usize & !(mem::align_of::<usize>() - 1)) as *mut usize;
+        while sentinel != sentinel_end {
+            *sentinel = Self::forward::<T> as *const () as usize ^ SENTINEL;
+            sentinel = sentinel.offset(1);
         }
+        ptr::write(meta_ptr as *mut M, meta);
         *fence = FencePost::new(Self::forward::<T>, self.backward.get());
-        obj
+        Ok(obj as *mut T)
+    }
+
+    pub fn push<T:'gt>(&self, elem: T) -> &T where S: Store<T, E>, M: Copy + Default {
+        self.push_meta(M::default(), elem)
     }
 
-    pub fn push<T:'gt>(&self, elem: T) -> &T where S: Erase<T, E> {
+    // Pushes `elem` along with a metadata value, which iteration
+    // with `iter_meta` returns alongside it
+    pub fn push_meta<T:'gt>(&self, meta: M, elem: T) -> &T where S: Store<T, E>, M: Copy {
+        match self.try_push_meta(meta, elem) {
+            Ok(obj) => obj,
+            Err(_) => panic!("DynChain: failed to allocate chunk!")
+        }
+    }
+
+    // Like `push`, but hands the element back if space for
+    // it can't be allocated
+    pub fn try_push<T:'gt>(&self, elem: T) -> Result<&T, T>
+            where S: Store<T, E>, M: Copy + Default {
+        self.try_push_meta(M::default(), elem)
+    }
+
+    pub fn try_push_meta<T:'gt>(&self, meta: M, elem: T) -> Result<&T, T>
+            where S: Store<T, E>, M: Copy {
         unsafe {
-            let obj = self.alloc::<T>();
+            let obj = match self.link::<T>(meta) {
+                Ok(obj) => obj,
+                Err(_) => return Err(elem)
+            };
             ptr::write(obj, elem);
-            self.backward.set(Self::backward::<T>);
+            Ok(&*obj)
+        }
+    }
+
+    // Claims a slot for a `T` and links it in as the last element
+    // without writing it, so `SyncDynChain` can write it after
+    // letting other pushers in.  Unsafe because the slot must be
+    // written before anything reads or drops the chain.
+    pub(crate) unsafe fn link<T:'gt>(&self, meta: M) -> Result<*mut T, AllocError>
+            where S: Store<T, E> {
+        let obj = self.alloc::<T>(meta)?;
+        self.backward.set(Some(Self::backward::<T>));
+        self.len.set(self.len.get() + 1);
+        Ok(obj)
+    }
+
+    // Pushes the value returned by `f`, which is written straight
+    // into a slot claimed before `f` runs rather than moved there.
+    // The slot is entered as a stub until then, so `f` may iterate
+    // the chain or push to it.  If it pushes, the value is moved to
+    // go after whatever it pushed, and the slot stays behind as an
+    // empty stub.
+    pub fn emplace<T:'gt, F>(&self, f: F) -> &T
+            where F: FnOnce() -> T, S: Store<T, E>, M: Copy + Default {
+        // Gives the slot back if `f` unwinds without having pushed
+        struct Release<'a, 'gt: 'a, E: ?Sized + 'a, S: 'a, M: 'a> {
+            chain: &'a DynChain<'gt, E, S, M>,
+            end: *mut u8,
+            len: usize,
+            last: Option<BackwardFn<E>>
+        }
+
+        impl<'a, 'gt, E: ?Sized, S, M> Drop for Release<'a, 'gt, E, S, M> {
+            fn drop(&mut self) {
+                if self.chain.vec.spare().0 == self.end {
+                    unsafe {
+                        self.chain.vec.raw_shrink_len(self.end.offset(-(self.len as isize)),
+                                                      self.len, 0);
+                    }
+                    self.chain.backward.set(self.last);
+                }
+            }
+        }
+
+        unsafe {
+            let last = self.backward.get();
+            let before = self.vec.len();
+            let obj = match self.alloc::<T>(M::default()) {
+                Ok(obj) => obj,
+                Err(_) => panic!("DynChain: failed to allocate chunk!")
+            };
+            let end = obj.offset(1) as *mut u8;
+            let fence = Self::backward::<T>(end).fence;
+            *fence = FencePost::new(Self::forward_stub::<T>, last);
+            self.backward.set(Some(Self::backward_stub::<T>));
+            let release = Release {
+                chain: self,
+                end: end,
+                len: self.vec.len() - before,
+                last: last
+            };
+            ptr::write(obj, f());
+            mem::forget(release);
+            if self.vec.spare().0 != end {
+                return self.push(ptr::read(obj))
+            }
+            *fence = FencePost::new(Self::forward::<T>, last);
+            self.backward.set(Some(Self::backward::<T>));
+            self.len.set(self.len.get() + 1);
             &*obj
         }
     }
+
+    // Constructs an element in place by calling `init` with a
+    // pointer to its slot.  If `init` panics, the slot is released.
+    //
+    // Unsafe because `init` must fully initialize the slot, and must
+    // not push to this chain.
+    pub unsafe fn emplace_with<T:'gt, F>(&self, init: F) -> &T
+            where F: FnOnce(*mut T), S: Store<T, E>, M: Copy + Default {
+        // Gives the slot back if construction unwinds
+        struct Release<'a> {
+            vec: &'a Chain<u8>,
+            end: *mut u8,
+            len: usize
+        }
+
+        impl<'a> Drop for Release<'a> {
+            fn drop(&mut self) {
+                unsafe {
+                    self.vec.raw_shrink_len(self.end.offset(-(self.len as isize)), self.len, 0);
+                }
+            }
+        }
+
+        let before = self.vec.len();
+        let obj = match self.alloc::<T>(M::default()) {
+            Ok(obj) => obj,
+            Err(_) => panic!("DynChain: failed to allocate chunk!")
+        };
+        let release = Release {
+            vec: &self.vec,
+            end: obj.offset(1) as *mut u8,
+            len: self.vec.len() - before
+        };
+        init(obj);
+        mem::forget(release);
+        self.backward.set(Some(Self::backward::<T>));
+        self.len.set(self.len.get() + 1);
+        &*obj
+    }
+}
+
+// Copied unsized values record their length after the fence, and
+// the size of the whole entry at its end so it can be found
+// walking backward:
+//
+//     [fence word] [length] [M] [padding] [contents] [padding] [size]
+impl<'gt, E: ?Sized + UnsizedCopy, S, M> DynChain<'gt, E, S, M> {
+    // Position of the contents following `fence` and its length word
+    unsafe fn contents_after(fence: *mut FencePost<E>) -> *mut u8 {
+        Self::obj_after::<E::Elem>((fence as *mut usize).offset(1) as *mut FencePost<E>)
+    }
+
+    unsafe fn forward_unsized(fence: *mut FencePost<E>) -> Forward<E> {
+        unsafe fn nop(_: *mut u8) {}
+
+        unsafe fn erase<EI: ?Sized + UnsizedCopy>(it: *mut u8, len: usize) -> *const EI {
+            EI::from_elems(it as *mut EI::Elem, len)
+        }
+
+        unsafe fn erase_mut<EI: ?Sized + UnsizedCopy>(it: *mut u8, len: usize) -> *mut EI {
+            EI::from_elems(it as *mut EI::Elem, len)
+        }
+
+        let len = *(fence as *mut usize).offset(1);
+        let obj = Self::contents_after(fence);
+        let size = obj.offset((mem::size_of::<E::Elem>() * len) as isize).align_for::<usize>();
+
+        Forward {
+            obj: obj,
+            meta: Self::meta_before(obj),
+            end: size.offset(mem::size_of::<usize>() as isize),
+            len: len,
+            info: TypeInfo::of_slice::<E, E::Elem>(len),
+            drop: nop,
+            erase: erase::<E>,
+            erase_mut: Some(erase_mut::<E>),
+            backward: Self::backward_unsized
+        }
+    }
+
+    unsafe fn backward_unsized(end: *mut u8) -> Backward<E> {
+        let size = *(end as *mut usize).offset(-1);
+        Backward {
+            forward: Self::forward_unsized,
+            fence: end.offset(-(size as isize)) as *mut FencePost<E>
+        }
+    }
+
+    // Copies the contents of `val` into the chain, for values whose
+    // size is only known at runtime
+    pub fn push_unsized_copy(&self, val: &E) -> &E where M: Copy + Default {
+        let elems = val.as_elems();
+        let bytes = mem::size_of::<E::Elem>().checked_mul(elems.len()).unwrap();
+        let size = Self::space_for::<FencePost<E>>() + mem::size_of::<usize>() +
+            Self::space_for::<M>() + bytes + mem::align_of::<E::Elem>() - 1 +
+            Self::space_for::<usize>();
+        unsafe {
+            let (space, _) = self.vec.raw_reserve(size);
+            let fence = space.align_for::<FencePost<E>>() as *mut FencePost<E>;
+            *(fence as *mut usize).offset(1) = elems.len();
+            let obj = Self::contents_after(fence);
+            ptr::write(Self::meta_before(obj) as *mut M, M::default());
+            ptr::copy_nonoverlapping(elems.as_ptr(), obj as *mut E::Elem, elems.len());
+            let size_ptr = obj.offset(bytes as isize).align_for::<usize>() as *mut usize;
+            let end = size_ptr.offset(1) as *mut u8;
+            *size_ptr = end.diff(fence) as usize;
+            *fence = FencePost::new(Self::forward_unsized, self.backward.get());
+            self.vec.raw_add_len(end.diff(space) as usize);
+            self.backward.set(Some(Self::backward_unsized));
+            self.len.set(self.len.get() + 1);
+            &*E::from_elems(obj as *mut E::Elem, elems.len())
+        }
+    }
 }
 
-impl<'gt, 'a, E: ?Sized, S> IntoIterator for &'a DynChain<'gt, E, S> {
+impl<'gt, 'a, E: ?Sized, S, M> IntoIterator for &'a DynChain<'gt, E, S, M> {
     type Item = &'a E;
     type IntoIter = Items<'a, E>;
 
     fn into_iter(self) -> Self::IntoIter {
-        Items {
-            chunks: self.vec.chunks(),
-            cur: ptr::null_mut(),
-            end: ptr::null_mut(),
-            back_cur: ptr::null_mut(),
-            back_start: ptr::null_mut(),
-            backward: unsafe { mem::transmute(0usize) },
-            back_backward: self.backward.get(),
+        Items::new(&self.vec, self.backward.get(), self.len.get())
+    }
+}
+
+impl<'gt, E: ?Sized, S, M> DynChain<'gt, E, S, M> {
+    pub fn iter(&self) -> Items<'_, E> {
+        self.into_iter()
+    }
+
+    pub fn iter_mut(&mut self) -> ItemsMut<'_, E> where S: Strategy<Access=Exclusive> {
+        ItemsMut {
+            items: self.into_iter(),
             _ph: PhantomData
         }
     }
-}
 
-impl<'gt, E: ?Sized, S> Drop for DynChain<'gt, E, S> {
-    fn drop(&mut self) {
-        unsafe {
-            let mut backward = mem::transmute(0usize);
-        
-            for chunk in self.vec.chunks() {
-                let mut cur = chunk.as_ptr() as *mut u8;
-                let end = cur.offset(chunk.len() as isize);
-                while cur != end {
+    // The first element.  Doesn't iterate; the first chunk starts
+    // with its fence post.
+    pub fn front(&self) -> Option<&E> {
+        self.iter().next()
+    }
+
+    // The most recently pushed element, found from the end of the
+    // tail chunk with the stored backward function
+    pub fn back(&self) -> Option<&E> {
+        self.iter().next_back()
+    }
+
+    // Removes all elements, yielding each for final processing before
+    // it is dropped.  The chunks are kept for later pushes to reuse.
+    // Elements not yet yielded are dropped along with the iterator.
+    pub fn drain(&mut self) -> DynDrain<'_, E> where S: Strategy<Access=Exclusive> {
+        // Detach the chunks until the drain is finished, so leaking it
+        // leaks the elements rather than dropping any twice
+        let vec = mem::take(&mut self.vec);
+        let last = self.backward.take();
+        let len = self.len.replace(0);
+        // Items only points at the chunks, which don't move with `vec`
+        let items = Items::new(unsafe { &*(&vec as *const Chain<u8>) }, last, len);
+        DynDrain {
+            items: items,
+            order: self.order,
+            vec: vec,
+            dest: &mut self.vec
+        }
+    }
+
+    // Calls `f` with each element in insertion order, stopping early
+    // if it returns `Break`.  This walks the chunks directly rather
+    // than keeping the state needed to iterate from both ends.  As
+    // with iterators, elements pushed during the walk aren't visited.
+    pub fn try_for_each<B, F>(&self, mut f: F) -> ControlFlow<B>
+            where F: FnMut(&E) -> ControlFlow<B> {
+        let limit = self.vec.spare().0;
+        let mut backward = None;
+        for chunk in self.vec.chunks() {
+            let mut cur = chunk.as_ptr() as *mut u8;
+            let mut end = chunk.as_ptr_range().end as *mut u8;
+            if cur <= limit && limit <= end {
+                end = limit;
+            }
+            while cur != end {
+                unsafe {
                     let fence = cur.align_for::<FencePost<E>>() as *mut FencePost<E>;
-                    let forward_fn = (*fence).forward(backward);
-                    let forward = forward_fn(fence);
-                    // Skip stub entries
+                    let forward = (*fence).forward(backward)(fence);
+                    cur = forward.end;
+                    backward = Some(forward.backward);
                     if !forward.obj.is_null() {
-                        (forward.drop)(forward.obj);
+                        f(&*forward.elem())?;
                     }
-                    cur = forward.end;
-                    backward = forward.backward;
                 }
             }
         }
+        ControlFlow::Continue(())
+    }
+
+    pub fn for_each<F: FnMut(&E)>(&self, mut f: F) {
+        let _ = self.try_for_each(|elem| -> ControlFlow<()> {
+            f(elem);
+            ControlFlow::Continue(())
+        });
+    }
+
+    // Iterates over each element with its metadata
+    pub fn iter_meta(&self) -> MetaItems<'_, E, M> {
+        MetaItems {
+            items: self.into_iter(),
+            _ph: PhantomData
+        }
+    }
+
+    // Calls `f` with each element's type and its bytes, in
+    // insertion order.  This lets external code serialize
+    // contents whose types it knows how to encode.
+    //
+    // Unsafe because the byte slices cover whole values,
+    // including any padding bytes, which are uninitialized.
+    // Only use this when the stored types have no padding.
+    pub unsafe fn visit_raw<F: FnMut(TypeInfo, &[u8])>(&self, mut f: F) {
+        self.walk(|forward| {
+            f(forward.info,
+              slice::from_raw_parts(forward.obj, forward.info.size))
+        })
+    }
+
+    // Calls `f` with the Forward record of each element in
+    // insertion order
+    unsafe fn walk<F: FnMut(Forward<E>)>(&self, mut f: F) {
+        let mut items = self.into_iter();
+        while let Some(forward) = items.next_forward() {
+            f(forward);
+        }
+    }
+}
+
+// Drops the elements remaining in `items`.  If an element's drop
+// panics, the rest are still dropped as this unwinds, as with Vec;
+// a second panic aborts.
+struct DropItems<'a, E: ?Sized + 'a> {
+    items: Items<'a, E>,
+    order: DropOrder
+}
+
+impl<'a, E: ?Sized> DropItems<'a, E> {
+    fn run(&mut self) {
+        loop {
+            let forward = match self.order {
+                DropOrder::Insertion => self.items.next_forward(),
+                DropOrder::Reverse => self.items.next_back_forward()
+            };
+            match forward {
+                Some(forward) => unsafe { (forward.drop)(forward.obj) },
+                None => break
+            }
+        }
     }
 }
 
-pub struct Items<'a, E: ?Sized> {
+impl<'a, E: ?Sized> Drop for DropItems<'a, E> {
+    fn drop(&mut self) {
+        self.run()
+    }
+}
+
+impl<'gt, E: ?Sized, S, M> Drop for DynChain<'gt, E, S, M> {
+    fn drop(&mut self) {
+        DropItems {
+            items: self.into_iter(),
+            order: self.order
+        }.run()
+    }
+}
+
+// Iterators cover the elements present when they were created.
+// Elements pushed while iterating are not visited from either end.
+pub struct Items<'a, E: ?Sized + 'a> {
     chunks: Chunks<'a, u8>,
+    // End of the data in the tail chunk when iteration began
+    limit: *mut u8,
     cur: *mut u8,
     end: *mut u8,
     back_cur: *mut u8,
     back_start: *mut u8,
-    backward: BackwardFn<E>,
-    back_backward: BackwardFn<E>,
+    backward: Option<BackwardFn<E>>,
+    back_backward: Option<BackwardFn<E>>,
+    // Elements not yet visited from either end
+    remaining: usize,
     _ph: PhantomData<E>
 }
 
-impl<'a, E: ?Sized> Iterator for Items<'a, E> {
-    type Item = &'a E;
+impl<'a, E: ?Sized> Clone for Items<'a, E> {
+    fn clone(&self) -> Self {
+        Items {
+            chunks: self.chunks.clone(),
+            limit: self.limit,
+            cur: self.cur,
+            end: self.end,
+            back_cur: self.back_cur,
+            back_start: self.back_start,
+            backward: self.backward,
+            back_backward: self.back_backward,
+            remaining: self.remaining,
+            _ph: PhantomData
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<&'a E> {
+impl<'a, E: ?Sized> Items<'a, E> {
+    fn new(vec: &'a Chain<u8>, last: Option<BackwardFn<E>>, len: usize) -> Self {
+        Items {
+            chunks: vec.chunks(),
+            limit: vec.spare().0,
+            cur: ptr::null_mut(),
+            end: ptr::null_mut(),
+            back_cur: ptr::null_mut(),
+            back_start: ptr::null_mut(),
+            backward: None,
+            back_backward: last,
+            remaining: len,
+            _ph: PhantomData
+        }
+    }
+
+    // Bounds of a chunk's data, cut off at `limit` for the chunk
+    // that was the tail when iteration began.  The end is rounded up to where a fence post would go, as are
+    // the positions where the front stops, so the two ends compare
+    // equal when they meet between elements.
+    fn bounds(&self, s: &[u8]) -> (*mut u8, *mut u8) {
+        let start = s.as_ptr() as *mut u8;
+        let end = unsafe { start.offset(s.len() as isize) };
+        let end = if start <= self.limit && self.limit <= end { self.limit } else { end };
+        (start, unsafe { end.align_for::<FencePost<E>>() })
+    }
+
+    // Advances to the next element from the front, returning its
+    // Forward record
+    fn next_forward(&mut self) -> Option<Forward<E>> {
         loop {
             unsafe {
                 while self.cur == self.end {
                     match self.chunks.next() {
                         Some(s) => {
-                            self.cur = s.as_ptr() as *mut u8;
-                            self.end = self.cur.offset(s.len() as isize);
+                            let (start, end) = self.bounds(s);
+                            self.cur = start;
+                            self.end = end;
                         }
-                        None => { 
-                            if self.back_start.is_null() {
+                        None => {
+                            // Done if the back has nothing left either
+                            if self.back_start == self.back_cur {
                                 return None
                             } else {
                                 self.cur = self.back_start;
@@ -302,33 +1003,35 @@ impl<'a, E: ?Sized> Iterator for Items<'a, E> {
                 let fence = self.cur.align_for::<FencePost<E>>() as *mut FencePost<E>;
                 let forward_fn = (*fence).forward(self.backward);
                 let forward = forward_fn(fence);
+                let next = forward.end.align_for::<FencePost<E>>();
                 if self.back_start == self.cur {
-                    self.back_start = forward.end
+                    self.back_start = next
                 }
-                self.cur = forward.end;
-                self.backward = forward.backward;
+                self.cur = next;
+                self.backward = Some(forward.backward);
                 // Skip stub entries
                 if !forward.obj.is_null() {
-                    return Some(&*(forward.erase)(forward.obj))
+                    self.remaining -= 1;
+                    return Some(forward)
                 }
             }
         }
     }
-}
 
-impl<'a, E: ?Sized> DoubleEndedIterator for Items<'a, E> {
-    fn next_back(&mut self) -> Option<&'a E> {
+    // Advances to the next element from the back
+    fn next_back_forward(&mut self) -> Option<Forward<E>> {
         loop {
             unsafe {
                 while self.back_cur == self.back_start {
                     match self.chunks.next_back() {
                         Some(s) => {
-                            self.back_start = s.as_ptr() as *mut u8;
-                            self.back_cur = self.back_start.offset(s.len() as isize);
+                            let (start, end) = self.bounds(s);
+                            self.back_start = start;
+                            self.back_cur = end;
                         }
                         None => {
-                            if self.end.is_null() {
-                                return None 
+                            if self.cur == self.end {
+                                return None
                             } else {
                                 self.back_cur = self.end;
                                 self.back_start = self.cur;
@@ -337,7 +1040,10 @@ impl<'a, E: ?Sized> DoubleEndedIterator for Items<'a, E> {
                     }
                 }
 
-                let backward = (self.back_backward)(self.back_cur);
+                let backward = match self.back_backward {
+                    Some(b) => b(self.back_cur),
+                    None => return None
+                };
                 let forward = (backward.forward)(backward.fence);
                 if self.end == self.back_cur {
                     self.end = backward.fence as *mut u8;
@@ -346,22 +1052,181 @@ impl<'a, E: ?Sized> DoubleEndedIterator for Items<'a, E> {
                 self.back_backward = (*backward.fence).backward(backward.forward);
                 // Skip stub entries
                 if !forward.obj.is_null() {
-                    return Some(&*(forward.erase)(forward.obj))
+                    self.remaining -= 1;
+                    return Some(forward)
                 }
             }
         }
     }
 }
 
-impl<'gt, E: ?Sized + fmt::Debug, S> fmt::Debug for DynChain<'gt, E, S> {
+impl<'a, E: ?Sized + 'a> Iterator for Items<'a, E> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<&'a E> {
+        self.next_forward().map(|forward| unsafe { &*forward.elem() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, E: ?Sized + 'a> ExactSizeIterator for Items<'a, E> {}
+
+impl<'a, E: ?Sized + 'a> DoubleEndedIterator for Items<'a, E> {
+    fn next_back(&mut self) -> Option<&'a E> {
+        self.next_back_forward().map(|forward| unsafe { &*forward.elem() })
+    }
+}
+
+pub struct ItemsMut<'a, E: ?Sized + 'a> {
+    items: Items<'a, E>,
+    _ph: PhantomData<&'a mut E>
+}
+
+impl<'a, E: ?Sized + 'a> Iterator for ItemsMut<'a, E> {
+    type Item = &'a mut E;
+
+    fn next(&mut self) -> Option<&'a mut E> {
+        self.items.next_forward().map(|forward| unsafe { &mut *forward.elem_mut() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, E: ?Sized + 'a> ExactSizeIterator for ItemsMut<'a, E> {}
+
+impl<'a, E: ?Sized + 'a> DoubleEndedIterator for ItemsMut<'a, E> {
+    fn next_back(&mut self) -> Option<&'a mut E> {
+        self.items.next_back_forward().map(|forward| unsafe { &mut *forward.elem_mut() })
+    }
+}
+
+pub struct DynDrain<'a, E: ?Sized + 'a> {
+    items: Items<'a, E>,
+    // The chain's drop order, for elements not yielded
+    order: DropOrder,
+    vec: Chain<u8>,
+    // Where the emptied chunks go back to
+    dest: &'a mut Chain<u8>
+}
+
+impl<'a, E: ?Sized + 'a> Iterator for DynDrain<'a, E> {
+    type Item = Drained<'a, E>;
+
+    fn next(&mut self) -> Option<Drained<'a, E>> {
+        self.items.next_forward().map(|forward| Drained {
+            obj: forward.obj,
+            elem: unsafe { forward.elem_mut() },
+            drop: forward.drop,
+            _ph: PhantomData
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, E: ?Sized + 'a> ExactSizeIterator for DynDrain<'a, E> {}
+
+impl<'a, E: ?Sized> Drop for DynDrain<'a, E> {
+    fn drop(&mut self) {
+        // Returns the chunks even if dropping an element panics
+        struct Restore<'b, 'a: 'b, E: ?Sized + 'a>(&'b mut DynDrain<'a, E>);
+
+        impl<'b, 'a, E: ?Sized> Drop for Restore<'b, 'a, E> {
+            fn drop(&mut self) {
+                self.0.vec.clear();
+                mem::swap(self.0.dest, &mut self.0.vec);
+            }
+        }
+
+        let items = self.items.clone();
+        let order = self.order;
+        let _restore = Restore(self);
+        DropItems {
+            items: items,
+            order: order
+        }.run()
+    }
+}
+
+// An element being drained.  It is dropped along with the guard.
+pub struct Drained<'a, E: ?Sized + 'a> {
+    obj: *mut u8,
+    elem: *mut E,
+    drop: unsafe fn(*mut u8),
+    _ph: PhantomData<&'a mut E>
+}
+
+impl<'a, E: ?Sized> ops::Deref for Drained<'a, E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        unsafe { &*self.elem }
+    }
+}
+
+impl<'a, E: ?Sized> ops::DerefMut for Drained<'a, E> {
+    fn deref_mut(&mut self) -> &mut E {
+        unsafe { &mut *self.elem }
+    }
+}
+
+impl<'a, E: ?Sized> Drop for Drained<'a, E> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.obj) }
+    }
+}
+
+pub struct MetaItems<'a, E: ?Sized + 'a, M: 'a> {
+    items: Items<'a, E>,
+    _ph: PhantomData<&'a M>
+}
+
+impl<'a, E: ?Sized, M> Iterator for MetaItems<'a, E, M> {
+    type Item = (&'a M, &'a E);
+
+    fn next(&mut self) -> Option<(&'a M, &'a E)> {
+        self.items.next_forward().map(|forward| unsafe {
+            (&*(forward.meta as *const M), &*forward.elem())
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, E: ?Sized, M> ExactSizeIterator for MetaItems<'a, E, M> {}
+
+impl<'a, E: ?Sized, M> DoubleEndedIterator for MetaItems<'a, E, M> {
+    fn next_back(&mut self) -> Option<(&'a M, &'a E)> {
+        self.items.next_back_forward().map(|forward| unsafe {
+            (&*(forward.meta as *const M), &*forward.elem())
+        })
+    }
+}
+
+impl<'gt, E: ?Sized, S, M> Default for DynChain<'gt, E, S, M> {
+    fn default() -> Self {
+        DynChain::new()
+    }
+}
+
+impl<'gt, E: ?Sized + fmt::Debug, S, M> fmt::Debug for DynChain<'gt, E, S, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut need_comma = false;
-        try!(write!(f, "["));
+        write!(f, "[")?;
         for elem in self {
             if need_comma {
-                try!(write!(f, ", "));
+                write!(f, ", ")?;
             }
-            try!(elem.fmt(f));
+            elem.fmt(f)?;
             need_comma = true;
         }
         write!(f, "]")
@@ -386,7 +1251,7 @@ mod test {
             }
         }
                 
-        let vec: DynChain<Display> = DynChain::new();
+        let vec: DynChain<dyn Display> = DynChain::new();
         vec.push(42);
         vec.push("Weasel");
         vec.push(Hi);
@@ -400,6 +1265,235 @@ mod test {
         }
     }
 
+    #[test]
+    fn push_while_iterating() {
+        let vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(64);
+        vec.push(1u8);
+        vec.push("two");
+        let mut fwd = vec.iter();
+        let mut back = vec.iter();
+        assert_eq!(fwd.len(), 2);
+        assert_eq!(format!("{:?}", fwd.next().unwrap()), "1");
+        assert_eq!(fwd.len(), 1);
+        // One more in the same chunk, then enough to add chunks
+        vec.push(3u16);
+        for i in 0..100u64 {
+            vec.push(i);
+        }
+        assert_eq!(format!("{:?}", back.next_back().unwrap()), "\"two\"");
+        assert_eq!(format!("{:?}", fwd.next().unwrap()), "\"two\"");
+        assert!(fwd.next().is_none());
+        assert_eq!(format!("{:?}", back.next_back().unwrap()), "1");
+        assert!(back.next_back().is_none());
+        assert_eq!(back.len(), 0);
+        assert_eq!(vec.len(), 103);
+        assert_eq!(vec.iter().count(), 103);
+        assert_eq!(vec.iter().rev().count(), 103);
+    }
+
+    #[test]
+    fn front_back() {
+        let chain: DynChain<dyn fmt::Debug> = DynChain::with_capacity(32);
+        assert!(chain.front().is_none() && chain.back().is_none());
+        chain.push(1u8);
+        assert_eq!(format!("{:?} {:?}", chain.front(), chain.back()), "Some(1) Some(1)");
+        for i in 0..100u64 {
+            chain.push(i);
+        }
+        chain.push("last");
+        assert_eq!(format!("{:?} {:?}", chain.front(), chain.back()), "Some(1) Some(\"last\")");
+    }
+
+    #[test]
+    fn try_for_each() {
+        let vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(16);
+        for i in 0..20u32 {
+            vec.push(i);
+        }
+        vec.push("end");
+        let mut seen = Vec::new();
+        let found = vec.try_for_each(|elem| {
+            let text = format!("{:?}", elem);
+            if text == "12" {
+                return ControlFlow::Break(seen.len())
+            }
+            seen.push(text);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(found, ControlFlow::Break(12));
+        let mut count = 0;
+        vec.for_each(|elem| {
+            count += 1;
+            if count == 1 {
+                // Not visited by this walk
+                vec.push(0u8);
+            }
+            let _ = format!("{:?}", elem);
+        });
+        assert_eq!((count, vec.len()), (21, 22));
+    }
+
+    #[test]
+    fn emplace() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(16);
+        assert!(vec.is_empty());
+        vec.emplace(|| [7u64; 4]);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+            vec.emplace_with(|_: *mut String| panic!("constructor failed"));
+        }));
+        assert!(res.is_err());
+        unsafe {
+            vec.emplace_with(|slot| ptr::write(slot, "after".to_string()));
+        }
+        // Pushes made while constructing come first
+        vec.emplace(|| { vec.push(0u8); 1u8 });
+        let items: Vec<String> = vec.iter().map(|e| format!("{:?}", e)).collect();
+        assert_eq!(items, ["[7, 7, 7, 7]", "\"after\"", "0", "1"]);
+        assert_eq!(vec.iter().rev().count(), 4);
+        assert!(vec.stats().chunk_count() >= 1);
+        // The value is constructed in its slot, which is invisible
+        // until it is finished
+        let slot = vec.emplace(|| {
+            assert_eq!(vec.iter().count(), 4);
+            [vec.len() as u64; 64]
+        });
+        assert!(ptr::eq(slot as *const _ as *const u8, vec.back().unwrap() as *const _ as *const u8));
+    }
+
+    #[test]
+    fn emplace_stub() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(16);
+        vec.emplace(|| {
+            vec.emplace(|| { vec.push(1u8); 2u16 });
+            0u32
+        });
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            vec.emplace(|| -> String { vec.push(3u8); panic!("constructor failed") });
+        }));
+        assert!(res.is_err());
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            vec.emplace(|| -> String { panic!("constructor failed") });
+        }));
+        assert!(res.is_err());
+        vec.push(4u8);
+        assert_eq!(format!("{:?}", vec), "[1, 2, 0, 3, 4]");
+        let back: Vec<String> = vec.iter().rev().map(|e| format!("{:?}", e)).collect();
+        assert_eq!(back, ["4", "3", "0", "2", "1"]);
+        assert_eq!(vec.len(), 5);
+    }
+
+    #[test]
+    fn drop_order() {
+        use std::cell::RefCell;
+
+        thread_local!(static DROPPED: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) });
+
+        #[derive(Debug)]
+        struct Noisy(u32);
+        impl Drop for Noisy {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.borrow_mut().push(self.0));
+            }
+        }
+
+        for &order in &[DropOrder::Insertion, DropOrder::Reverse] {
+            {
+                let vec: DynChain<dyn fmt::Debug> = DynChain::with_drop_order(order);
+                for i in 0..50 {
+                    vec.push(Noisy(i));
+                    vec.push([i as u8; 3]);
+                }
+            }
+            let mut expect: Vec<u32> = (0..50).collect();
+            if order == DropOrder::Reverse {
+                expect.reverse();
+            }
+            DROPPED.with(|d| assert_eq!(d.replace(Vec::new()), expect));
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        #[derive(Debug, PartialEq)]
+        #[repr(align(32))]
+        struct Wide(u8);
+
+        let vec: DynChain<dyn fmt::Debug, Unsize, u16> = DynChain::new();
+        for i in 0..100u16 {
+            match i % 4 {
+                0 => { vec.push_meta(i, i as u8); }
+                1 => { vec.push_meta(i, Wide(i as u8)); }
+                2 => { vec.push_meta(i, [i as u64; 3]); }
+                _ => { vec.push(()); }
+            }
+        }
+        for (i, (&meta, elem)) in vec.iter_meta().enumerate() {
+            let expect = if i % 4 == 3 { 0 } else { i as u16 };
+            assert_eq!(meta, expect);
+            if i % 4 == 1 {
+                assert_eq!(format!("{:?}", elem), format!("{:?}", Wide(i as u8)));
+            }
+        }
+        let back: Vec<u16> = vec.iter_meta().rev().map(|(&m, _)| m).collect();
+        let forward: Vec<u16> = vec.iter_meta().map(|(&m, _)| m).collect();
+        assert_eq!(back.into_iter().rev().collect::<Vec<_>>(), forward);
+    }
+
+    #[test]
+    fn drop_panic() {
+        use std::cell::Cell;
+        use std::panic;
+
+        thread_local!(static DROPPED: Cell<u32> = const { Cell::new(0) });
+
+        #[derive(Debug)]
+        struct Bomb(bool);
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.set(d.get() + 1));
+                if self.0 {
+                    panic!("boom")
+                }
+            }
+        }
+
+        for &order in &[DropOrder::Insertion, DropOrder::Reverse] {
+            DROPPED.with(|d| d.set(0));
+            let res = panic::catch_unwind(move || {
+                let vec: DynChain<dyn fmt::Debug> = DynChain::with_drop_order(order);
+                for i in 0..30 {
+                    vec.push(Bomb(i == 10));
+                    vec.push("filler");
+                }
+            });
+            assert!(res.is_err());
+            DROPPED.with(|d| assert_eq!(d.get(), 30));
+        }
+    }
+
+    #[test]
+    fn visit_raw() {
+        let vec: DynChain<dyn fmt::Debug> = DynChain::new();
+        vec.push(0x0102u16);
+        vec.push([7u8; 3]);
+        vec.push(42u64);
+
+        let mut seen = Vec::new();
+        unsafe {
+            vec.visit_raw(|info, bytes| {
+                assert_eq!(info.size(), bytes.len());
+                seen.push((info.align(), bytes.to_vec()));
+            });
+        }
+        assert_eq!(seen, vec![(2, 0x0102u16.to_ne_bytes().to_vec()),
+                              (1, vec![7, 7, 7]),
+                              (8, 42u64.to_ne_bytes().to_vec())]);
+    }
+
     #[test]
     fn unsize_slice() {
         let vec: DynChain<[u8]> = DynChain::new();
@@ -410,14 +1504,138 @@ mod test {
         }
     }
 
+    #[test]
+    fn unsized_copy() {
+        let mut vec: DynChain<[u32]> = DynChain::new();
+        vec.push([1, 2]);
+        let runtime: Vec<u32> = (3..10).collect();
+        assert_eq!(vec.push_unsized_copy(&runtime), &runtime[..]);
+        vec.push_unsized_copy(&[]);
+        vec.push([10u32]);
+        let expected: [&[u32]; 4] = [&[1, 2], &runtime, &[], &[10]];
+        assert!(vec.iter().eq(expected.iter().cloned()));
+        assert!(vec.iter().rev().eq(expected.iter().rev().cloned()));
+        assert_eq!(vec.len(), 4);
+        let mut sizes = Vec::new();
+        unsafe { vec.visit_raw(|info, _| sizes.push(info.size())) };
+        assert_eq!(sizes, [8, 28, 0, 4]);
+
+        for elems in vec.iter_mut() {
+            elems.reverse();
+        }
+        assert_eq!(vec.iter().nth(1).unwrap()[0], 9);
+
+        let strs: DynChain<str, Deref> = DynChain::new();
+        strs.push(String::from("owned"));
+        let copied = String::from("copied");
+        strs.push_unsized_copy(&copied[..4]);
+        assert_eq!(format!("{:?}", strs), "[\"owned\", \"copi\"]");
+    }
+
     #[test]
     fn deref_str() {
         let vec: DynChain<str, Deref> = DynChain::new();
-        vec.push(format!("Hello"));
+        vec.push("Hello".to_string());
         vec.push("world");
 
         for item in &vec {
             println!("{}", item);
         }
     }
+
+    #[test]
+    fn iter_mut() {
+        let mut vec: DynChain<[u32]> = DynChain::new();
+        vec.push([1, 2]);
+        vec.push([3]);
+        vec.push([4, 5, 6]);
+        for elem in vec.iter_mut() {
+            elem.reverse();
+        }
+        vec.iter_mut().next_back().unwrap()[0] = 0;
+        let all: Vec<u32> = vec.iter().flat_map(|e| e.iter().cloned()).collect();
+        assert_eq!(all, [2, 1, 3, 0, 5, 4]);
+    }
+
+    #[test]
+    fn drain() {
+        thread_local!(static DROPPED: Cell<u32> = const { Cell::new(0) });
+        struct Count(Vec<u32>);
+        impl Drop for Count {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.set(d.get() + 1));
+            }
+        }
+        impl fmt::Debug for Count {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        let mut vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(256);
+        for i in 0..4 {
+            vec.push(Count(vec![i]));
+        }
+        let reserved = vec.stats().reserved_bytes();
+        let mut seen = Vec::new();
+        for (i, elem) in vec.drain().enumerate() {
+            seen.push(format!("{:?}", &*elem));
+            assert_eq!(DROPPED.with(|d| d.get()), i as u32);
+            if i == 1 {
+                break
+            }
+        }
+        assert_eq!(seen, ["[0]", "[1]"]);
+        assert_eq!(DROPPED.with(|d| d.get()), 4);
+        assert!(vec.is_empty());
+        assert_eq!(vec.iter().count(), 0);
+
+        vec.push(7);
+        assert_eq!(format!("{:?}", vec), "[7]");
+        assert_eq!(vec.stats().reserved_bytes(), reserved);
+    }
+
+    #[test]
+    fn iter_meet() {
+        // Small elements leave padding before the next fence post,
+        // where the two ends of an iterator meet
+        let vec: DynChain<dyn fmt::Debug> = DynChain::with_capacity(256);
+        for i in 0..4u32 {
+            vec.push(i);
+            vec.push(i as u8);
+        }
+        for front in 0..9 {
+            let mut items = vec.iter();
+            let head = items.by_ref().take(front).count();
+            assert_eq!(head + items.rev().count(), 8);
+            let mut items = vec.iter();
+            let tail = items.by_ref().rev().take(front).count();
+            assert_eq!(tail + items.count(), 8);
+        }
+    }
+
+    #[test]
+    fn drain_reverse() {
+        use std::cell::RefCell;
+
+        thread_local!(static DROPPED: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) });
+
+        #[derive(Debug)]
+        struct Noisy(u32);
+        impl Drop for Noisy {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.borrow_mut().push(self.0));
+            }
+        }
+
+        let mut vec: DynChain<dyn fmt::Debug> = DynChain::with_drop_order(DropOrder::Reverse);
+        for i in 0..5 {
+            vec.push(Noisy(i));
+        }
+        let mut drain = vec.drain();
+        drain.next();
+        drop(drain);
+        // The yielded element goes first, then the rest from the back
+        DROPPED.with(|d| assert_eq!(*d.borrow(), [0, 4, 3, 2, 1]));
+    }
 }
@@ -0,0 +1,78 @@
+use std::cmp;
+
+// Decides the capacity of each new chunk a chain allocates.
+// `last` is the capacity of the current tail chunk, or 0 for the
+// first chunk; `needed` is the contiguous space requested.  The
+// chain never allocates less than `needed`, whatever is returned.
+pub trait GrowthPolicy {
+    fn next_capacity(&self, last: usize, needed: usize) -> usize;
+}
+
+// Doubles the size of each chunk, starting from 8 elements.  This
+// is what a chain does without an explicit policy.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Doubling;
+
+impl GrowthPolicy for Doubling {
+    fn next_capacity(&self, last: usize, needed: usize) -> usize {
+        let mut cap = if last == 0 { 8 } else { last.saturating_mul(2) };
+        while cap < needed {
+            cap = cap.saturating_mul(2);
+        }
+        cap
+    }
+}
+
+// Allocates every chunk with the same capacity, so the cost of
+// any single allocation is bounded
+#[derive(Clone, Copy, Debug)]
+pub struct Fixed(pub usize);
+
+impl GrowthPolicy for Fixed {
+    fn next_capacity(&self, _: usize, needed: usize) -> usize {
+        cmp::max(self.0, needed)
+    }
+}
+
+// Doubles like `Doubling` until chunks reach the given capacity
+#[derive(Clone, Copy, Debug)]
+pub struct Capped(pub usize);
+
+impl GrowthPolicy for Capped {
+    fn next_capacity(&self, last: usize, needed: usize) -> usize {
+        cmp::max(cmp::min(Doubling.next_capacity(last, needed), self.0), needed)
+    }
+}
+
+// Any `Fn(last, needed) -> capacity` closure is a policy
+impl<F: Fn(usize, usize) -> usize> GrowthPolicy for F {
+    fn next_capacity(&self, last: usize, needed: usize) -> usize {
+        self(last, needed)
+    }
+}
+
+// The chunk capacities a chain ended up with during a run.  Passing
+// it to `Chain::with_profile` allocates the same chunks up front, so
+// repeating the same workload never has to grow the chain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizingProfile {
+    caps: Vec<usize>
+}
+
+impl SizingProfile {
+    // Builds a profile from capacities saved by an earlier run
+    pub fn from_capacities(caps: Vec<usize>) -> Self {
+        SizingProfile {
+            caps: caps
+        }
+    }
+
+    pub fn capacities(&self) -> &[usize] {
+        &self.caps
+    }
+
+    // Total elements the profiled chunks can hold
+    pub fn total(&self) -> usize {
+        self.caps.iter().fold(0, |sum, &cap| sum.saturating_add(cap))
+    }
+}
@@ -1,5 +1,23 @@
 mod chain;
 mod dynchain;
+mod growth;
+mod usage;
+mod sync;
+mod small;
+mod once;
+mod call;
+mod splice;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(feature = "rayon")]
+mod rayon_impls;
 
 pub use self::chain::*;
 pub use self::dynchain::*;
+pub use self::growth::*;
+pub use self::usage::*;
+pub use self::sync::*;
+pub use self::small::*;
+pub use self::once::*;
+pub use self::call::*;
+pub use self::splice::*;
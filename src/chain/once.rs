@@ -0,0 +1,93 @@
+use super::dynchain::{DynChain, Erase, EraseMut, Exclusive, Strategy};
+
+// Calls a stored closure, leaving its slot empty so it can't be
+// called again
+pub trait CallOnce {
+    fn call_once(&mut self);
+}
+
+impl<F: FnOnce()> CallOnce for Option<F> {
+    fn call_once(&mut self) {
+        if let Some(f) = self.take() {
+            f()
+        }
+    }
+}
+
+// Erases closures, wrapped in an Option, to CallOnce
+struct Shim(());
+
+impl<'gt, F: FnOnce() + 'gt> Erase<Option<F>, dyn CallOnce + 'gt> for Shim {
+    fn erase(real: &Option<F>) -> &(dyn CallOnce + 'gt) {
+        real
+    }
+}
+
+impl<'gt, F: FnOnce() + 'gt> EraseMut<Option<F>, dyn CallOnce + 'gt> for Shim {
+    fn erase_mut(real: &mut Option<F>) -> &mut (dyn CallOnce + 'gt) {
+        real
+    }
+}
+
+impl Strategy for Shim {
+    type Access = Exclusive;
+}
+
+// A queue of closures, each stored inline in a DynChain and called
+// exactly once.  Closures are moved out of the chain to be called;
+// a closure that was never run is dropped with the queue.
+pub struct OnceQueue<'gt> {
+    chain: DynChain<'gt, dyn CallOnce + 'gt, Shim>
+}
+
+impl<'gt> OnceQueue<'gt> {
+    pub const fn new() -> Self {
+        OnceQueue {
+            chain: DynChain::new()
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    pub fn push<F: FnOnce() + 'gt>(&self, f: F) {
+        self.chain.push(Some(f));
+    }
+
+    // Calls every queued closure in order and empties the queue,
+    // keeping its memory for the next batch.  If one panics, those
+    // after it are dropped without being called.
+    pub fn drain_execute(&mut self) {
+        for mut slot in self.chain.drain() {
+            slot.call_once();
+        }
+    }
+}
+
+impl<'gt> Default for OnceQueue<'gt> {
+    fn default() -> Self {
+        OnceQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn drain_execute() {
+        let log = RefCell::new(Vec::new());
+        let mut queue = OnceQueue::new();
+        let owned = String::from("owned");
+        queue.push(|| log.borrow_mut().push(1.to_string()));
+        queue.push(|| log.borrow_mut().push(owned));
+        queue.push(|| log.borrow_mut().push(3.to_string()));
+        queue.drain_execute();
+        assert!(queue.is_empty());
+        queue.drain_execute();
+        assert_eq!(*log.borrow(), ["1", "owned", "3"]);
+    }
+}
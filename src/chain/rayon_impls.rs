@@ -0,0 +1,72 @@
+// Rayon support for Chain, behind the `rayon` feature.  Work is
+// split at chunk boundaries first, then within each chunk.
+use super::chain::Chain;
+use super::dynchain::{DynChain, Exclusive, Strategy};
+use rayon::iter::{FlatMap, IntoParallelIterator};
+use rayon::prelude::*;
+use rayon::{slice, vec};
+
+impl<T: Sync> Chain<T> {
+    // Parallel iterator over the chain's chunks, collected up front.
+    // Under the default doubling policy there are only
+    // logarithmically many, but a `Fixed` or `Capped` policy gives
+    // one per so many elements, making the collection linear.
+    pub fn par_chunks(&self) -> vec::IntoIter<&[T]> {
+        self.chunks().collect::<Vec<_>>().into_par_iter()
+    }
+}
+
+impl<'a, T: Sync + 'a> IntoParallelIterator for &'a Chain<T> {
+    type Item = &'a T;
+    type Iter = FlatMap<vec::IntoIter<&'a [T]>, fn(&'a [T]) -> slice::Iter<'a, T>>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        fn iter<'a, T: Sync>(chunk: &'a [T]) -> slice::Iter<'a, T> {
+            chunk.par_iter()
+        }
+
+        self.par_chunks().flat_map(iter as fn(&'a [T]) -> slice::Iter<'a, T>)
+    }
+}
+
+impl<'gt, E: ?Sized + Send, S: Strategy<Access=Exclusive>, M> DynChain<'gt, E, S, M> {
+    // Parallel iterator over mutable references to the elements.
+    // Each fence post can only be decoded knowing the element before
+    // it, so the elements are found with one sequential walk and the
+    // work is split from there.
+    pub fn par_iter_mut(&mut self) -> vec::IntoIter<&mut E> {
+        self.iter_mut().collect::<Vec<_>>().into_par_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::chain::Chain;
+    use super::super::dynchain::DynChain;
+    use rayon::prelude::*;
+
+    #[test]
+    fn par_iter() {
+        let chain: Chain<u64> = (0..10000).collect();
+        assert_eq!(chain.par_iter().sum::<u64>(), (0..10000u64).sum::<u64>());
+        assert_eq!(chain.par_chunks().map(|c| c.len()).sum::<usize>(), 10000);
+        let evens: Vec<u64> = chain.par_iter().filter(|&&x| x % 2 == 0).cloned().collect();
+        assert_eq!(evens, (0..10000).filter(|x| x % 2 == 0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dyn_par_iter_mut() {
+        let mut chain: DynChain<[u32]> = DynChain::with_capacity(64);
+        for i in 0..1000u32 {
+            if i % 2 == 0 {
+                chain.push([i, i]);
+            } else {
+                chain.push([i]);
+            }
+        }
+        chain.par_iter_mut().for_each(|elem| elem.iter_mut().for_each(|x| *x += 1));
+        let firsts: Vec<u32> = chain.iter().map(|elem| elem[0]).collect();
+        assert!(firsts.into_iter().eq(1..1001));
+        assert_eq!(chain.iter().map(|elem| elem.len()).sum::<usize>(), 1500);
+    }
+}
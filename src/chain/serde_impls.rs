@@ -0,0 +1,75 @@
+// Serde support for Chain, behind the `serde` feature.  Chains
+// serialize as sequences and deserialize straight into chunks.
+use super::chain::Chain;
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer, SerializeSeq};
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr;
+
+// Upper bound on what a sequence's size hint can make us reserve
+// up front, so untrusted input can't request huge allocations
+const MAX_HINT: usize = 4096;
+
+impl<T: Serialize> Serialize for Chain<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for chunk in self.chunks() {
+            for elem in chunk {
+                seq.serialize_element(elem)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+struct ChainVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for ChainVisitor<T> {
+    type Value = Chain<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Chain<T>, A::Error> {
+        let chain: Chain<T> = Chain::new();
+        loop {
+            let hint = cmp::min(seq.size_hint().unwrap_or(0), MAX_HINT);
+            let (ptr, cap) = chain.raw_reserve(cmp::max(hint, 1));
+            for i in 0..cap {
+                match seq.next_element()? {
+                    Some(elem) => unsafe {
+                        ptr::write(ptr.offset(i as isize), elem);
+                        chain.raw_add_len(1);
+                    },
+                    None => return Ok(chain)
+                }
+            }
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Chain<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Chain<T>, D::Error> {
+        deserializer.deserialize_seq(ChainVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::chain::Chain;
+    use serde_json;
+
+    #[test]
+    fn round_trip() {
+        let chain: Chain<String> = (0..100).map(|i| i.to_string()).collect();
+        let json = serde_json::to_string(&chain).unwrap();
+        let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        assert_eq!(json, serde_json::to_string(&expected).unwrap());
+        let back: Chain<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, chain);
+        assert!(serde_json::from_str::<Chain<u8>>("[1, \"x\"]").is_err());
+    }
+}
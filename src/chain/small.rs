@@ -0,0 +1,218 @@
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::iter;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::slice;
+use std::str;
+use super::chain::{Chain, Iter};
+
+// A chain whose first `N` elements are stored inline, so small
+// chains never touch the allocator.  Once something doesn't fit
+// inline, it and everything after it go to an ordinary heap chain.
+pub struct SmallChain<T, const N: usize> {
+    inline: UnsafeCell<[MaybeUninit<T>; N]>,
+    // Initialized elements at the start of `inline`
+    len: Cell<usize>,
+    spill: Chain<T>
+}
+
+pub type SmallIter<'a, T> = iter::Chain<slice::Iter<'a, T>, Iter<'a, T>>;
+
+impl<T, const N: usize> SmallChain<T, N> {
+    pub const fn new() -> Self {
+        SmallChain {
+            inline: UnsafeCell::new(unsafe { MaybeUninit::uninit().assume_init() }),
+            len: Cell::new(0),
+            spill: Chain::new()
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.get() + self.spill.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Whether any elements have been moved out to the heap
+    #[inline]
+    pub fn spilled(&self) -> bool {
+        self.spill.len() != 0
+    }
+
+    #[inline]
+    fn inline_ptr(&self) -> *mut T {
+        self.inline.get() as *mut T
+    }
+
+    // Room for `len` more elements in the inline storage, as long as
+    // nothing has spilled yet (which would break ordering)
+    #[inline]
+    fn inline_room(&self, len: usize) -> bool {
+        !self.spilled() && N - self.len.get() >= len
+    }
+
+    fn inline_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.inline_ptr(), self.len.get()) }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match index.checked_sub(self.len.get()) {
+            None => self.inline_slice().get(index),
+            Some(index) => self.spill.get(index)
+        }
+    }
+
+    pub fn push(&self, elem: T) -> &T {
+        if !self.inline_room(1) {
+            return self.spill.push(elem)
+        }
+        unsafe {
+            let slot = self.inline_ptr().offset(self.len.get() as isize);
+            ptr::write(slot, elem);
+            self.len.set(self.len.get() + 1);
+            &*slot
+        }
+    }
+
+    // Copies `elems` into contiguous space, inline if they fit
+    pub fn extend_from_slice(&self, elems: &[T]) -> &[T] where T: Copy {
+        if !self.inline_room(elems.len()) {
+            return self.spill.extend_from_slice(elems)
+        }
+        unsafe {
+            let dest = self.inline_ptr().offset(self.len.get() as isize);
+            ptr::copy_nonoverlapping(elems.as_ptr(), dest, elems.len());
+            self.len.set(self.len.get() + elems.len());
+            slice::from_raw_parts(dest, elems.len())
+        }
+    }
+
+    pub fn clear(&mut self) {
+        // Clears the spill even if an inline element's drop panics
+        struct Guard<'a, T: 'a>(&'a mut Chain<T>);
+
+        impl<'a, T> Drop for Guard<'a, T> {
+            fn drop(&mut self) {
+                self.0.clear()
+            }
+        }
+
+        // Reset first so a panicking drop can't lead to a double drop;
+        // dropping the slice still drops every element
+        let inline = ptr::slice_from_raw_parts_mut(self.inline_ptr(), self.len.replace(0));
+        let _guard = Guard(&mut self.spill);
+        unsafe {
+            ptr::drop_in_place(inline);
+        }
+    }
+
+    pub fn iter(&self) -> SmallIter<'_, T> {
+        self.inline_slice().iter().chain(self.spill.iter())
+    }
+}
+
+impl<const N: usize> SmallChain<u8, N> {
+    // Copies `s` into contiguous space, returning the copy
+    pub fn push_str(&self, s: &str) -> &str {
+        unsafe { str::from_utf8_unchecked(self.extend_from_slice(s.as_bytes())) }
+    }
+}
+
+impl<T, const N: usize> Drop for SmallChain<T, N> {
+    fn drop(&mut self) {
+        let len = self.len.replace(0);
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.inline_ptr(), len));
+        }
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a SmallChain<T, N> {
+    type Item = &'a T;
+    type IntoIter = SmallIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T, const N: usize> Default for SmallChain<T, N> {
+    fn default() -> Self {
+        SmallChain::new()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for SmallChain<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic;
+    use std::rc::Rc;
+
+    #[test]
+    fn spill() {
+        let chain: SmallChain<u8, 8> = SmallChain::new();
+        let a = chain.push_str("abc");
+        let b = chain.push_str("def");
+        assert!(!chain.spilled());
+        // Doesn't fit in the two inline bytes left
+        let c = chain.push_str("ghi");
+        assert!(chain.spilled());
+        // Later pushes follow the spill to keep order
+        chain.push(b'!');
+        assert_eq!((a, b, c), ("abc", "def", "ghi"));
+        assert_eq!(chain.iter().cloned().collect::<Vec<_>>(), b"abcdefghi!");
+        assert_eq!(chain.get(6), Some(&b'g'));
+        assert_eq!(chain.len(), 10);
+    }
+
+    #[test]
+    fn drops() {
+        let rc = Rc::new(());
+        let mut chain: SmallChain<Rc<()>, 2> = SmallChain::new();
+        for _ in 0..3 {
+            chain.push(rc.clone());
+        }
+        assert_eq!(Rc::strong_count(&rc), 4);
+        chain.clear();
+        assert_eq!(Rc::strong_count(&rc), 1);
+        chain.push(rc.clone());
+        drop(chain);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn drop_panic() {
+        struct Bomb(Option<Rc<()>>);
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                if self.0.is_none() {
+                    panic!("bomb")
+                }
+            }
+        }
+
+        let rc = Rc::new(());
+        let mut chain: SmallChain<Bomb, 2> = SmallChain::new();
+        chain.push(Bomb(None));
+        for _ in 0..3 {
+            chain.push(Bomb(Some(rc.clone())));
+        }
+        assert!(chain.spilled());
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| chain.clear()));
+        assert!(res.is_err());
+        assert!(chain.is_empty());
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
@@ -0,0 +1,168 @@
+use std::cell::Cell;
+use std::cmp;
+use std::iter::{Flatten, Peekable};
+use super::chain::{Chain, Chunks, Iter};
+
+// A chain that can also take references to static data, such as
+// canned fragments of a response, without copying them.  Each static
+// slice is recorded along with the position it was pushed at and is
+// stitched in between the owned chunks during iteration.
+//
+// This is a separate type because Chain hands out mutable access to
+// its chunks, which static data can't allow.
+pub struct SpliceChain<T: 'static> {
+    chain: Chain<T>,
+    // Static slices, each with the count of owned elements before it
+    statics: Chain<(usize, &'static [T])>,
+    static_len: Cell<usize>
+}
+
+impl<T: 'static> SpliceChain<T> {
+    pub const fn new() -> Self {
+        SpliceChain {
+            chain: Chain::new(),
+            statics: Chain::new(),
+            static_len: Cell::new(0)
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.chain.len() + self.static_len.get()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn push(&self, elem: T) -> &T {
+        self.chain.push(elem)
+    }
+
+    pub fn extend_from_slice(&self, elems: &[T]) -> &[T] where T: Copy {
+        self.chain.extend_from_slice(elems)
+    }
+
+    // Appends `data` by reference
+    pub fn push_static(&self, data: &'static [T]) {
+        self.statics.push((self.chain.len(), data));
+        self.static_len.set(self.static_len.get() + data.len());
+    }
+
+    // Iterates over the contents as slices, alternating between owned
+    // chunks and static data as they were pushed
+    pub fn chunks(&self) -> SpliceChunks<'_, T> {
+        SpliceChunks {
+            chunks: self.chain.chunks(),
+            statics: self.statics.iter().peekable(),
+            cur: &[],
+            pos: 0
+        }
+    }
+
+    pub fn iter(&self) -> Flatten<SpliceChunks<'_, T>> {
+        self.chunks().flatten()
+    }
+
+    pub fn to_vec(&self) -> Vec<T> where T: Clone {
+        let mut vec = Vec::with_capacity(self.len());
+        for chunk in self.chunks() {
+            vec.extend_from_slice(chunk);
+        }
+        vec
+    }
+}
+
+impl SpliceChain<u8> {
+    pub fn push_str(&self, s: &str) -> &str {
+        self.chain.push_str(s)
+    }
+
+    pub fn push_static_str(&self, s: &'static str) {
+        self.push_static(s.as_bytes())
+    }
+}
+
+impl<T: 'static> Default for SpliceChain<T> {
+    fn default() -> Self {
+        SpliceChain::new()
+    }
+}
+
+pub struct SpliceChunks<'a, T: 'static> {
+    chunks: Chunks<'a, T>,
+    statics: Peekable<Iter<'a, (usize, &'static [T])>>,
+    // Unvisited part of the current owned chunk
+    cur: &'a [T],
+    // Owned elements visited so far
+    pos: usize
+}
+
+impl<'a, T: 'static> Iterator for SpliceChunks<'a, T> {
+    type Item = &'a [T];
+
+    fn next(&mut self) -> Option<&'a [T]> {
+        loop {
+            if let Some(&&(at, data)) = self.statics.peek() {
+                if at == self.pos {
+                    self.statics.next();
+                    if data.is_empty() {
+                        continue
+                    }
+                    return Some(data)
+                }
+            }
+            if self.cur.is_empty() {
+                match self.chunks.next() {
+                    Some(chunk) => {
+                        self.cur = chunk;
+                        continue
+                    },
+                    None => return None
+                }
+            }
+            // Stop short of the next static slice
+            let len = match self.statics.peek() {
+                Some(&&(at, _)) => cmp::min(at - self.pos, self.cur.len()),
+                None => self.cur.len()
+            };
+            let (head, rest) = self.cur.split_at(len);
+            self.cur = rest;
+            self.pos += len;
+            return Some(head)
+        }
+    }
+}
+
+impl<'a, T: 'static> IntoIterator for &'a SpliceChain<T> {
+    type Item = &'a T;
+    type IntoIter = Flatten<SpliceChunks<'a, T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static HEADER: &str = "HTTP/1.1 200 OK\r\n";
+
+    #[test]
+    fn splice() {
+        let chain = SpliceChain::new();
+        chain.push_static_str(HEADER);
+        chain.push_str("Content-Length: 5\r\n");
+        chain.push_static_str("\r\n");
+        chain.push_str("hello");
+        chain.push_static(b"");
+        let chunks: Vec<&[u8]> = chain.chunks().collect();
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks[0].as_ptr() == HEADER.as_ptr());
+        assert_eq!(chain.to_vec(), b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(chain.len(), chain.iter().count());
+    }
+}
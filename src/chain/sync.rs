@@ -0,0 +1,329 @@
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use super::dynchain::{DynChain, Store, Unsize};
+
+struct SyncChunk<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Set once the slot at the same index has been written
+    ready: Box<[AtomicBool]>,
+    // Slots handed out so far.  Keeps counting past the capacity
+    // while pushers race to install the next chunk.
+    reserved: AtomicUsize,
+    next: AtomicPtr<SyncChunk<T>>
+}
+
+impl<T> SyncChunk<T> {
+    fn new(cap: usize) -> *mut Self {
+        Box::into_raw(Box::new(SyncChunk {
+            slots: (0..cap).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            ready: (0..cap).map(|_| AtomicBool::new(false)).collect(),
+            reserved: AtomicUsize::new(0),
+            next: AtomicPtr::new(ptr::null_mut())
+        }))
+    }
+
+    #[inline]
+    fn cap(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+// A chain that any number of threads can push to at once without
+// locking.  Each push claims a slot with an atomic increment; the
+// thread that finds a chunk full installs the next one with a
+// compare-and-swap, and the others use whichever chunk won.
+pub struct SyncChain<T> {
+    head: *mut SyncChunk<T>,
+    tail: AtomicPtr<SyncChunk<T>>,
+    _ph: PhantomData<T>
+}
+
+unsafe impl<T: Send> Send for SyncChain<T> {}
+unsafe impl<T: Send + Sync> Sync for SyncChain<T> {}
+
+impl<T> SyncChain<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(8)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        let head = SyncChunk::new(cmp::max(cap, 1));
+        SyncChain {
+            head: head,
+            tail: AtomicPtr::new(head),
+            _ph: PhantomData
+        }
+    }
+
+    pub fn push(&self, elem: T) -> &T {
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            unsafe {
+                let chunk = &*tail;
+                let index = chunk.reserved.fetch_add(1, Ordering::Relaxed);
+                if index < chunk.cap() {
+                    let slot = chunk.slots[index].get() as *mut T;
+                    ptr::write(slot, elem);
+                    chunk.ready[index].store(true, Ordering::Release);
+                    return &*slot
+                }
+                // Full: install the next chunk unless someone beat us
+                let mut next = chunk.next.load(Ordering::Acquire);
+                if next.is_null() {
+                    let new = SyncChunk::new(chunk.cap().saturating_mul(2));
+                    match chunk.next.compare_exchange(ptr::null_mut(), new,
+                                                      Ordering::AcqRel, Ordering::Acquire) {
+                        Ok(_) => next = new,
+                        Err(actual) => {
+                            drop(Box::from_raw(new));
+                            next = actual;
+                        }
+                    }
+                }
+                let _ = self.tail.compare_exchange(tail, next, Ordering::AcqRel, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Iterates over the elements whose pushes have completed, in
+    // order, stopping at the first slot still being written
+    pub fn iter(&self) -> SyncIter<'_, T> {
+        SyncIter {
+            chunk: self.head,
+            index: 0,
+            _ph: PhantomData
+        }
+    }
+}
+
+impl<T> Default for SyncChain<T> {
+    fn default() -> Self {
+        SyncChain::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SyncChain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Drop for SyncChain<T> {
+    fn drop(&mut self) {
+        let mut cur = self.head;
+        while !cur.is_null() {
+            unsafe {
+                let chunk = Box::from_raw(cur);
+                for (slot, ready) in chunk.slots.iter().zip(chunk.ready.iter()) {
+                    if ready.load(Ordering::Relaxed) {
+                        ptr::drop_in_place(slot.get() as *mut T);
+                    }
+                }
+                cur = chunk.next.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<'a, T: 'a> IntoIterator for &'a SyncChain<T> {
+    type Item = &'a T;
+    type IntoIter = SyncIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct SyncIter<'a, T: 'a> {
+    chunk: *const SyncChunk<T>,
+    index: usize,
+    _ph: PhantomData<&'a T>
+}
+
+impl<'a, T> Iterator for SyncIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        while !self.chunk.is_null() {
+            unsafe {
+                let chunk = &*self.chunk;
+                if self.index == chunk.cap() {
+                    self.chunk = chunk.next.load(Ordering::Acquire);
+                    self.index = 0;
+                    continue
+                }
+                if !chunk.ready[self.index].load(Ordering::Acquire) {
+                    return None
+                }
+                let elem = &*(chunk.slots[self.index].get() as *const T);
+                self.index += 1;
+                return Some(elem)
+            }
+        }
+        None
+    }
+}
+
+// A DynChain that any number of threads can push to at once.  Each
+// element's fence post folds in the backward link of the one before
+// it, and records sit back to back, so claiming a slot and linking it
+// are serialized: a push holds the lock only while it reserves bytes
+// and writes the fence post and metadata.  Constructing the element
+// and moving it into its slot happen after the lock is released, so
+// pushers of large or slow-to-build values don't hold each other up.
+// Reading the elements takes exclusive access or ownership, once the
+// pushing threads are done.
+pub struct SyncDynChain<'gt, E: ?Sized, S=Unsize, M=()> {
+    inner: Mutex<DynChain<'gt, E, S, M>>
+}
+
+impl<'gt, E: ?Sized, S, M> SyncDynChain<'gt, E, S, M> {
+    pub const fn new() -> Self {
+        SyncDynChain {
+            inner: Mutex::new(DynChain::new())
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        SyncDynChain {
+            inner: Mutex::new(DynChain::with_capacity(cap))
+        }
+    }
+
+    // Linking can't panic partway, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, DynChain<'gt, E, S, M>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Elements whose slots have been claimed, including any still
+    // being written
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn push<T: Send + 'gt>(&self, elem: T) -> &T where S: Store<T, E>, M: Copy + Default {
+        match self.try_push(elem) {
+            Ok(elem) => elem,
+            Err(_) => panic!("SyncDynChain: failed to allocate chunk!")
+        }
+    }
+
+    pub fn try_push<T: Send + 'gt>(&self, elem: T) -> Result<&T, T>
+            where S: Store<T, E>, M: Copy + Default {
+        let slot = match unsafe { self.lock().link::<T>(M::default()) } {
+            Ok(slot) => slot,
+            Err(_) => return Err(elem)
+        };
+        // The slot is ours alone once linked: chunks never move, and
+        // nothing reads the elements without `&mut self`
+        unsafe {
+            ptr::write(slot, elem);
+            Ok(&*slot)
+        }
+    }
+
+    // Pushes the value returned by `f`.  Unlike `DynChain::emplace`,
+    // `f` runs before a slot is claimed, outside the lock, and the
+    // value is then moved in; a panic in `f` leaves the chain as it
+    // was.
+    pub fn emplace<T: Send + 'gt, F>(&self, f: F) -> &T
+            where F: FnOnce() -> T, S: Store<T, E>, M: Copy + Default {
+        self.push(f())
+    }
+
+    // The chain itself, for reading once pushes are done
+    pub fn get_mut(&mut self) -> &mut DynChain<'gt, E, S, M> {
+        self.inner.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn into_inner(self) -> DynChain<'gt, E, S, M> {
+        self.inner.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<'gt, E: ?Sized, S, M> Default for SyncDynChain<'gt, E, S, M> {
+    fn default() -> Self {
+        SyncDynChain::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn concurrent_push() {
+        let chain = Arc::new(SyncChain::with_capacity(4));
+        let threads: Vec<_> = (0..4).map(|t| {
+            let chain = chain.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    assert_eq!(*chain.push(t * 1000 + i), t * 1000 + i);
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut all: Vec<usize> = chain.iter().cloned().collect();
+        all.sort();
+        assert!(all.into_iter().eq(0..4000));
+    }
+
+    #[test]
+    fn concurrent_dyn_push() {
+        let chain: SyncDynChain<dyn fmt::Debug + Send> = SyncDynChain::new();
+        thread::scope(|scope| {
+            for t in 0..4u32 {
+                let chain = &chain;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        if i % 2 == 0 {
+                            assert_eq!(*chain.push(t * 100 + i), t * 100 + i);
+                        } else {
+                            chain.push(format!("{}", t * 100 + i));
+                        }
+                    }
+                });
+            }
+        });
+        let chain = chain.into_inner();
+        assert_eq!(chain.len(), 400);
+        let mut all: Vec<u32> = chain.iter()
+            .map(|elem| format!("{:?}", elem).trim_matches('"').parse().unwrap())
+            .collect();
+        all.sort();
+        assert!(all.into_iter().eq(0..400));
+    }
+
+    #[test]
+    fn concurrent_dyn_emplace() {
+        let chain: SyncDynChain<dyn fmt::Debug + Send> = SyncDynChain::with_capacity(16);
+        thread::scope(|scope| {
+            for t in 0..4usize {
+                let chain = &chain;
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let v = chain.emplace(|| vec![t; i]);
+                        assert_eq!(*v, vec![t; i]);
+                    }
+                });
+            }
+        });
+        let mut chain = chain;
+        assert_eq!(chain.get_mut().len(), 200);
+        assert_eq!(chain.get_mut().iter().rev().count(), 200);
+    }
+}
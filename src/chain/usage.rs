@@ -0,0 +1,90 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Reports heap memory a value owns beyond its own size, so the
+// deep size of a chain or zone can account for what its elements
+// point to.  Shared pointers count nothing, since their contents
+// are not owned by any one element.
+pub trait MemUsage {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! no_heap {
+    ($($t:ty),*) => {
+        $(impl MemUsage for $t {
+            #[inline]
+            fn heap_size(&self) -> usize { 0 }
+        })*
+    }
+}
+
+no_heap!(bool, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, ());
+
+impl<T: ?Sized> MemUsage for &T {
+    fn heap_size(&self) -> usize { 0 }
+}
+
+impl<T: ?Sized> MemUsage for Rc<T> {
+    fn heap_size(&self) -> usize { 0 }
+}
+
+impl<T: ?Sized> MemUsage for Arc<T> {
+    fn heap_size(&self) -> usize { 0 }
+}
+
+impl MemUsage for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: MemUsage> MemUsage for Box<T> {
+    fn heap_size(&self) -> usize {
+        mem::size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T: MemUsage> MemUsage for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, MemUsage::heap_size)
+    }
+}
+
+impl<T: MemUsage> MemUsage for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<T>() +
+            self.iter().map(MemUsage::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: MemUsage> MemUsage for VecDeque<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<T>() +
+            self.iter().map(MemUsage::heap_size).sum::<usize>()
+    }
+}
+
+// Estimated from capacity; the table's control bytes are ignored
+impl<K: MemUsage + Eq + Hash, V: MemUsage> MemUsage for HashMap<K, V> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<(K, V)>() +
+            self.iter().map(|(k, v)| k.heap_size() + v.heap_size()).sum::<usize>()
+    }
+}
+
+macro_rules! tuple {
+    ($($t:ident . $i:tt),*) => {
+        impl<$($t: MemUsage),*> MemUsage for ($($t,)*) {
+            fn heap_size(&self) -> usize {
+                0 $(+ self.$i.heap_size())*
+            }
+        }
+    }
+}
+
+tuple!(A.0, B.1);
+tuple!(A.0, B.1, C.2);
+tuple!(A.0, B.1, C.2, D.3);
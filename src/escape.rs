@@ -0,0 +1,166 @@
+// Escaping for text written into byte zones, so escaped output is
+// built directly in the zone rather than in a temporary String.
+use super::zone::Zone;
+use std::fmt::{self, Write};
+
+// Escaping rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    // Contents of a JSON string literal, without the quotes
+    Json,
+    // HTML text or attribute values
+    Html
+}
+
+impl Escape {
+    // Replacement for `c`, if it needs one.  JSON control characters
+    // without a short form are handled separately.
+    fn replacement(self, c: char) -> Option<&'static str> {
+        match self {
+            Escape::Json => match c {
+                '"' => Some("\\\""),
+                '\\' => Some("\\\\"),
+                '\n' => Some("\\n"),
+                '\r' => Some("\\r"),
+                '\t' => Some("\\t"),
+                '\x08' => Some("\\b"),
+                '\x0c' => Some("\\f"),
+                _ => None
+            },
+            Escape::Html => match c {
+                '&' => Some("&amp;"),
+                '<' => Some("&lt;"),
+                '>' => Some("&gt;"),
+                '"' => Some("&quot;"),
+                '\'' => Some("&#39;"),
+                _ => None
+            }
+        }
+    }
+
+    fn is_control(self, c: char) -> bool {
+        self == Escape::Json && c < '\x20'
+    }
+
+    // Length of `s` once escaped
+    pub fn escaped_len(self, s: &str) -> usize {
+        s.chars().map(|c| match self.replacement(c) {
+            Some(r) => r.len(),
+            None if self.is_control(c) => 6,
+            None => c.len_utf8()
+        }).sum()
+    }
+}
+
+// Escapes everything written through it before passing it on
+pub struct EscapeWriter<W> {
+    inner: W,
+    escape: Escape
+}
+
+impl<W: fmt::Write> EscapeWriter<W> {
+    pub fn new(inner: W, escape: Escape) -> Self {
+        EscapeWriter {
+            inner: inner,
+            escape: escape
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for EscapeWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Pass through runs that need no escaping in one piece
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let control = self.escape.is_control(c);
+            let replacement = self.escape.replacement(c);
+            if replacement.is_none() && !control {
+                continue
+            }
+            self.inner.write_str(&s[start..i])?;
+            match replacement {
+                Some(r) => self.inner.write_str(r)?,
+                None => write!(self.inner, "\\u{:04x}", c as u32)?
+            }
+            start = i + c.len_utf8();
+        }
+        self.inner.write_str(&s[start..])
+    }
+}
+
+// Formats a value through an EscapeWriter, so only its text is
+// escaped.  Format options such as width aren't passed on.
+pub struct Escaped<'a, T: ?Sized + 'a> {
+    value: &'a T,
+    escape: Escape
+}
+
+impl<'a, T: fmt::Display + ?Sized> fmt::Display for Escaped<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(EscapeWriter::new(f, self.escape), "{}", self.value)
+    }
+}
+
+impl<'a, T: fmt::Debug + ?Sized> fmt::Debug for Escaped<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(EscapeWriter::new(f, self.escape), "{:?}", self.value)
+    }
+}
+
+impl Escape {
+    // Wraps `value` so that formatting it escapes its text
+    pub fn arg<T: ?Sized>(self, value: &T) -> Escaped<'_, T> {
+        Escaped {
+            value: value,
+            escape: self
+        }
+    }
+}
+
+// Formats into a byte zone like `Zone::format`, escaping each
+// argument but not the template itself:
+//
+//     format_escaped!(zone, Escape::Html, "<b>{}</b>", name)
+//
+// Only positional arguments are escaped.  Names captured inline, as
+// in "{name}", bypass the macro and are written as they are.
+#[macro_export]
+macro_rules! format_escaped {
+    ($zone:expr, $escape:expr, $fmt:expr $(, $arg:expr)* $(,)*) => {{
+        let escape: $crate::escape::Escape = $escape;
+        $zone.format(format_args!($fmt $(, escape.arg(&$arg))*))
+    }}
+}
+
+impl Zone<u8> {
+    // Copies `s` into the zone with escaping applied
+    pub fn escape(&self, escape: Escape, s: &str) -> &str {
+        let mut quota = self.alloc_str(escape.escaped_len(s));
+        let _ = EscapeWriter::new(&mut quota, escape).write_str(s);
+        quota.into_slice()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape() {
+        let zone = Zone::new();
+        assert_eq!(zone.escape(Escape::Json, "say \"hi\"\n\\ \x01 \u{e9}"),
+                   "say \\\"hi\\\"\\n\\\\ \\u0001 \u{e9}");
+        assert_eq!(zone.escape(Escape::Html, "<a href='x'>&</a>"),
+                   "&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;");
+        assert_eq!(zone.escape(Escape::Html, "plain"), "plain");
+        let name = "<script>";
+        assert_eq!(format_escaped!(zone, Escape::Html, "<b>{}</b> x{}", name, 3),
+                   "<b>&lt;script&gt;</b> x3");
+        assert_eq!(format_escaped!(zone, Escape::Json, "{{\"k\": \"{}\"}}", "a\"b\n"),
+                   "{\"k\": \"a\\\"b\\n\"}");
+    }
+}
@@ -0,0 +1,260 @@
+// A global allocator adapter for measuring how much of a program's
+// allocation traffic could be served by a zone.  Install it and mark
+// the code of interest:
+//
+//     #[global_allocator]
+//     static ALLOC: ScopedArenaAlloc = ScopedArenaAlloc::new(256);
+//
+//     let (result, stats) = scoped_arena_alloc(|| work());
+//
+// Inside the scope, allocations up to the threshold come from a
+// thread-local chain of chunks instead of the system allocator.
+// Freeing one only decrements a count; the chunks are reused once a
+// scope ends with nothing allocated from them still alive.  Memory
+// that outlives its scope keeps them from being reused, so this is
+// meant for experiments rather than production.
+//
+// Every allocation, arena or not, carries a 16-byte header so that
+// `dealloc` can tell where it came from.
+use super::chain::Chain;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const HEADER: usize = 16;
+
+// What the thread's arena has handed out that hasn't been freed.
+// Allocations can be freed from other threads, so each thread's count
+// lives at a fixed address for good.
+struct Live(AtomicUsize);
+
+// The thread's chunks.  If the thread exits with allocations from them
+// still alive elsewhere, they are leaked rather than freed.
+struct ThreadArena {
+    chain: UnsafeCell<Chain<u8>>,
+    live: Cell<*const Live>
+}
+
+impl Drop for ThreadArena {
+    fn drop(&mut self) {
+        let live = self.live.get();
+        if !live.is_null() && unsafe { (*live).0.load(Ordering::Acquire) } != 0 {
+            mem::forget(mem::take(self.chain.get_mut()));
+        }
+    }
+}
+
+// Allocation counts for a scope
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScopeStats {
+    pub arena_allocs: usize,
+    pub arena_bytes: usize,
+    // Allocations in the scope that were too large or too strictly
+    // aligned for the arena
+    pub system_allocs: usize,
+    pub system_bytes: usize
+}
+
+thread_local! {
+    static ARENA: ThreadArena = const {
+        ThreadArena {
+            chain: UnsafeCell::new(Chain::new()),
+            live: Cell::new(ptr::null())
+        }
+    };
+    // Nesting depth of scoped_arena_alloc
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    // Set while the arena itself is allocating, so its chunks come
+    // from the system
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+    static STATS: Cell<ScopeStats> = const {
+        Cell::new(ScopeStats {
+            arena_allocs: 0,
+            arena_bytes: 0,
+            system_allocs: 0,
+            system_bytes: 0
+        })
+    };
+}
+
+pub struct ScopedArenaAlloc {
+    threshold: usize
+}
+
+impl ScopedArenaAlloc {
+    // Allocations of at most `threshold` bytes made within a scope go
+    // to the arena
+    pub const fn new(threshold: usize) -> Self {
+        ScopedArenaAlloc {
+            threshold: threshold
+        }
+    }
+
+    // Allocates from the thread's arena, or returns null if the
+    // thread is exiting
+    unsafe fn arena_alloc(&self, layout: Layout) -> *mut u8 {
+        BUSY.with(|busy| busy.set(true));
+        let ptr = ARENA.try_with(|arena| {
+            let mut live = arena.live.get();
+            if live.is_null() {
+                live = System.alloc(Layout::new::<Live>()) as *mut Live;
+                if live.is_null() {
+                    return ptr::null_mut()
+                }
+                ptr::write(live as *mut Live, Live(AtomicUsize::new(0)));
+                arena.live.set(live);
+            }
+            // The header needs no more than 16-byte alignment, and
+            // the chain's chunks start on 8-byte boundaries
+            let chain = &*arena.chain.get();
+            let (origin, _) = chain.raw_reserve(HEADER + layout.size() + HEADER - 1);
+            let pad = (HEADER - (origin as usize & (HEADER - 1))) & (HEADER - 1);
+            chain.raw_add_len(pad + HEADER + layout.size());
+            let ptr = origin.offset((pad + HEADER) as isize);
+            *(ptr.offset(-(HEADER as isize)) as *mut *const Live) = live;
+            (*live).0.fetch_add(1, Ordering::Relaxed);
+            ptr
+        }).unwrap_or(ptr::null_mut());
+        BUSY.with(|busy| busy.set(false));
+        ptr
+    }
+
+    // Layout of the system allocation holding `layout` behind a header
+    fn system_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let offset = cmp::max(HEADER, layout.align());
+        let size = layout.size().checked_add(offset)?;
+        Layout::from_size_align(size, offset).ok().map(|outer| (outer, offset))
+    }
+}
+
+fn record<F: FnOnce(&mut ScopeStats)>(f: F) {
+    let _ = STATS.try_with(|stats| {
+        let mut s = stats.get();
+        f(&mut s);
+        stats.set(s);
+    });
+}
+
+unsafe impl GlobalAlloc for ScopedArenaAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let in_scope = DEPTH.try_with(|depth| depth.get() != 0).unwrap_or(false) &&
+            !BUSY.with(|busy| busy.get());
+        if in_scope && layout.size() <= self.threshold && layout.align() <= HEADER {
+            let ptr = self.arena_alloc(layout);
+            if !ptr.is_null() {
+                record(|s| {
+                    s.arena_allocs += 1;
+                    s.arena_bytes += layout.size();
+                });
+                return ptr
+            }
+        }
+        if in_scope {
+            record(|s| {
+                s.system_allocs += 1;
+                s.system_bytes += layout.size();
+            });
+        }
+        let (outer, offset) = match Self::system_layout(layout) {
+            Some(outer) => outer,
+            None => return ptr::null_mut()
+        };
+        let base = System.alloc(outer);
+        if base.is_null() {
+            return base
+        }
+        let ptr = base.offset(offset as isize);
+        *(ptr.offset(-(HEADER as isize)) as *mut *const Live) = ptr::null();
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let live = *(ptr.offset(-(HEADER as isize)) as *const *const Live);
+        if !live.is_null() {
+            (*live).0.fetch_sub(1, Ordering::Release);
+            return
+        }
+        let (outer, offset) = Self::system_layout(layout).unwrap();
+        System.dealloc(ptr.offset(-(offset as isize)), outer);
+    }
+}
+
+// Runs `f` with small allocations on this thread served by the arena
+// (when ScopedArenaAlloc is the global allocator), returning its
+// result and what it allocated.  Scopes nest; counts for an inner
+// scope are included in the outer one.
+pub fn scoped_arena_alloc<R, F: FnOnce() -> R>(f: F) -> (R, ScopeStats) {
+    // Leaves the scope even if `f` panics, adding its counts to the
+    // enclosing scope's
+    struct Exit(ScopeStats);
+
+    impl Drop for Exit {
+        fn drop(&mut self) {
+            let depth = DEPTH.with(|depth| {
+                depth.set(depth.get() - 1);
+                depth.get()
+            });
+            if depth == 0 {
+                // Reuse the chunks if nothing from them is alive
+                let _ = ARENA.try_with(|arena| {
+                    let live = arena.live.get();
+                    if !live.is_null() && unsafe { (*live).0.load(Ordering::Acquire) } == 0 {
+                        unsafe { (*arena.chain.get()).clear() }
+                    }
+                });
+            }
+            let outer = self.0;
+            STATS.with(|stats| {
+                let inner = stats.get();
+                stats.set(ScopeStats {
+                    arena_allocs: outer.arena_allocs + inner.arena_allocs,
+                    arena_bytes: outer.arena_bytes + inner.arena_bytes,
+                    system_allocs: outer.system_allocs + inner.system_allocs,
+                    system_bytes: outer.system_bytes + inner.system_bytes
+                })
+            });
+        }
+    }
+
+    let exit = Exit(STATS.with(|stats| stats.replace(ScopeStats::default())));
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let result = f();
+    let stats = STATS.with(|stats| stats.get());
+    drop(exit);
+    (result, stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[global_allocator]
+    static ALLOC: ScopedArenaAlloc = ScopedArenaAlloc::new(64);
+
+    #[test]
+    fn scoped() {
+        let (kept, stats) = scoped_arena_alloc(|| {
+            let small: Vec<Box<u64>> = (0..10).map(Box::new).collect();
+            let big = vec![0u8; 1000];
+            let (_, inner) = scoped_arena_alloc(|| Box::new(1u32));
+            assert_eq!(inner.arena_allocs, 1);
+            assert_eq!(small.iter().map(|b| **b).sum::<u64>(), 45);
+            drop(big);
+            Box::new([7u8; 16])
+        });
+        // Eleven boxes plus the one from the inner scope; the 80-byte
+        // Vec and 1000-byte buffer are over the threshold
+        assert_eq!(stats, ScopeStats {
+            arena_allocs: 12,
+            arena_bytes: 80 + 4 + 16,
+            system_allocs: 2,
+            system_bytes: 1080
+        });
+        // Outlives its scope and can be freed from another thread
+        assert_eq!(thread::spawn(move || *kept).join().unwrap(), [7; 16]);
+    }
+}
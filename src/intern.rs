@@ -0,0 +1,217 @@
+use super::chain::Chain;
+use super::zone::Zone;
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::fmt;
+use std::ffi::{CStr, CString, NulError};
+use std::str;
+
+// A numeric handle for a value stored in an Interner.  Ids are
+// handed out densely in interning order, so they can be written
+// out as plain integers and mapped back with `from_index`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id(u32);
+
+impl Id {
+    #[inline]
+    pub fn from_index(index: usize) -> Id {
+        assert!(index <= u32::MAX as usize);
+        Id(index as u32)
+    }
+
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Debug for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Id({})", self.0)
+    }
+}
+
+// Hashes and compares by the pointed-to value.  Values live in
+// the chain, which never moves them, so the pointer stays valid
+// for as long as the interner does.
+struct Key<T: ?Sized>(*const T);
+
+impl<T: ?Sized + Hash> Hash for Key<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        unsafe { (*self.0).hash(state) }
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for Key<T> {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { *self.0 == *other.0 }
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Key<T> {}
+
+impl<T: ?Sized> Borrow<T> for Key<T> {
+    fn borrow(&self) -> &T {
+        unsafe { &*self.0 }
+    }
+}
+
+struct Index<T> {
+    ids: HashMap<Key<T>, Id>,
+    values: Vec<*const T>
+}
+
+// Maps values to Ids and back.  Each distinct value is stored once,
+// and references to it remain valid for the life of the interner.
+pub struct Interner<T> {
+    chain: Chain<T>,
+    index: RefCell<Index<T>>
+}
+
+impl<T: Eq + Hash> Interner<T> {
+    pub fn new() -> Self {
+        Interner {
+            chain: Chain::new(),
+            index: RefCell::new(Index {
+                ids: HashMap::new(),
+                values: Vec::new()
+            })
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Interner {
+            chain: Chain::with_capacity(cap),
+            index: RefCell::new(Index {
+                ids: HashMap::with_capacity(cap),
+                values: Vec::with_capacity(cap)
+            })
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.borrow().values.len()
+    }
+
+    // Returns the Id for `value`, storing it if it hasn't been seen
+    pub fn intern(&self, value: T) -> Id {
+        let mut index = self.index.borrow_mut();
+        if let Some(&id) = index.ids.get(&value) {
+            return id
+        }
+        let id = Id::from_index(index.values.len());
+        let ptr = self.chain.push(value) as *const T;
+        index.values.push(ptr);
+        index.ids.insert(Key(ptr), id);
+        id
+    }
+
+    // Looks up the Id of `value` without storing it
+    pub fn get(&self, value: &T) -> Option<Id> {
+        self.index.borrow().ids.get(value).cloned()
+    }
+
+    pub fn resolve(&self, id: Id) -> Option<&T> {
+        self.index.borrow().values.get(id.index()).map(|&ptr| unsafe { &*ptr })
+    }
+
+    pub fn iter(&self) -> ::chain::Iter<'_, T> {
+        self.chain.iter()
+    }
+}
+
+impl<T: Eq + Hash> Default for Interner<T> {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+// Deduplicating pool of NUL-terminated strings for passing to C.
+// Interning the same string again returns the existing copy
+// instead of allocating a new one.
+pub struct CStrPool {
+    bytes: Zone<u8>,
+    // Keys cover each string without its terminator
+    strs: RefCell<HashMap<Key<str>, *const CStr>>
+}
+
+impl CStrPool {
+    pub fn new() -> Self {
+        CStrPool {
+            bytes: Zone::new(),
+            strs: RefCell::new(HashMap::new())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strs.borrow().len()
+    }
+
+    // Returns a pooled copy of `s` with a terminating NUL, failing
+    // if `s` contains an interior NUL
+    pub fn intern(&self, s: &str) -> Result<&CStr, NulError> {
+        if let Some(&cstr) = self.strs.borrow().get(s) {
+            return Ok(unsafe { &*cstr })
+        }
+        if s.as_bytes().contains(&0) {
+            // Only CString can construct the error
+            return Err(CString::new(s).unwrap_err())
+        }
+        let mut quota = self.bytes.alloc(s.len() + 1);
+        quota.fill(s.as_bytes());
+        let _ = quota.push(0);
+        let bytes = quota.into_slice();
+        unsafe {
+            let key = str::from_utf8_unchecked(&bytes[..s.len()]) as *const str;
+            let cstr = CStr::from_bytes_with_nul_unchecked(bytes);
+            self.strs.borrow_mut().insert(Key(key), cstr);
+            Ok(cstr)
+        }
+    }
+}
+
+impl Default for CStrPool {
+    fn default() -> Self {
+        CStrPool::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let interner = Interner::with_capacity(1);
+        let words = ["foo", "bar", "foo", "baz", "bar"];
+        let ids: Vec<Id> = words.iter().map(|w| interner.intern(w.to_string())).collect();
+        assert_eq!(ids[0], ids[2]);
+        assert_eq!(ids[1], ids[4]);
+        assert_eq!(interner.len(), 3);
+        for (w, &id) in words.iter().zip(ids.iter()) {
+            assert_eq!(interner.resolve(id).map(|s| &s[..]), Some(*w));
+            assert_eq!(interner.resolve(Id::from_index(id.index())), interner.resolve(id));
+        }
+        assert_eq!(interner.get(&"baz".to_string()), Some(ids[3]));
+        assert_eq!(interner.get(&"qux".to_string()), None);
+        assert_eq!(interner.resolve(Id::from_index(3)), None);
+        let order: Vec<&str> = interner.iter().map(|s| &s[..]).collect();
+        assert_eq!(order, ["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn cstr_pool() {
+        let pool = CStrPool::new();
+        let gl = pool.intern("glGenBuffers").unwrap();
+        let vk = pool.intern("vkCreateInstance").unwrap();
+        assert_eq!(gl.to_bytes_with_nul(), b"glGenBuffers\0");
+        assert_eq!(vk.to_str(), Ok("vkCreateInstance"));
+        assert_eq!(pool.intern("glGenBuffers").unwrap().as_ptr(), gl.as_ptr());
+        assert_eq!(pool.intern("").unwrap().to_bytes(), b"");
+        assert!(pool.intern("a\0b").is_err());
+        assert_eq!(pool.len(), 3);
+    }
+}
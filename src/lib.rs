@@ -1,4 +1,33 @@
-#![feature(alloc, core, optin_builtin_traits)]
+#![cfg_attr(feature = "nightly", feature(unsize))]
+// House style: explicit field inits and pointer offsets, and arenas
+// that hand out `&mut` from `&self` by design
+#![allow(clippy::redundant_field_names,
+         clippy::ptr_offset_with_cast,
+         clippy::len_without_is_empty,
+         clippy::mut_from_ref,
+         clippy::missing_safety_doc,
+         clippy::module_inception,
+         clippy::type_complexity)]
+
+#[cfg(all(feature = "mmap", unix))]
+extern crate libc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 pub mod chain;
 pub mod zone;
+pub mod intern;
+pub mod arena;
+pub mod txn;
+#[cfg(feature = "escape")]
+pub mod escape;
+#[cfg(all(feature = "mmap", unix))]
+pub mod mmap;
+#[cfg(all(feature = "mmap", not(unix)))]
+compile_error!("the `mmap` feature needs mmap(2); MappedZone and ReservedZone have no backend for this target");
+#[cfg(feature = "global-alloc")]
+pub mod global;
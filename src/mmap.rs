@@ -0,0 +1,379 @@
+// Zones built directly on mmap rather than the heap.
+//
+// MappedZone's chunks are memory-mapped regions of a file.  The
+// kernel can write pages back to the file under memory pressure,
+// so outputs much larger than RAM can be built with the usual zone
+// interface.  Only unix targets are supported; there is no
+// CreateFileMapping backend, so the feature refuses to build on
+// Windows rather than quietly providing nothing.
+use super::zone::{Quota, QuotaSource, StrQuota};
+use libc;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Smallest region mapped at a time
+const MIN_REGION: usize = 1 << 20;
+
+struct Region {
+    ptr: *mut u8,
+    len: usize
+}
+
+pub struct MappedZone {
+    file: File,
+    // Bytes of the file mapped so far
+    size: Cell<usize>,
+    // Next free byte and end of the current region
+    cur: Cell<*mut u8>,
+    end: Cell<*mut u8>,
+    regions: RefCell<Vec<Region>>
+}
+
+impl MappedZone {
+    // Creates a zone backed by an anonymous temporary file, which
+    // is removed as soon as it is opened
+    pub fn temp() -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!("monotonic-{}-{}", process::id(),
+                                                COUNT.fetch_add(1, Ordering::Relaxed)));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        fs::remove_file(&path)?;
+        Ok(Self::with_file(file))
+    }
+
+    // Creates a zone that appends regions to `file`, which must be
+    // open for reading and writing.  Regions are padded out to the
+    // page size, so the file may end up larger than the data.
+    pub fn with_file(file: File) -> Self {
+        MappedZone {
+            file: file,
+            size: Cell::new(0),
+            cur: Cell::new(ptr::null_mut()),
+            end: Cell::new(ptr::null_mut()),
+            regions: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    // Maps a new region of at least `len` bytes at the end of the file
+    fn grow(&self, len: usize) -> io::Result<()> {
+        let page = page_size();
+        let want = cmp::max(cmp::max(len, self.size.get()), MIN_REGION);
+        let region = (want + page - 1) & !(page - 1);
+        let offset = self.size.get();
+        self.file.set_len((offset + region) as u64)?;
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), region,
+                       libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED,
+                       self.file.as_raw_fd(), offset as libc::off_t)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+        let ptr = ptr as *mut u8;
+        self.regions.borrow_mut().push(Region { ptr: ptr, len: region });
+        self.size.set(offset + region);
+        self.cur.set(ptr);
+        self.end.set(unsafe { ptr.offset(region as isize) });
+        Ok(())
+    }
+
+    // Allocates `len` zeroed bytes, mapping more of the file if needed
+    pub fn try_alloc(&self, len: usize) -> io::Result<&mut [u8]> {
+        // Nothing may be mapped yet, leaving `cur` null
+        if len == 0 {
+            return Ok(&mut [])
+        }
+        if (self.end.get() as usize - self.cur.get() as usize) < len {
+            self.grow(len)?;
+        }
+        let ptr = self.cur.get();
+        unsafe {
+            self.cur.set(ptr.offset(len as isize));
+            Ok(slice::from_raw_parts_mut(ptr, len))
+        }
+    }
+
+    pub fn alloc(&self, len: usize) -> &mut [u8] {
+        match self.try_alloc(len) {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("MappedZone: failed to map region: {}", e)
+        }
+    }
+
+    pub fn alloc_copy(&self, data: &[u8]) -> &mut [u8] {
+        let bytes = self.alloc(data.len());
+        bytes.copy_from_slice(data);
+        bytes
+    }
+
+    pub fn push_str(&self, s: &str) -> &str {
+        unsafe { str::from_utf8_unchecked(self.alloc_copy(s.as_bytes())) }
+    }
+
+    // Space for up to `len` bytes, written through the quota as with
+    // `Zone<u8>::alloc`.  Whatever isn't written is given back when
+    // the quota is dropped, unless something was allocated after it.
+    pub fn try_alloc_quota(&self, len: usize) -> io::Result<Quota<'_, u8>> {
+        let bytes = self.try_alloc(len)?;
+        unsafe { Ok(Quota::from_raw(bytes.as_mut_ptr(), len, self)) }
+    }
+
+    pub fn alloc_quota(&self, len: usize) -> Quota<'_, u8> {
+        match self.try_alloc_quota(len) {
+            Ok(quota) => quota,
+            Err(e) => panic!("MappedZone: failed to map region: {}", e)
+        }
+    }
+
+    pub fn alloc_str(&self, len: usize) -> StrQuota<'_> {
+        StrQuota(self.alloc_quota(len))
+    }
+
+    pub fn format(&self, args: fmt::Arguments) -> &str {
+        let mut len = 32;
+        loop {
+            let mut quota = self.alloc_str(len);
+            if let Ok(()) = fmt::write(&mut quota, args) {
+                return quota.into_slice()
+            }
+            quota.clear();
+            // Regions don't grow on their own the way chunks do
+            len = quota.capacity() * 2;
+        }
+    }
+}
+
+impl QuotaSource<u8> for MappedZone {
+    unsafe fn shrink(&self, origin: *mut u8, cap: usize, len: usize) {
+        if self.cur.get() == origin.offset(cap as isize) {
+            // Allocations are handed out zeroed
+            ptr::write_bytes(origin.offset(len as isize), 0, cap - len);
+            self.cur.set(origin.offset(len as isize));
+        }
+    }
+}
+
+impl Drop for MappedZone {
+    fn drop(&mut self) {
+        for region in self.regions.borrow().iter() {
+            unsafe {
+                libc::munmap(region.ptr as *mut libc::c_void, region.len);
+            }
+        }
+    }
+}
+
+// Smallest amount of a reservation committed at a time
+const MIN_COMMIT: usize = 64 << 10;
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// A byte zone that reserves a fixed range of address space up front
+// and commits pages as allocation reaches them.  Everything
+// allocated is contiguous, so the whole zone can be viewed as one
+// slice, and rewinding gives the pages back to the system.
+//
+// Built on mmap and mprotect, so like MappedZone it is unix only;
+// a VirtualAlloc reserve/commit version for Windows doesn't exist yet.
+pub struct ReservedZone {
+    base: *mut u8,
+    reserved: usize,
+    // Bytes allocated and bytes made accessible so far
+    len: Cell<usize>,
+    committed: Cell<usize>
+}
+
+impl ReservedZone {
+    // Reserves `size` bytes of address space, rounded up to whole
+    // pages.  No memory is committed yet.
+    pub fn new(size: usize) -> io::Result<Self> {
+        let page = page_size();
+        let reserved = size.checked_add(page - 1)
+                            .ok_or(io::Error::new(io::ErrorKind::InvalidInput,
+                                                  "reservation too large"))? & !(page - 1);
+        let base = unsafe {
+            libc::mmap(ptr::null_mut(), reserved, libc::PROT_NONE,
+                       libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                       -1, 0)
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(ReservedZone {
+            base: base as *mut u8,
+            reserved: reserved,
+            len: Cell::new(0),
+            committed: Cell::new(0)
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    // Size of the reserved range, which allocation can never exceed
+    #[inline]
+    pub fn reserved(&self) -> usize {
+        self.reserved
+    }
+
+    // Makes the reservation accessible up to at least `end`
+    fn commit(&self, end: usize) -> io::Result<()> {
+        let page = page_size();
+        let start = self.committed.get();
+        let want = cmp::max(end - start, MIN_COMMIT);
+        let upto = cmp::min((start + want + page - 1) & !(page - 1), self.reserved);
+        let res = unsafe {
+            libc::mprotect(self.base.offset(start as isize) as *mut libc::c_void,
+                           upto - start, libc::PROT_READ | libc::PROT_WRITE)
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        self.committed.set(upto);
+        Ok(())
+    }
+
+    // Allocates `len` zeroed bytes directly after the previous
+    // allocation, failing once the reservation is used up
+    pub fn try_alloc(&self, len: usize) -> io::Result<&mut [u8]> {
+        let start = self.len.get();
+        let end = match start.checked_add(len) {
+            Some(end) if end <= self.reserved => end,
+            _ => return Err(io::Error::other("ReservedZone: reservation exhausted"))
+        };
+        if end > self.committed.get() {
+            self.commit(end)?;
+        }
+        self.len.set(end);
+        unsafe { Ok(slice::from_raw_parts_mut(self.base.offset(start as isize), len)) }
+    }
+
+    pub fn alloc(&self, len: usize) -> &mut [u8] {
+        match self.try_alloc(len) {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("ReservedZone: failed to commit pages: {}", e)
+        }
+    }
+
+    pub fn alloc_copy(&self, data: &[u8]) -> &mut [u8] {
+        let bytes = self.alloc(data.len());
+        bytes.copy_from_slice(data);
+        bytes
+    }
+
+    // Everything allocated so far, as one slice
+    pub fn as_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.base, self.len.get()) }
+    }
+
+    // Discards everything allocated after the first `len` bytes and
+    // returns the pages past that point to the system.  Later
+    // allocations see zeroed memory again.
+    pub fn rewind(&mut self, len: usize) {
+        if len >= self.len.get() {
+            return
+        }
+        let page = page_size();
+        let keep = (len + page - 1) & !(page - 1);
+        let committed = self.committed.get();
+        if keep < committed {
+            unsafe {
+                let ptr = self.base.offset(keep as isize) as *mut libc::c_void;
+                libc::madvise(ptr, committed - keep, libc::MADV_DONTNEED);
+                libc::mprotect(ptr, committed - keep, libc::PROT_NONE);
+            }
+            self.committed.set(keep);
+        }
+        // Bytes of the last kept page past `len` are not released,
+        // so clear them by hand
+        unsafe {
+            ptr::write_bytes(self.base.offset(len as isize), 0, cmp::min(keep, self.len.get()) - len);
+        }
+        self.len.set(len);
+    }
+}
+
+impl Drop for ReservedZone {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.reserved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mapped() {
+        let zone = MappedZone::temp().unwrap();
+        assert!(zone.alloc(0).is_empty());
+        assert_eq!(zone.push_str(""), "");
+        let hello = zone.push_str("hello");
+        let big = zone.alloc(3 << 20);
+        big[(3 << 20) - 1] = 1;
+        let tail = zone.alloc_copy(b"tail");
+        assert_eq!(hello, "hello");
+        assert_eq!((big[0], big[(3 << 20) - 1]), (0, 1));
+        assert_eq!(tail, b"tail");
+        assert!(zone.file().metadata().unwrap().len() >= (4 << 20));
+    }
+
+    #[test]
+    fn mapped_text() {
+        let zone = MappedZone::temp().unwrap();
+        let mut quota = zone.alloc_quota(16);
+        quota.fill(b"abc");
+        assert_eq!(quota.into_slice(), b"abc");
+        let mut name = zone.alloc_str(8);
+        name.fill("xyz");
+        name.make_ascii_uppercase();
+        let name = name.into_slice();
+        let long = "y".repeat(100);
+        let text = zone.format(format_args!("{}-{}", name, long));
+        let after = zone.alloc(2);
+        assert_eq!(name, "XYZ");
+        assert_eq!(text, format!("XYZ-{}", long));
+        // Unwritten space is given back zeroed
+        assert_eq!(after.as_ptr() as usize, text.as_ptr() as usize + text.len());
+        assert_eq!(after, [0, 0]);
+    }
+
+    #[test]
+    fn reserved() {
+        let mut zone = ReservedZone::new(1 << 30).unwrap();
+        assert_eq!(zone.reserved(), 1 << 30);
+        let head = zone.alloc_copy(b"head").as_ptr();
+        let big = zone.alloc(1 << 20);
+        big[(1 << 20) - 1] = 9;
+        assert_eq!(big.as_ptr() as usize, head as usize + 4);
+        assert_eq!(zone.len(), 4 + (1 << 20));
+        assert_eq!(&zone.as_slice()[..4], b"head");
+
+        zone.rewind(2);
+        assert_eq!(zone.as_slice(), b"he");
+        let again = zone.alloc(1 << 20);
+        assert!(again.iter().all(|&b| b == 0));
+        assert!(zone.try_alloc(1 << 30).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// Reserve-then-commit across several containers.  A record spread
+// over more than one container (a node in one zone, its name in
+// another, its spans in a chain) is staged in each of them first.
+// Staging is the only step that can fail, and staged elements aren't
+// part of any container until committed, so a failed allocation
+// never leaves part of a record visible:
+//
+//     let mut node = Staged::try_new(&mut nodes, 1)?;
+//     let mut name = Staged::try_new(&mut names, text.len())?;
+//     // ...fill both...
+//     let (node, name) = (node, name).commit();
+//
+// Dropping a staged reservation drops whatever was written to it.
+use super::chain::{AllocError, Chain};
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::slice;
+
+// Containers that can stage contiguous space for a transaction
+pub trait TxnTarget {
+    type Elem;
+
+    // Reserves contiguous space for at least `len` elements without
+    // adding to the length, returning it and its capacity
+    #[doc(hidden)]
+    fn txn_reserve(&mut self, len: usize) -> Result<(*mut Self::Elem, usize), AllocError>;
+
+    // Adds `len` elements written to the reserved space
+    #[doc(hidden)]
+    unsafe fn txn_commit(&mut self, len: usize);
+}
+
+impl<T> TxnTarget for Chain<T> {
+    type Elem = T;
+
+    fn txn_reserve(&mut self, len: usize) -> Result<(*mut T, usize), AllocError> {
+        self.raw_try_reserve(len)
+    }
+
+    unsafe fn txn_commit(&mut self, len: usize) {
+        self.raw_add_len(len)
+    }
+}
+
+// Space staged in one container.  The container stays mutably
+// borrowed until the space is committed or dropped, so nothing else
+// can claim it in the meantime.
+pub struct Staged<'a, C: TxnTarget + 'a> {
+    target: &'a mut C,
+    ptr: *mut C::Elem,
+    cap: usize,
+    filled: usize
+}
+
+impl<'a, C: TxnTarget> Staged<'a, C> {
+    pub fn try_new(target: &'a mut C, len: usize) -> Result<Self, AllocError> {
+        let (ptr, cap) = target.txn_reserve(len)?;
+        Ok(Staged {
+            target: target,
+            ptr: ptr,
+            cap: cap,
+            filled: 0
+        })
+    }
+
+    // Elements written so far
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.filled
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn push(&mut self, elem: C::Elem) -> Result<(), C::Elem> {
+        if self.filled == self.cap {
+            return Err(elem)
+        }
+        unsafe {
+            ptr::write(self.ptr.offset(self.filled as isize), elem);
+        }
+        self.filled += 1;
+        Ok(())
+    }
+
+    // Copies `elems` if they fit, or hands them back
+    pub fn extend_from_slice<'b>(&mut self, elems: &'b [C::Elem]) -> Result<(), &'b [C::Elem]>
+            where C::Elem: Copy {
+        if self.cap - self.filled < elems.len() {
+            return Err(elems)
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(elems.as_ptr(), self.ptr.offset(self.filled as isize),
+                                     elems.len());
+        }
+        self.filled += elems.len();
+        Ok(())
+    }
+
+    // The elements written so far
+    pub fn as_mut_slice(&mut self) -> &mut [C::Elem] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.filled) }
+    }
+
+    // The space not yet written.  See `Reservation::spare_mut`.
+    pub fn spare_mut(&mut self) -> &mut [MaybeUninit<C::Elem>] {
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr.offset(self.filled as isize) as *mut MaybeUninit<C::Elem>,
+                                      self.cap - self.filled)
+        }
+    }
+
+    // Counts the first `n` elements of `spare_mut` as written.
+    // Unsafe because they must really have been initialized.
+    pub unsafe fn assume_filled(&mut self, n: usize) {
+        assert!(n <= self.cap - self.filled);
+        self.filled += n;
+    }
+}
+
+impl<'a, C: TxnTarget> Drop for Staged<'a, C> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.filled));
+        }
+    }
+}
+
+// Commits staged space, adding what was written to each container.
+// Implemented for a single Staged and tuples of them.
+pub trait Commit {
+    type Output;
+
+    fn commit(self) -> Self::Output;
+}
+
+impl<'a, C: TxnTarget> Commit for Staged<'a, C> {
+    type Output = &'a mut [C::Elem];
+
+    fn commit(self) -> &'a mut [C::Elem] {
+        let ptr = self.ptr;
+        let len = self.filled;
+        unsafe {
+            // Elements now belong to the container
+            let target = ptr::read(&self.target);
+            mem::forget(self);
+            target.txn_commit(len);
+            slice::from_raw_parts_mut(ptr, len)
+        }
+    }
+}
+
+macro_rules! commit_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: Commit),*> Commit for ($($name,)*) {
+            type Output = ($($name::Output,)*);
+
+            #[allow(non_snake_case)]
+            fn commit(self) -> Self::Output {
+                let ($($name,)*) = self;
+                ($($name.commit(),)*)
+            }
+        }
+    }
+}
+
+commit_tuple!(A, B);
+commit_tuple!(A, B, C);
+commit_tuple!(A, B, C, D);
+commit_tuple!(A, B, C, D, E);
+commit_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use super::super::zone::Zone;
+
+    #[test]
+    fn commit_all() {
+        let mut nodes: Zone<(u32, usize)> = Zone::new();
+        let mut names: Chain<u8> = Chain::new();
+        let mut node = Staged::try_new(&mut nodes, 1).unwrap();
+        let mut name = Staged::try_new(&mut names, 5).unwrap();
+        name.extend_from_slice(b"hello").unwrap();
+        node.push((7, name.len())).unwrap();
+        let (node, name) = (node, name).commit();
+        assert_eq!((node, &*name), (&mut [(7, 5)][..], &b"hello"[..]));
+        assert_eq!(names.len(), 5);
+    }
+
+    #[test]
+    fn failed() {
+        let rc = Rc::new(());
+        let mut rcs: Chain<Rc<()>> = Chain::new();
+        let mut words: Chain<u64> = Chain::new();
+        let result = (|| {
+            let mut first = Staged::try_new(&mut rcs, 2)?;
+            first.push(rc.clone()).unwrap();
+            let second = Staged::try_new(&mut words, usize::MAX)?;
+            (first, second).commit();
+            Ok(())
+        })();
+        assert_eq!(result.err(), Some(AllocError));
+        assert_eq!((rcs.len(), Rc::strong_count(&rc)), (0, 1));
+        assert_eq!(words.len(), 0);
+    }
+}
@@ -1,20 +1,62 @@
-use super::chain::{Chain, DynChain, Erase};
+use super::chain::{AllocError, Chain, ChainStats, Chunks, Iter, MemUsage, DynChain, DropOrder, Erase, GrowthPolicy, SizingProfile, Shared, Strategy};
+#[cfg(feature = "drop-timing")]
+use super::chain::DropTiming;
+use super::txn::TxnTarget;
+use super::arena::{alloc_aligned, AnyArena};
+use std::alloc::Layout;
+use std::any::TypeId;
+use std::array;
+use std::collections::HashMap;
 use std::mem;
 use std::ptr;
 use std::cmp;
 use std::fmt;
 use std::io;
 use std::slice;
-use std::intrinsics;
+use std::str;
+
+// Allocates a struct from a zone, writing each field directly into
+// the zone's memory rather than building the whole value on the
+// stack first:
+//
+//     let big = zone_alloc!(zone, Big { header: 1, payload: [0; 4096] });
+//
+// Field expressions must not allocate from the same zone.
+#[macro_export]
+macro_rules! zone_alloc {
+    ($zone:expr, $name:ident { $($field:ident: $value:expr),* $(,)* }) => {{
+        let zone = &$zone;
+        let slot = unsafe { zone.reserve_raw() };
+        // Never runs; checks the literal names every field of
+        // the zone's element type
+        #[allow(unreachable_code, clippy::diverging_sub_expression)]
+        {
+            if false {
+                unsafe { ::std::ptr::write(slot, $name { $($field: unreachable!()),* }) };
+            }
+        }
+        $(
+            let value = $value;
+            assert!(zone.is_reserved(slot),
+                    "zone_alloc!: field expression allocated from the zone");
+            unsafe { ::std::ptr::write(&mut (*slot).$field, value) };
+        )*
+        unsafe { zone.commit_raw(slot) }
+    }}
+}
 
 pub struct Zone<T> {
     chain: Chain<T>
 }
 
 impl<T> Zone<T> {
+    // Creates an empty zone.  Nothing is allocated until the
+    // first allocation is made from it.
     #[inline]
-    pub fn new() -> Self {
-        Self::with_capacity(8)
+    pub const fn new() -> Self {
+        Zone {
+            chain: Chain::new()
+        }
     }
 
     #[inline]
@@ -24,32 +66,316 @@ impl<T> Zone<T> {
         }
     }
 
+    #[inline]
+    pub fn with_profile(profile: &SizingProfile) -> Self {
+        Zone {
+            chain: Chain::with_profile(profile)
+        }
+    }
+
+    #[inline]
+    pub fn sizing_profile(&self) -> SizingProfile {
+        self.chain.sizing_profile()
+    }
+
+    #[inline]
+    pub fn with_policy<G>(policy: G) -> Self
+            where G: GrowthPolicy + Send + Sync + 'static {
+        Zone {
+            chain: Chain::with_policy(policy)
+        }
+    }
+
     #[inline]
     #[allow(mutable_transmutes)]
     pub fn push(&self, elem: T) -> &mut T {
         unsafe { mem::transmute(self.chain.push(elem)) }
     }
 
+    // Like `push`, but hands the element back if space for
+    // it can't be allocated
+    #[inline]
+    #[allow(mutable_transmutes)]
+    pub fn try_push(&self, elem: T) -> Result<&mut T, T> {
+        self.chain.try_push(elem).map(|elem| unsafe { mem::transmute(elem) })
+    }
+
+    pub fn stats(&self) -> ChainStats {
+        self.chain.stats()
+    }
+
+    // Drops everything allocated from the zone, keeping its chunks
+    // for reuse
+    pub fn reset(&mut self) {
+        self.chain.clear()
+    }
+
+    // Drops up to `max` allocations, returning whether any remain.  See
+    // `Chain::clear_some`.
+    pub fn reset_some(&mut self, max: usize) -> bool {
+        self.chain.clear_some(max)
+    }
+
+    #[cfg(feature = "peak-stats")]
+    pub fn peak_bytes(&self) -> usize {
+        self.chain.peak_bytes()
+    }
+
+    #[cfg(feature = "peak-stats")]
+    pub fn reset_peak(&self) {
+        self.chain.reset_peak()
+    }
+
+    // See `Chain::remaining_in_current_chunk`
+    #[inline]
+    pub fn remaining_in_current_chunk(&self) -> usize {
+        self.chain.remaining_in_current_chunk()
+    }
+
+    // See `Chain::will_grow`
+    pub fn will_grow(&self, count: usize) -> bool {
+        self.chain.will_grow(count)
+    }
+
+    // See `Chain::total_spare_capacity`
+    pub fn total_spare_capacity(&self) -> usize {
+        self.chain.total_spare_capacity()
+    }
+
+    // Reports the zone's teardown cost when it is dropped.  See
+    // `Chain::set_drop_timing_hook`.
+    #[cfg(feature = "drop-timing")]
+    pub fn set_drop_timing_hook<F>(&mut self, hook: F)
+            where F: Fn(DropTiming) + Send + Sync + 'static {
+        self.chain.set_drop_timing_hook(hook)
+    }
+
+    // Calls `hook` each time the zone's chunk allocations cross a
+    // multiple of `every` bytes.  See `Chain::set_budget_hook`.
+    pub fn set_budget_hook<F>(&mut self, every: usize, hook: F)
+            where F: Fn(usize) + Send + Sync + 'static {
+        self.chain.set_budget_hook(every, hook)
+    }
+
+    // Memory held by the zone, including heap data owned by the
+    // values allocated from it
+    pub fn deep_size(&self) -> usize where T: MemUsage {
+        self.chain.deep_size()
+    }
+
+    // Allocates `values` contiguously with a single reservation,
+    // returning a separate reference to each
+    pub fn alloc_many<const N: usize>(&self, values: [T; N]) -> [&mut T; N] {
+        unsafe {
+            let (ptr, _) = self.chain.raw_reserve(N);
+            ptr::write(ptr as *mut [T; N], values);
+            self.chain.raw_add_len(N);
+            array::from_fn(|i| &mut *ptr.offset(i as isize))
+        }
+    }
+
+    // Compares two values allocated from the zone by allocation
+    // order.  See `Chain::cmp_order`.
+    pub fn cmp_order(&self, a: &T, b: &T) -> cmp::Ordering {
+        self.chain.cmp_order(a, b)
+    }
+
+    // Collects a fallible iterator into a contiguous slice, stopping
+    // at the first error.  Values are written to the zone's spare
+    // space and only committed once the iterator is exhausted; on an
+    // error or panic the ones written so far are dropped and the
+    // space is left free.  The iterator must not allocate from the
+    // zone itself, which is detected and panics.
+    pub fn try_alloc_iter<I, E>(&self, iter: I) -> Result<&mut [T], E>
+            where I: IntoIterator<Item=Result<T, E>> {
+        // Values written but not yet committed
+        struct Pending<T> {
+            ptr: *mut T,
+            len: usize
+        }
+
+        impl<T> Drop for Pending<T> {
+            fn drop(&mut self) {
+                unsafe {
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len));
+                }
+            }
+        }
+
+        let mut iter = iter.into_iter();
+        let (ptr, mut cap) = self.chain.raw_reserve(cmp::max(iter.size_hint().0, 1));
+        let mut pending = Pending {
+            ptr: ptr,
+            len: 0
+        };
+        loop {
+            let next = iter.next();
+            if self.chain.spare().0 != pending.ptr {
+                // The values may have been overwritten, so they can
+                // only be leaked
+                mem::forget(pending);
+                panic!("Zone: allocated from during try_alloc_iter");
+            }
+            let elem = match next {
+                Some(Ok(elem)) => elem,
+                Some(Err(err)) => return Err(err),
+                None => break
+            };
+            unsafe {
+                if pending.len == cap {
+                    // Moves on to a fresh chunk, leaving the rest of
+                    // this one unused
+                    let (ptr, new_cap) = self.chain.raw_reserve(cap * 2);
+                    ptr::copy_nonoverlapping(pending.ptr, ptr, pending.len);
+                    pending.ptr = ptr;
+                    cap = new_cap;
+                }
+                ptr::write(pending.ptr.offset(pending.len as isize), elem);
+            }
+            pending.len += 1;
+        }
+        let (ptr, len) = (pending.ptr, pending.len);
+        mem::forget(pending);
+        unsafe {
+            self.chain.raw_add_len(len);
+            Ok(slice::from_raw_parts_mut(ptr, len))
+        }
+    }
+
+    // Constructs a value in place.  See `Chain::emplace_with`; for
+    // structs, `zone_alloc!` does the same without unsafe code.
+    pub unsafe fn emplace_with<F: FnOnce(*mut T)>(&self, init: F) -> &mut T {
+        let ptr = self.reserve_raw();
+        init(ptr);
+        self.commit_raw(ptr)
+    }
+
+    // Deliberately leaks the zone, giving 'static access to
+    // everything allocated from it.  The memory is never freed and
+    // element destructors never run.  Meant for programs that build
+    // a structure once and keep it until exit.
+    pub fn leak(self) -> &'static ZoneView<T> {
+        Box::leak(Box::new(ZoneView {
+            chain: self.chain
+        }))
+    }
+
+    // Raw access for in-place construction by `zone_alloc!`.  Returns
+    // the uncommitted slot the next push would use.
+    #[doc(hidden)]
+    pub unsafe fn reserve_raw(&self) -> *mut T {
+        self.chain.raw_reserve(1).0
+    }
+
+    // Whether `ptr` is still the next free slot, i.e. nothing has
+    // been allocated from the zone since `reserve_raw` returned it
+    #[doc(hidden)]
+    pub fn is_reserved(&self, ptr: *mut T) -> bool {
+        self.chain.spare().0 == ptr
+    }
+
+    // Commits the slot at `ptr`, which must have come from
+    // `reserve_raw` and been fully initialized
+    #[doc(hidden)]
+    pub unsafe fn commit_raw(&self, ptr: *mut T) -> &mut T {
+        self.chain.raw_add_len(1);
+        &mut *ptr
+    }
+
     // We only permit allocation of chunks for Copy types
     // since the caller can fail to fill the entire chunk,
     // leaving uninitialized values that would be hit on
     // drop.
-    pub fn alloc(&self, len: usize) -> Quota<T> where T: Copy {
+    pub fn alloc(&self, len: usize) -> Quota<'_, T> where T: Copy {
+        match self.try_alloc(len) {
+            Ok(quota) => quota,
+            Err(_) => panic!("Zone: failed to allocate chunk!")
+        }
+    }
+
+    pub fn try_alloc(&self, len: usize) -> Result<Quota<'_, T>, AllocError> where T: Copy {
         unsafe {
-            let (origin, cap) = self.chain.reserve(len);
-            self.chain.add_len(cap);
-            Quota {
-                origin: origin,
-                len: 0,
-                cap: cap,
-                arena: self
-            }
+            let (origin, cap) = self.chain.raw_try_reserve(len)?;
+            self.chain.raw_add_len(cap);
+            Ok(Quota::from_raw(origin, cap, self))
+        }
+    }
+
+    // Like `alloc`, but only allocates a new chunk if the current one
+    // is completely full.  The returned quota may have less than `len`
+    // capacity, which lets streaming producers flush what they have
+    // instead of forcing the zone to grow.
+    pub fn alloc_up_to(&self, len: usize) -> Quota<'_, T> where T: Copy {
+        unsafe {
+            let (origin, cap) = match self.chain.spare() {
+                (_, 0) => self.chain.raw_reserve(len),
+                (origin, cap) => (origin, cmp::min(cap, len))
+            };
+            self.chain.raw_add_len(cap);
+            Quota::from_raw(origin, cap, self)
         }
     }
 }
 
+impl<T> TxnTarget for Zone<T> {
+    type Elem = T;
+
+    fn txn_reserve(&mut self, len: usize) -> Result<(*mut T, usize), AllocError> {
+        self.chain.txn_reserve(len)
+    }
+
+    unsafe fn txn_commit(&mut self, len: usize) {
+        self.chain.txn_commit(len)
+    }
+}
+
+// Values are leaked in place rather than dropped, since the zone
+// only holds bytes.  Nothing they borrow is touched once they are
+// allocated, so they may borrow for any lifetime.
+impl<'gt> AnyArena<'gt> for Zone<u8> {
+    fn alloc_bytes(&self, layout: Layout) -> *mut u8 {
+        alloc_aligned(&self.chain, layout)
+    }
+
+    unsafe fn register_drop(&self, _: *mut u8, _: unsafe fn(*mut u8)) {}
+}
+
+impl<T> Default for Zone<T> {
+    fn default() -> Self {
+        Zone::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Zone<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.chain.fmt(f)
+    }
+}
+
+// Read-only view of everything allocated from a leaked zone
+pub struct ZoneView<T> {
+    chain: Chain<T>
+}
+
+impl<T> ZoneView<T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.chain.get(index)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.chain.iter()
+    }
+}
+
 impl Zone<u8> {
-    pub fn alloc_str(&self, len: usize) -> StrQuota {
+    pub fn alloc_str(&self, len: usize) -> StrQuota<'_> {
         StrQuota(self.alloc(len))
     }
     
@@ -67,18 +393,449 @@ impl Zone<u8> {
             len = quota.capacity() + 1;
         }
     }
+
+    pub fn push_char(&self, c: char) -> &str {
+        let mut buf = [0; 4];
+        self.chain.push_str(c.encode_utf8(&mut buf))
+    }
+
+    // Copies `bytes` as a string, replacing invalid UTF-8 sequences
+    // with U+FFFD.  Validation happens during the copy, and the claim
+    // only grows if replacements make the text longer.
+    pub fn push_utf8_lossy(&self, bytes: &[u8]) -> &str {
+        let mut builder = self.char_builder();
+        if !bytes.is_empty() {
+            builder.grow(bytes.len());
+        }
+        for chunk in bytes.utf8_chunks() {
+            builder.push_str(chunk.valid());
+            if !chunk.invalid().is_empty() {
+                builder.push(char::REPLACEMENT_CHARACTER);
+            }
+        }
+        builder.finish()
+    }
+
+    // Starts a string to be built up a character at a time directly
+    // in the zone
+    pub fn char_builder(&self) -> CharBuilder<'_> {
+        CharBuilder {
+            zone: self,
+            ptr: ptr::null_mut(),
+            len: 0,
+            cap: 0
+        }
+    }
+
+    // Copies `s` padded with spaces to `width` characters
+    pub fn padded(&self, s: &str, width: usize, align: Align) -> &str {
+        let pad = width.saturating_sub(s.chars().count());
+        let mut quota = self.alloc_str(s.len() + pad);
+        let _ = quota.write_padded(s, width, align);
+        quota.into_slice()
+    }
+
+    // Joins `cells` into one CSV record, quoting cells as needed.
+    // No line terminator is added.
+    pub fn csv_row(&self, cells: &[&str]) -> &str {
+        let len = cells.iter().map(|c| csv_len(c) + 1).sum::<usize>().saturating_sub(1);
+        let mut quota = self.alloc_str(len);
+        for (i, cell) in cells.iter().enumerate() {
+            if i != 0 {
+                quota.fill(",");
+            }
+            let _ = quota.write_csv_cell(cell);
+        }
+        quota.into_slice()
+    }
+}
+
+// Accumulates a string in the zone.  The text stays contiguous: when
+// the space claimed for it runs out, it is moved to a larger claim,
+// in place if nothing has been allocated from the zone after it.
+pub struct CharBuilder<'a> {
+    zone: &'a Zone<u8>,
+    ptr: *mut u8,
+    len: usize,
+    cap: usize
+}
+
+impl<'a> CharBuilder<'a> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        if self.cap == 0 {
+            return ""
+        }
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.ptr, self.len)) }
+    }
+
+    fn grow(&mut self, extra: usize) {
+        let cap = cmp::max(cmp::max(self.cap * 2, self.len + extra), 16);
+        let chain = &self.zone.chain;
+        unsafe {
+            // Give the old claim back first, so the new one starts at
+            // the same place if it still fits in the chunk.  The bytes
+            // are untouched until copied.
+            if self.cap != 0 {
+                chain.raw_shrink_len(self.ptr, self.cap, 0);
+            }
+            let (ptr, _) = chain.raw_reserve(cap);
+            if self.len != 0 {
+                ptr::copy(self.ptr, ptr, self.len);
+            }
+            chain.raw_add_len(cap);
+            self.ptr = ptr;
+            self.cap = cap;
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        if self.cap - self.len < s.len() {
+            self.grow(s.len());
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(s.as_ptr(), self.ptr.offset(self.len as isize), s.len());
+        }
+        self.len += s.len();
+    }
+
+    #[inline]
+    pub fn push(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.push_str(c.encode_utf8(&mut buf))
+    }
+
+    // Returns the finished string, releasing unused space if possible
+    pub fn finish(self) -> &'a str {
+        let (ptr, len) = (self.ptr, self.len);
+        if self.cap == 0 {
+            return ""
+        }
+        // Dropping shrinks the claim to the text
+        drop(self);
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(ptr, len)) }
+    }
+}
+
+impl<'a> Drop for CharBuilder<'a> {
+    fn drop(&mut self) {
+        if self.cap != 0 {
+            unsafe {
+                self.zone.chain.raw_shrink_len(self.ptr, self.cap, self.len)
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Write for CharBuilder<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl Zone<u16> {
+    // Encodes `s` as UTF-16 directly into the zone.  A UTF-8 string
+    // never needs more UTF-16 code units than it has bytes, so a
+    // single allocation always suffices.
+    pub fn alloc_wide(&self, s: &str) -> &[u16] {
+        let mut quota = self.alloc(s.len());
+        quota.extend(s.encode_utf16());
+        quota.into_slice()
+    }
+
+    // Like `alloc_wide`, but appends a terminating NUL as expected
+    // by most Windows APIs
+    pub fn alloc_wide_nul(&self, s: &str) -> &[u16] {
+        let mut quota = self.alloc(s.len() + 1);
+        quota.extend(s.encode_utf16());
+        let _ = quota.push(0);
+        quota.into_slice()
+    }
+}
+
+// Append-only store of byte records.  Each record is written as a
+// little-endian u32 length followed by its bytes, contiguously
+// within one chunk, so the contents can be dumped and walked back
+// without any other framing.
+pub struct RecordZone {
+    chain: Chain<u8>
+}
+
+impl RecordZone {
+    pub const fn new() -> Self {
+        RecordZone {
+            chain: Chain::new()
+        }
+    }
+
+    pub fn push_record(&self, data: &[u8]) -> &[u8] {
+        self.push_parts(&[data])
+    }
+
+    // Appends the concatenation of `parts` as one record, without
+    // first copying them into a temporary buffer
+    pub fn push_parts(&self, parts: &[&[u8]]) -> &[u8] {
+        let len = parts.iter().fold(0, |sum, part| sum + part.len());
+        assert!(len <= u32::MAX as usize, "RecordZone: record too large");
+        let prefix = [len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+        unsafe {
+            let (ptr, _) = self.chain.raw_reserve(prefix.len() + len);
+            ptr::copy_nonoverlapping(prefix.as_ptr(), ptr, prefix.len());
+            let data = ptr.offset(prefix.len() as isize);
+            let mut cur = data;
+            for part in parts {
+                ptr::copy_nonoverlapping(part.as_ptr(), cur, part.len());
+                cur = cur.offset(part.len() as isize);
+            }
+            self.chain.raw_add_len(prefix.len() + len);
+            slice::from_raw_parts(data, len)
+        }
+    }
+
+    pub fn records(&self) -> Records<'_> {
+        Records {
+            chunks: self.chain.chunks(),
+            cur: &[]
+        }
+    }
+}
+
+impl Default for RecordZone {
+    fn default() -> Self {
+        RecordZone::new()
+    }
+}
+
+pub struct Records<'a> {
+    chunks: Chunks<'a, u8>,
+    cur: &'a [u8]
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        while self.cur.is_empty() {
+            self.cur = self.chunks.next()?;
+        }
+        let cur = self.cur;
+        let len = cur[0] as usize | (cur[1] as usize) << 8 |
+                  (cur[2] as usize) << 16 | (cur[3] as usize) << 24;
+        self.cur = &cur[4 + len..];
+        Some(&cur[4..4 + len])
+    }
+}
+
+// Upper size limits of the classes a SmallObjectZone routes to
+const SIZE_CLASSES: [usize; 3] = [64, 512, 4096];
+
+// A byte allocator that routes each request to a separate zone by
+// size.  Mixing sizes in a single zone wastes the end of a chunk
+// whenever a large request doesn't fit; keeping small requests
+// apart from large ones lets them pack tightly.
+pub struct SmallObjectZone {
+    classes: [Zone<u8>; 3],
+    large: Zone<u8>
+}
+
+impl SmallObjectZone {
+    pub const fn new() -> Self {
+        SmallObjectZone {
+            classes: [Zone::new(), Zone::new(), Zone::new()],
+            large: Zone::new()
+        }
+    }
+
+    fn zone_for(&self, len: usize) -> &Zone<u8> {
+        for (i, &limit) in SIZE_CLASSES.iter().enumerate() {
+            if len <= limit {
+                return &self.classes[i]
+            }
+        }
+        &self.large
+    }
+
+    pub fn alloc(&self, len: usize) -> Quota<'_, u8> {
+        self.zone_for(len).alloc(len)
+    }
+
+    pub fn alloc_str(&self, len: usize) -> StrQuota<'_> {
+        self.zone_for(len).alloc_str(len)
+    }
+
+    pub fn format(&self, args: fmt::Arguments) -> &str {
+        let mut len = 32;
+        loop {
+            let mut quota = self.alloc_str(len);
+            if let Ok(()) = fmt::write(&mut quota, args) {
+                return quota.into_slice()
+            }
+            quota.clear();
+            len = quota.capacity() + 1;
+        }
+    }
+}
+
+impl Default for SmallObjectZone {
+    fn default() -> Self {
+        SmallObjectZone::new()
+    }
+}
+
+// A byte zone whose allocations all start on an `ALIGN`-byte
+// boundary, e.g. for staging buffers with strict upload alignment
+pub struct AlignedZone<const ALIGN: usize> {
+    chain: Chain<u8>
+}
+
+impl<const ALIGN: usize> AlignedZone<ALIGN> {
+    pub const fn new() -> Self {
+        AlignedZone {
+            chain: Chain::new()
+        }
+    }
+
+    pub fn with_capacity(len: usize) -> Self {
+        AlignedZone {
+            chain: Chain::with_capacity(len)
+        }
+    }
+
+    // Allocates `len` zeroed bytes starting at a multiple of `ALIGN`
+    pub fn alloc(&self, len: usize) -> &mut [u8] {
+        assert!(ALIGN.is_power_of_two());
+        unsafe {
+            // Reserving ALIGN - 1 extra bytes guarantees an aligned
+            // start fits within the same chunk
+            let (origin, _) = self.chain.raw_reserve(len + ALIGN - 1);
+            let pad = (ALIGN - (origin as usize & (ALIGN - 1))) & (ALIGN - 1);
+            let ptr = origin.offset(pad as isize);
+            ptr::write_bytes(ptr, 0, len);
+            self.chain.raw_add_len(pad + len);
+            slice::from_raw_parts_mut(ptr, len)
+        }
+    }
+
+    // Allocates an aligned copy of `data`
+    pub fn alloc_copy(&self, data: &[u8]) -> &mut [u8] {
+        let slice = self.alloc(data.len());
+        slice.copy_from_slice(data);
+        slice
+    }
+}
+
+impl<const ALIGN: usize> Default for AlignedZone<ALIGN> {
+    fn default() -> Self {
+        AlignedZone::new()
+    }
+}
+
+// A builder for output that must end up contiguous when its final
+// size isn't known in advance.  Appends go to ordinary chunks; `finish`
+// then moves everything into one block of exactly the right size.
+pub struct ContiguousZone<T> {
+    chain: Chain<T>
+}
+
+impl<T> ContiguousZone<T> {
+    pub const fn new() -> Self {
+        ContiguousZone {
+            chain: Chain::new()
+        }
+    }
+
+    // Starts with room for `count` elements, so output within that
+    // estimate needs only the final move
+    pub fn with_capacity(count: usize) -> Self {
+        ContiguousZone {
+            chain: Chain::with_capacity(count)
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    #[inline]
+    pub fn push(&self, elem: T) -> &T {
+        self.chain.push(elem)
+    }
+
+    pub fn extend_from_slice(&self, elems: &[T]) -> &[T] where T: Copy {
+        self.chain.extend_from_slice(elems)
+    }
+
+    // Relocates everything appended so far into a single block and
+    // returns it.  Appending afterwards is allowed; the next `finish`
+    // relocates again.
+    pub fn finish(&mut self) -> &mut [T] {
+        self.chain.compact();
+        self.chain.chunks_mut().next().unwrap_or(&mut [])
+    }
+}
+
+impl ContiguousZone<u8> {
+    pub fn push_str(&self, s: &str) -> &str {
+        self.chain.push_str(s)
+    }
+
+    // Like `finish`, viewing the bytes as text.  Fails if bytes pushed
+    // individually left invalid UTF-8.
+    pub fn finish_str(&mut self) -> Result<&mut str, str::Utf8Error> {
+        str::from_utf8_mut(self.finish())
+    }
+}
+
+impl<T> Default for ContiguousZone<T> {
+    fn default() -> Self {
+        ContiguousZone::new()
+    }
 }
 
 // A Quota is basically a write-only Vec pointing into a Zone
 // that can be converted into a slice after filling it
+// Gives back the unused end of a quota's allocation when it's
+// dropped, if nothing has been allocated after it
+pub(crate) trait QuotaSource<T> {
+    unsafe fn shrink(&self, origin: *mut T, cap: usize, len: usize);
+}
+
+impl<T> QuotaSource<T> for Zone<T> {
+    unsafe fn shrink(&self, origin: *mut T, cap: usize, len: usize) {
+        self.chain.raw_shrink_len(origin, cap, len)
+    }
+}
+
 pub struct Quota<'a, T: 'a> {
     origin: *mut T,
     len: usize,
     cap: usize,
-    arena: &'a Zone<T>
+    arena: &'a (dyn QuotaSource<T> + 'a)
 }
 
 impl<'a, T> Quota<'a, T> {
+    // An empty quota over `cap` uninitialized slots at `origin`
+    pub(crate) unsafe fn from_raw(origin: *mut T, cap: usize,
+                                  arena: &'a (dyn QuotaSource<T> + 'a)) -> Self {
+        Quota {
+            origin: origin,
+            len: 0,
+            cap: cap,
+            arena: arena
+        }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.len
@@ -131,17 +888,28 @@ impl<'a, T> Quota<'a, T> {
         count
     }
     
+    // Fills all remaining space, initializing each element from
+    // its index within the quota
+    pub fn fill_with<F>(&mut self, mut f: F) -> usize
+            where F: FnMut(usize) -> T {
+        let count = self.cap - self.len;
+        while self.len < self.cap {
+            let elem = f(self.len);
+            unsafe {
+                ptr::write(self.origin.offset(self.len as isize), elem);
+            }
+            self.len += 1;
+        }
+        count
+    }
+
     pub fn clear(&mut self) {
+        let len = self.len;
+        // Reset first so a panicking drop can't lead to a double drop;
+        // dropping the slice still drops every element
+        self.len = 0;
         unsafe {
-            if intrinsics::needs_drop::<T>() {
-                let mut ptr = self.origin;
-                let end = self.origin.offset(self.len as isize);
-                while ptr < end {
-                    intrinsics::drop_in_place(ptr);
-                    ptr = ptr.offset(1);
-                }
-                self.len = 0;
-            }
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.origin, len));
         }
     }
     
@@ -157,8 +925,33 @@ impl<'a, T> Drop for Quota<'a, T> {
     fn drop(&mut self) {
         // Shrink the allocation if we haven't already allocated more space past it.
         unsafe {
-            self.arena.chain.shrink_len(self.origin, self.cap, self.len)
+            self.arena.shrink(self.origin, self.cap, self.len)
+        }
+    }
+}
+
+impl<'a> Quota<'a, u8> {
+    // Number of bytes to skip before the next write lands on a
+    // multiple of `align`, which must be a power of two
+    pub fn align_offset(&self, align: usize) -> usize {
+        assert!(align.is_power_of_two());
+        let pos = self.origin as usize + self.len;
+        pos.wrapping_neg() & (align - 1)
+    }
+
+    // Zero-fills up to the next multiple of `align`, so a field
+    // written next is aligned.  Returns false without writing
+    // anything if the quota lacks room for the padding.
+    pub fn realign(&mut self, align: usize) -> bool {
+        let pad = self.align_offset(align);
+        if pad > self.cap - self.len {
+            return false
+        }
+        unsafe {
+            ptr::write_bytes(self.origin.offset(self.len as isize), 0, pad);
         }
+        self.len += pad;
+        true
     }
 }
 
@@ -171,7 +964,7 @@ impl<'a> io::Write for Quota<'a, u8> {
     fn flush(&mut self) -> io::Result<()> { Ok(()) }
 }
 
-pub struct StrQuota<'a>(Quota<'a, u8>);
+pub struct StrQuota<'a>(pub(crate) Quota<'a, u8>);
 
 impl<'a> StrQuota<'a> {
     #[inline]
@@ -184,19 +977,113 @@ impl<'a> StrQuota<'a> {
         self.0.capacity()
     }
     
+    // Fills as much of `data` as fits without splitting a character
     #[inline]
     pub fn fill(&mut self, data: &str) -> usize {
-        self.0.fill(data.as_bytes())
+        let mut len = cmp::min(data.len(), self.capacity() - self.len());
+        while !data.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.0.fill(&data.as_bytes()[..len])
     }
     
     pub fn clear(&mut self) {
         self.0.clear()
     }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.0.origin, self.0.len)) }
+    }
+
+    // The text written so far, for editing in place
+    #[inline]
+    pub fn as_mut_str(&mut self) -> &mut str {
+        unsafe { str::from_utf8_unchecked_mut(slice::from_raw_parts_mut(self.0.origin, self.0.len)) }
+    }
+
+    pub fn make_ascii_lowercase(&mut self) {
+        self.as_mut_str().make_ascii_lowercase()
+    }
+
+    pub fn make_ascii_uppercase(&mut self) {
+        self.as_mut_str().make_ascii_uppercase()
+    }
     
     #[inline]
     pub fn into_slice(self) -> &'a str {
         unsafe { mem::transmute(self.0.into_slice()) }
     }
+
+    // Like `into_slice`, but the text can still be edited in place
+    #[inline]
+    pub fn into_mut_str(self) -> &'a mut str {
+        unsafe { str::from_utf8_unchecked_mut(self.0.into_slice()) }
+    }
+
+    // Writes `s` padded with spaces to at least `width` characters.
+    // Nothing is written if the result doesn't fit.
+    pub fn write_padded(&mut self, s: &str, width: usize, align: Align) -> fmt::Result {
+        let pad = width.saturating_sub(s.chars().count());
+        if self.capacity() - self.len() < s.len() + pad {
+            return Err(fmt::Error)
+        }
+        if let Align::Right = align {
+            self.write_fill(b' ', pad);
+        }
+        self.fill(s);
+        if let Align::Left = align {
+            self.write_fill(b' ', pad);
+        }
+        Ok(())
+    }
+
+    // Writes `s` as a CSV cell, quoting it if it contains a comma,
+    // quote or line break.  Nothing is written if it doesn't fit.
+    pub fn write_csv_cell(&mut self, s: &str) -> fmt::Result {
+        if self.capacity() - self.len() < csv_len(s) {
+            return Err(fmt::Error)
+        }
+        if !csv_needs_quotes(s) {
+            self.fill(s);
+            return Ok(())
+        }
+        self.fill("\"");
+        for (i, part) in s.split('"').enumerate() {
+            if i != 0 {
+                self.fill("\"\"");
+            }
+            self.fill(part);
+        }
+        self.fill("\"");
+        Ok(())
+    }
+
+    fn write_fill(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            let _ = self.0.push(byte);
+        }
+    }
+}
+
+// Which side of a padded field the text sits on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right
+}
+
+fn csv_needs_quotes(s: &str) -> bool {
+    s.bytes().any(|b| b == b',' || b == b'"' || b == b'\n' || b == b'\r')
+}
+
+// Length of `s` once written as a CSV cell
+fn csv_len(s: &str) -> usize {
+    if csv_needs_quotes(s) {
+        s.len() + 2 + s.bytes().filter(|&b| b == b'"').count()
+    } else {
+        s.len()
+    }
 }
 
 impl<'a> fmt::Write for StrQuota<'a> {
@@ -215,6 +1102,10 @@ impl<'a> fmt::Write for StrQuota<'a> {
 // we use a trivial erase strategy
 struct Forget;
 
+impl Strategy for Forget {
+    type Access = Shared;
+}
+
 impl<T> Erase<T, ()> for Forget {
     fn erase(_: &T) -> &() {
         static UNIT: () = ();
@@ -222,21 +1113,188 @@ impl<T> Erase<T, ()> for Forget {
     }
 }
 
+// A destructor registered through AnyArena, run when the zone drops
+// its values
+struct Deferred(*mut u8, unsafe fn(*mut u8));
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        unsafe { (self.1)(self.0) }
+    }
+}
+
 pub struct DynZone<'gt> {
-    chain: DynChain<'gt, (), Forget>
+    chain: DynChain<'gt, (), Forget>,
+    // Memory handed out through AnyArena.  Declared after `chain` so
+    // it outlives the destructors registered there.
+    bytes: Chain<u8>
 }
 
 impl<'gt> DynZone<'gt> {
+    pub const fn new() -> Self {
+        DynZone {
+            chain: DynChain::new(),
+            bytes: Chain::new()
+        }
+    }
+
+    // Creates a zone dropping its values in the given order.  With
+    // `DropOrder::Reverse`, values may refer to those allocated
+    // before them from their destructors.
+    pub const fn with_drop_order(order: DropOrder) -> Self {
+        DynZone {
+            chain: DynChain::with_drop_order(order),
+            bytes: Chain::new()
+        }
+    }
+
+    // Stats for the typed values.  Memory allocated through AnyArena
+    // is only counted by `reserved_bytes`.
+    pub fn stats(&self) -> ChainStats {
+        self.chain.stats()
+    }
+
+    // Bytes allocated for all of the zone's storage
+    pub fn reserved_bytes(&self) -> usize {
+        self.chain.stats().reserved_bytes() + self.bytes.stats().reserved_bytes()
+    }
+
+    // Drops everything allocated from the zone and frees its memory
+    pub fn reset(&mut self) {
+        self.chain = DynChain::with_drop_order(self.chain.drop_order());
+        self.bytes = Chain::new();
+    }
+
+    // Allocates the value returned by `f`, written straight into the
+    // zone's memory.  See `DynChain::emplace`.
     #[allow(mutable_transmutes)]
     pub fn alloc<T: 'gt, F: FnOnce() -> T>(&self, f: F) -> &mut T {
-        // FIXME: we need a way to emplace inside the underlying chain
-        unsafe { mem::transmute(self.chain.push(f())) }
+        unsafe { mem::transmute(self.chain.emplace(f)) }
+    }
+
+    // Constructs a value in place.  See `DynChain::emplace_with`.
+    #[allow(mutable_transmutes)]
+    pub unsafe fn emplace_with<T: 'gt, F: FnOnce(*mut T)>(&self, init: F) -> &mut T {
+        mem::transmute(self.chain.emplace_with(init))
+    }
+
+    // Like `alloc`, but hands the value back if space for it
+    // can't be allocated
+    #[allow(mutable_transmutes)]
+    pub fn try_alloc<T: 'gt, F: FnOnce() -> T>(&self, f: F) -> Result<&mut T, T> {
+        self.chain.try_push(f()).map(|obj| unsafe { mem::transmute(obj) })
     }
 }
 
+impl<'gt> AnyArena<'gt> for DynZone<'gt> {
+    fn alloc_bytes(&self, layout: Layout) -> *mut u8 {
+        alloc_aligned(&self.bytes, layout)
+    }
+
+    unsafe fn register_drop(&self, ptr: *mut u8, drop: unsafe fn(*mut u8)) {
+        self.chain.push(Deferred(ptr, drop));
+    }
+}
+
+impl<'gt> Default for DynZone<'gt> {
+    fn default() -> Self {
+        DynZone::new()
+    }
+}
+
+// Elements are erased to (), so only the footprint is shown
+impl<'gt> fmt::Debug for DynZone<'gt> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DynZone")
+            .field("reserved_bytes", &self.stats().reserved_bytes())
+            .finish()
+    }
+}
+
+// Holds at most one value of each type, like an extensions or
+// context bag.  Values live in a DynZone and are all freed together
+// when the map is dropped.
+pub struct TypeMapZone {
+    zone: DynZone<'static>,
+    slots: HashMap<TypeId, *mut u8>
+}
+
+impl TypeMapZone {
+    pub fn new() -> Self {
+        TypeMapZone {
+            zone: DynZone::new(),
+            slots: HashMap::new()
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.slots.contains_key(&TypeId::of::<T>())
+    }
+
+    // Stores `value` as the map's T.  An existing T is overwritten in
+    // place and dropped, so replacing values doesn't grow the zone.
+    pub fn insert<T: 'static>(&mut self, value: T) -> &mut T {
+        if let Some(&slot) = self.slots.get(&TypeId::of::<T>()) {
+            let slot = slot as *mut T;
+            unsafe {
+                *slot = value;
+                return &mut *slot
+            }
+        }
+        let slot = self.zone.alloc(|| value) as *mut T;
+        self.slots.insert(TypeId::of::<T>(), slot as *mut u8);
+        unsafe { &mut *slot }
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.slots.get(&TypeId::of::<T>()).map(|&slot| unsafe { &*(slot as *const T) })
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.slots.get(&TypeId::of::<T>()).map(|&slot| unsafe { &mut *(slot as *mut T) })
+    }
+
+    pub fn get_or_insert_with<T: 'static, F: FnOnce() -> T>(&mut self, f: F) -> &mut T {
+        if !self.contains::<T>() {
+            return self.insert(f())
+        }
+        self.get_mut().unwrap()
+    }
+}
+
+impl Default for TypeMapZone {
+    fn default() -> Self {
+        TypeMapZone::new()
+    }
+}
+
+// Runs `f` with a fresh zone, which is dropped when `f` returns
+// or unwinds.  Nothing allocated from the zone can escape `f`.
+pub fn with_zone<T, R, F>(f: F) -> R where F: FnOnce(&Zone<T>) -> R {
+    let zone = Zone::new();
+    f(&zone)
+}
+
+// Like `with_zone`, for a DynZone
+pub fn with_dyn_zone<'gt, R, F>(f: F) -> R where F: FnOnce(&DynZone<'gt>) -> R {
+    let zone = DynZone::new();
+    f(&zone)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::rc::Rc;
 
     #[test]
     fn format() {
@@ -246,4 +1304,342 @@ mod test {
                        format!("hello {}", i));
         }
     }
+
+    #[test]
+    fn fields() {
+        let zone = Zone::new();
+        assert_eq!(zone.padded("ab", 5, Align::Left), "ab   ");
+        assert_eq!(zone.padded("\u{e9}t\u{e9}", 5, Align::Right), "  \u{e9}t\u{e9}");
+        assert_eq!(zone.padded("toolong", 3, Align::Right), "toolong");
+        assert_eq!(zone.csv_row(&["plain", "a,b", "say \"hi\"", ""]),
+                   "plain,\"a,b\",\"say \"\"hi\"\"\",");
+        assert_eq!(zone.csv_row(&[]), "");
+
+        let mut quota = zone.alloc_str(4);
+        let quotes = "\"".repeat(quota.capacity());
+        assert!(quota.write_csv_cell(&quotes).is_err());
+        assert_eq!(quota.len(), 0);
+        assert!(quota.write_padded("x", 4, Align::Left).is_ok());
+        assert_eq!(quota.into_slice(), "x   ");
+    }
+
+    #[test]
+    fn alloc_many() {
+        let zone = Zone::with_capacity(2);
+        zone.push(String::from("before"));
+        let [left, right, parent] = zone.alloc_many([String::from("l"), String::from("r"),
+                                                     String::new()]);
+        parent.push_str(left);
+        parent.push_str(right);
+        left.push('!');
+        assert_eq!((&left[..], &right[..], &parent[..]), ("l!", "r", "lr"));
+        let [] = zone.alloc_many::<0>([]);
+    }
+
+    #[test]
+    fn try_alloc_iter() {
+        let zone = Zone::with_capacity(2);
+        zone.push(String::from("before"));
+        let parsed: Result<_, ()> = zone.try_alloc_iter((0..10).map(|i| Ok(i.to_string())));
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.len(), 10);
+        assert_eq!((&parsed[0][..], &parsed[9][..]), ("0", "9"));
+        let failed = zone.try_alloc_iter((0..5).map(|i| if i < 3 { Ok(i.to_string()) } else { Err(i) }));
+        assert_eq!(failed.err(), Some(3));
+        zone.push(String::from("after"));
+        assert_eq!(zone.stats().chunks().iter().map(|c| c.0).sum::<usize>(), 12);
+
+        let rc = Rc::new(());
+        let rcs = Zone::new();
+        let failed = rcs.try_alloc_iter((0..5).map(|i| if i < 3 { Ok(rc.clone()) } else { Err(i) }));
+        assert!(failed.is_err());
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn fill_with() {
+        let zone = Zone::new();
+        let mut quota = zone.alloc(4);
+        let _ = quota.push(100u32);
+        quota.fill_with(|i| i as u32 * i as u32);
+        let squares = quota.into_slice();
+        assert_eq!(squares[0], 100);
+        assert_eq!(squares[1..4], [1, 4, 9]);
+    }
+
+    #[test]
+    fn alloc_up_to() {
+        let zone = Zone::with_capacity(8);
+        let mut quota = zone.alloc(5);
+        assert_eq!(quota.fill(&[1u8; 5]), 5);
+        drop(quota);
+        let mut partial = zone.alloc_up_to(10);
+        assert_eq!(partial.capacity(), 3);
+        assert_eq!(partial.fill(&[2; 10]), 3);
+        drop(partial);
+        let full = zone.alloc_up_to(10);
+        assert!(full.capacity() >= 10);
+    }
+
+    #[test]
+    fn aligned() {
+        let zone = AlignedZone::<256>::with_capacity(16);
+        for len in &[1, 0, 300, 7, 1000] {
+            let bytes = zone.alloc(*len);
+            assert_eq!(bytes.as_ptr() as usize % 256, 0);
+            assert_eq!(bytes.len(), *len);
+            assert!(bytes.iter().all(|&b| b == 0));
+        }
+        assert_eq!(zone.alloc_copy(b"abc"), b"abc");
+    }
+
+    #[test]
+    fn emplace() {
+        struct Big {
+            id: u32,
+            name: String,
+            data: [u64; 512]
+        }
+
+        let zone = Zone::with_capacity(1);
+        let first = zone_alloc!(zone, Big { id: 1, name: "one".to_string(), data: [1; 512] });
+        let second = zone_alloc!(zone, Big { id: 2, name: "two".to_string(), data: [2; 512], });
+        assert_eq!((first.id, &first.name[..], first.data[511]), (1, "one", 1));
+        assert_eq!((second.id, &second.name[..], second.data[0]), (2, "two", 2));
+    }
+
+    #[test]
+    fn try_alloc() {
+        let zone = Zone::new();
+        assert_eq!(zone.try_alloc(usize::MAX).err(), Some(AllocError));
+        assert_eq!(zone.try_push(5u8).map(|x| *x), Ok(5));
+        let mut quota = zone.try_alloc(3).unwrap();
+        assert_eq!(quota.fill(b"abc"), 3);
+        assert_eq!(DynZone::new().try_alloc(|| 7u32).map(|x| *x), Ok(7));
+    }
+
+    #[test]
+    fn dyn_zone_drop_order() {
+        use std::cell::RefCell;
+
+        struct Logged<'a>(u32, &'a RefCell<Vec<u32>>);
+        impl<'a> Drop for Logged<'a> {
+            fn drop(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
+        }
+
+        let log = RefCell::new(Vec::new());
+        let mut zone = DynZone::with_drop_order(DropOrder::Reverse);
+        for i in 0..3 {
+            zone.alloc(|| Logged(i, &log));
+            zone.alloc(|| [i; 100]);
+        }
+        zone.reset();
+        assert_eq!(log.replace(Vec::new()), [2, 1, 0]);
+        zone.alloc(|| Logged(0, &log));
+        zone.alloc(|| Logged(1, &log));
+        drop(zone);
+        assert_eq!(log.into_inner(), [1, 0]);
+    }
+
+    #[test]
+    fn small_object() {
+        let zone = SmallObjectZone::new();
+        let small = zone.format(format_args!("{}", 42));
+        let big = zone.format(format_args!("{:1$}", "x", 1000));
+        let mut quota = zone.alloc(10000);
+        assert_eq!(quota.fill(&[7; 10000]), 10000);
+        let huge = quota.into_slice();
+        assert_eq!(small, "42");
+        assert_eq!(big.len(), 1000);
+        assert!(huge.iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn realign() {
+        let zone = Zone::new();
+        let mut quota = zone.alloc(64);
+        let _ = quota.push(1u8);
+        assert!(quota.realign(8));
+        assert_eq!(quota.align_offset(8), 0);
+        let at = quota.len();
+        quota.fill(&[2; 8]);
+        let bytes = quota.into_slice();
+        assert_eq!(bytes[at..at + 8], [2; 8]);
+        assert_eq!(&bytes[at] as *const u8 as usize % 8, 0);
+        assert!(bytes[1..at].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn scoped() {
+        let total = with_zone(|zone| {
+            let a = zone.push(20u64);
+            let b = zone.push(22u64);
+            *a + *b
+        });
+        assert_eq!(total, 42);
+
+        let name = String::from("scoped");
+        let len = with_dyn_zone(|zone| {
+            let s = zone.alloc(|| &name[..]);
+            let v = zone.alloc(|| vec![1, 2, 3]);
+            s.len() + v.len()
+        });
+        assert_eq!(len, 9);
+    }
+
+    #[test]
+    fn records() {
+        let zone = RecordZone::new();
+        let mut expect = Vec::new();
+        for i in 0..200 {
+            let body = format!("record {}", i);
+            if i % 2 == 0 {
+                zone.push_record(body.as_bytes());
+            } else {
+                zone.push_parts(&[b"record ", format!("{}", i).as_bytes()]);
+            }
+            expect.push(body);
+        }
+        zone.push_record(b"");
+        expect.push(String::new());
+        let got: Vec<&[u8]> = zone.records().collect();
+        assert_eq!(got, expect.iter().map(|s| s.as_bytes()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn leak() {
+        fn build() -> &'static ZoneView<String> {
+            let zone = Zone::new();
+            for i in 0..10 {
+                zone.push(i.to_string());
+            }
+            zone.leak()
+        }
+
+        let view = build();
+        assert_eq!(view.len(), 10);
+        assert_eq!(view.get(3).map(|s| &s[..]), Some("3"));
+        let first: &'static String = view.iter().next().unwrap();
+        assert_eq!(first, "0");
+    }
+
+    #[test]
+    fn alloc_wide() {
+        let zone = Zone::new();
+        for s in &["", "hello", "h\u{e9}llo \u{1d11e}"] {
+            let wide: Vec<u16> = s.encode_utf16().collect();
+            assert_eq!(zone.alloc_wide(s), &wide[..]);
+            let nul = zone.alloc_wide_nul(s);
+            assert_eq!(&nul[..wide.len()], &wide[..]);
+            assert_eq!(nul[wide.len()..], [0]);
+        }
+    }
+
+    #[test]
+    fn str_in_place() {
+        let zone = Zone::new();
+        let mut quota = zone.alloc_str(16);
+        quota.fill("Content-Type");
+        quota.make_ascii_lowercase();
+        assert_eq!(quota.as_str(), "content-type");
+        let name = quota.into_mut_str();
+        name[..1].make_ascii_uppercase();
+        assert_eq!(name, "Content-type");
+
+        // A partial fill stops at a character boundary
+        let mut quota = zone.alloc_str(2);
+        let cap = quota.capacity();
+        let text = "x".repeat(cap - 1) + "é";
+        assert_eq!(quota.fill(&text), cap - 1);
+        assert_eq!(quota.into_mut_str(), &text[..cap - 1]);
+    }
+
+    #[test]
+    fn default_debug() {
+        #[derive(Default, Debug)]
+        struct Ctx {
+            nodes: Zone<u32>,
+            objects: DynZone<'static>
+        }
+        let ctx = Ctx::default();
+        ctx.nodes.push(1);
+        ctx.nodes.push(2);
+        ctx.objects.alloc(|| "x".to_string());
+        assert_eq!(format!("{:?}", ctx.nodes), "[1, 2]");
+        assert!(format!("{:?}", ctx).starts_with("Ctx { nodes: [1, 2], objects: DynZone"));
+    }
+
+    #[test]
+    fn type_map() {
+        use std::rc::Rc;
+
+        let rc = Rc::new(());
+        let mut map = TypeMapZone::new();
+        map.insert(5u32);
+        map.insert(rc.clone());
+        *map.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(map.get::<u32>(), Some(&6));
+        assert_eq!(map.get::<u64>(), None);
+        assert_eq!(map.get_or_insert_with(|| "ctx".to_string()), "ctx");
+        assert_eq!(map.len(), 3);
+
+        // Replacing drops the old value right away
+        map.insert(rc.clone());
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(map);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn contiguous() {
+        let mut zone = ContiguousZone::with_capacity(4);
+        for word in ["one", "two", "three", "four"].iter() {
+            zone.push_str(word);
+            zone.push_str(" ");
+        }
+        assert_eq!(zone.finish_str().unwrap(), "one two three four ");
+        assert_eq!(zone.chain.stats().chunk_count(), 1);
+        assert_eq!(zone.chain.stats().wasted(), 0);
+
+        zone.push(0xff);
+        assert!(zone.finish_str().is_err());
+        assert_eq!(zone.finish().len(), 20);
+    }
+
+    #[test]
+    fn char_builder() {
+        let zone = Zone::new();
+        assert_eq!(zone.push_char('é'), "é");
+        let mut word = zone.char_builder();
+        for c in "identifier_with_a_long_name".chars() {
+            word.push(c.to_ascii_uppercase());
+        }
+        word.push('λ');
+        let word = word.finish();
+        assert_eq!(word, "IDENTIFIER_WITH_A_LONG_NAMEλ");
+        // Grown in place, then trimmed to the text
+        assert_eq!(zone.chain.len(), 2 + word.len());
+
+        // Moves when something else was allocated after it
+        let mut a = zone.char_builder();
+        a.push_str("abc");
+        let b = zone.push_char('x');
+        a.push_str(&"d".repeat(100));
+        assert_eq!(&a.as_str()[..4], "abcd");
+        assert_eq!(b, "x");
+        assert_eq!(a.finish().len(), 103);
+    }
+
+    #[test]
+    fn utf8_lossy() {
+        let zone = Zone::new();
+        assert_eq!(zone.push_utf8_lossy(b""), "");
+        assert_eq!(zone.push_utf8_lossy("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        let before = zone.chain.len();
+        let text = zone.push_utf8_lossy(b"a\xffb\xe2\x82c\xf0");
+        assert_eq!(text, "a\u{fffd}b\u{fffd}c\u{fffd}");
+        assert_eq!(zone.chain.len(), before + text.len());
+    }
 }